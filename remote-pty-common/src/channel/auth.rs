@@ -0,0 +1,243 @@
+use std::{env, fmt, fs};
+
+use ring::hmac;
+
+use crate::{
+    log::debug,
+    proto::handshake::{AuthCall, AuthResponse},
+};
+
+use super::{Channel, RemoteChannel};
+
+// labels distinguishing the proofs of each side so the
+// proof of one side cannot be replayed as the other
+const MASTER_LABEL: &[u8] = b"remote-pty master";
+const SLAVE_LABEL: &[u8] = b"remote-pty slave";
+
+// secret shared by the master and slaves used to authenticate connections
+#[derive(Clone, PartialEq)]
+pub struct Psk(Vec<u8>);
+
+impl Psk {
+    pub fn new(key: impl Into<Vec<u8>>) -> Self {
+        Self(key.into())
+    }
+
+    // reads the key from RPTY_PSK or the file at RPTY_PSK_FILE,
+    // returns None if neither are set
+    pub fn from_env() -> Result<Option<Self>, String> {
+        let key = match (env::var("RPTY_PSK"), env::var("RPTY_PSK_FILE")) {
+            (Ok(_), Ok(_)) => {
+                return Err("only one of RPTY_PSK or RPTY_PSK_FILE can be set".to_string())
+            }
+            (Ok(key), _) => key.into_bytes(),
+            (_, Ok(path)) => {
+                let mut key =
+                    fs::read(&path).map_err(|e| format!("failed to read {}: {}", path, e))?;
+
+                // ignore the trailing newline of the file
+                while matches!(key.last(), Some(b'\n' | b'\r')) {
+                    key.pop();
+                }
+
+                key
+            }
+            _ => return Ok(None),
+        };
+
+        if key.is_empty() {
+            return Err("the pre-shared key cannot be empty".to_string());
+        }
+
+        Ok(Some(Self(key)))
+    }
+
+    fn sign(&self, label: &[u8], first: &[u8; 32], second: &[u8; 32]) -> [u8; 32] {
+        let key = hmac::Key::new(hmac::HMAC_SHA256, &self.0);
+        let tag = hmac::sign(&key, &[label, first, second].concat());
+
+        let mut proof = [0u8; 32];
+        proof.copy_from_slice(tag.as_ref());
+        proof
+    }
+
+    fn verify(&self, label: &[u8], first: &[u8; 32], second: &[u8; 32], proof: &[u8; 32]) -> bool {
+        let key = hmac::Key::new(hmac::HMAC_SHA256, &self.0);
+
+        hmac::verify(&key, &[label, first, second].concat(), proof).is_ok()
+    }
+}
+
+// the key must never be logged
+impl fmt::Debug for Psk {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Psk(..)")
+    }
+}
+
+impl RemoteChannel {
+    // authenticates with the master once the handshake has completed,
+    // the master must prove it knows the key before the slave does
+    pub(super) fn authenticate(&mut self, psk: &Psk) -> Result<(), String> {
+        let nonce: [u8; 32] = rand::random();
+
+        let res =
+            self.send::<AuthCall, AuthResponse>(Channel::HANDSHAKE, AuthCall::Challenge(nonce))?;
+
+        let (master_nonce, proof) = match res {
+            AuthResponse::Challenge { nonce, proof } => (nonce, proof),
+            AuthResponse::Rejected(reason) => {
+                return Err(format!("master rejected authentication: {}", reason))
+            }
+            res => return Err(format!("unexpected auth response: {:?}", res)),
+        };
+
+        if !psk.verify(MASTER_LABEL, &nonce, &master_nonce, &proof) {
+            return Err("master failed to prove knowledge of the pre-shared key".to_string());
+        }
+
+        let res = self.send::<AuthCall, AuthResponse>(
+            Channel::HANDSHAKE,
+            AuthCall::Proof(psk.sign(SLAVE_LABEL, &master_nonce, &nonce)),
+        )?;
+
+        match res {
+            AuthResponse::Accepted => Ok(()),
+            AuthResponse::Rejected(reason) => {
+                Err(format!("master rejected authentication: {}", reason))
+            }
+            res => Err(format!("unexpected auth response: {:?}", res)),
+        }
+    }
+
+    // authenticates the slave once the handshake has completed,
+    // no other requests must be accepted from the slave unless this succeeds
+    pub(super) fn accept_authentication(&mut self, psk: &Psk) -> Result<(), String> {
+        let req = self
+            .receive_request::<AuthCall>(Channel::HANDSHAKE)
+            .map_err(|e| format!("client failed to authenticate: {}", e))?;
+
+        let slave_nonce = match req.payload {
+            AuthCall::Challenge(nonce) => nonce,
+            call => return self.reject_auth(req.id, format!("unexpected auth call {:?}", call)),
+        };
+
+        let nonce: [u8; 32] = rand::random();
+        self.send_response(
+            Channel::HANDSHAKE,
+            req.id,
+            AuthResponse::Challenge {
+                nonce,
+                proof: psk.sign(MASTER_LABEL, &slave_nonce, &nonce),
+            },
+        )?;
+
+        // the client disconnects if we fail to prove knowledge of the key
+        let req = self
+            .receive_request::<AuthCall>(Channel::HANDSHAKE)
+            .map_err(|e| format!("client failed to authenticate: {}", e))?;
+
+        match req.payload {
+            AuthCall::Proof(proof) if psk.verify(SLAVE_LABEL, &nonce, &slave_nonce, &proof) => {
+                debug("client authenticated");
+                self.send_response(Channel::HANDSHAKE, req.id, AuthResponse::Accepted)
+            }
+            AuthCall::Proof(_) => self.reject_auth(req.id, "invalid proof".to_string()),
+            call => self.reject_auth(req.id, format!("unexpected auth call {:?}", call)),
+        }
+    }
+
+    fn reject_auth(&mut self, id: u32, reason: String) -> Result<(), String> {
+        debug(format!("rejecting client authentication: {}", reason));
+
+        let _ = self.send_response(
+            Channel::HANDSHAKE,
+            id,
+            AuthResponse::Rejected(reason.clone()),
+        );
+
+        Err(format!("authentication failed: {}", reason))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use crate::channel::{transport::mem::MemoryTransport, RemoteChannel};
+
+    use super::Psk;
+
+    // performs the handshake between a slave and master with the supplied keys
+    fn handshake(
+        slave_psk: Option<&str>,
+        master_psk: Option<&str>,
+    ) -> (Result<(), String>, Result<(), String>) {
+        let (t1, t2) = MemoryTransport::pair();
+
+        let mut slave = RemoteChannel::new(t1);
+        let mut master = RemoteChannel::new(t2);
+        slave.set_psk(slave_psk.map(Psk::new));
+        master.set_psk(master_psk.map(Psk::new));
+
+        let accept_thread = thread::spawn(move || master.accept_handshake().map(|_| ()));
+        let res = slave.handshake().map(|_| ());
+        // the slave disconnects if the master fails to authenticate
+        drop(slave);
+
+        (res, accept_thread.join().unwrap())
+    }
+
+    #[test]
+    fn test_auth_with_matching_keys() {
+        let (slave, master) = handshake(Some("secret"), Some("secret"));
+
+        assert_eq!(slave, Ok(()));
+        assert_eq!(master, Ok(()));
+    }
+
+    #[test]
+    fn test_auth_with_different_keys() {
+        let (slave, master) = handshake(Some("guess"), Some("secret"));
+
+        assert_eq!(
+            slave,
+            Err("master failed to prove knowledge of the pre-shared key".to_string())
+        );
+        assert!(master.is_err());
+    }
+
+    #[test]
+    fn test_auth_required_by_master() {
+        let (slave, master) = handshake(None, Some("secret"));
+
+        assert_eq!(
+            slave,
+            Err("remote rejected handshake: authentication is required".to_string())
+        );
+        assert_eq!(master, Err("authentication is required".to_string()));
+    }
+
+    #[test]
+    fn test_auth_required_by_slave() {
+        let (slave, master) = handshake(Some("secret"), None);
+
+        assert_eq!(
+            slave,
+            Err("master does not support authentication".to_string())
+        );
+        assert_eq!(master, Ok(()));
+    }
+
+    #[test]
+    fn test_proofs_are_bound_to_side() {
+        let psk = Psk::new("secret");
+        let (n1, n2) = ([1; 32], [2; 32]);
+
+        let proof = psk.sign(super::MASTER_LABEL, &n1, &n2);
+
+        assert!(psk.verify(super::MASTER_LABEL, &n1, &n2, &proof));
+        assert!(!psk.verify(super::SLAVE_LABEL, &n1, &n2, &proof));
+        assert!(!Psk::new("other").verify(super::MASTER_LABEL, &n1, &n2, &proof));
+    }
+}
@@ -0,0 +1,214 @@
+use std::env;
+
+use flate2::{Compress, Compression, Decompress, FlushCompress, FlushDecompress};
+
+// frames smaller than this are sent uncompressed, keystrokes gain
+// nothing from compression and should not incur any extra latency
+pub const DEFAULT_COMPRESSION_THRESHOLD: usize = 64;
+
+// configures compression of frames sent on the channel
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct CompressionConf {
+    // the deflate compression level from 1 (fastest) to 9 (smallest)
+    pub level: u32,
+    // frames with smaller payloads are not compressed
+    pub threshold: usize,
+}
+
+impl CompressionConf {
+    // reads the compression conf from the env, returns None if disabled
+    pub fn from_env() -> Result<Option<Self>, String> {
+        let level = match env::var("RPTY_COMPRESSION_LEVEL") {
+            Ok(l) => l
+                .parse::<u32>()
+                .map_err(|_| "failed to parse number in RPTY_COMPRESSION_LEVEL")?,
+            Err(_) => return Ok(None),
+        };
+
+        if level == 0 {
+            return Ok(None);
+        }
+
+        if level > 9 {
+            return Err("RPTY_COMPRESSION_LEVEL must be between 0 and 9".to_string());
+        }
+
+        let threshold = env::var("RPTY_COMPRESSION_THRESHOLD")
+            .unwrap_or_else(|_| DEFAULT_COMPRESSION_THRESHOLD.to_string())
+            .parse::<usize>()
+            .map_err(|_| "failed to parse number in RPTY_COMPRESSION_THRESHOLD")?;
+
+        Ok(Some(Self { level, threshold }))
+    }
+}
+
+// compresses frame payloads as a single deflate stream which is flushed
+// after each frame, so small frames can reference data from earlier frames.
+// frames must be written in the order they are compressed
+pub struct FrameCompressor {
+    inner: Compress,
+    threshold: usize,
+}
+
+impl FrameCompressor {
+    pub fn new(conf: CompressionConf) -> Self {
+        Self {
+            inner: Compress::new(Compression::new(conf.level), false),
+            threshold: conf.threshold,
+        }
+    }
+
+    // returns the compressed payload or None if the payload
+    // is below the threshold and should be sent as is
+    pub fn compress(&mut self, payload: &[u8]) -> Result<Option<Vec<u8>>, String> {
+        if payload.len() < self.threshold {
+            return Ok(None);
+        }
+
+        let start = self.inner.total_in();
+        let mut out = Vec::with_capacity(payload.len() / 2 + 64);
+
+        loop {
+            if out.len() == out.capacity() {
+                out.reserve(out.capacity());
+            }
+
+            let consumed = (self.inner.total_in() - start) as usize;
+            self.inner
+                .compress_vec(&payload[consumed..], &mut out, FlushCompress::Sync)
+                .map_err(|e| format!("failed to compress frame: {}", e))?;
+
+            // the flush is complete once all the input is consumed
+            // without filling the output
+            let consumed = (self.inner.total_in() - start) as usize;
+            if consumed == payload.len() && out.len() < out.capacity() {
+                break;
+            }
+        }
+
+        Ok(Some(out))
+    }
+}
+
+// decompresses frames produced by the remote's FrameCompressor,
+// frames must be decompressed in the order they are received
+pub struct FrameDecompressor {
+    inner: Decompress,
+}
+
+impl FrameDecompressor {
+    pub fn new() -> Self {
+        Self {
+            inner: Decompress::new(false),
+        }
+    }
+
+    // decompresses the payload, failing if the output exceeds
+    // the max size so a small frame cannot cause a large allocation
+    pub fn decompress(&mut self, payload: &[u8], max_size: usize) -> Result<Vec<u8>, String> {
+        let start = self.inner.total_in();
+        let mut out = Vec::with_capacity((payload.len() * 4).clamp(64, max_size.max(1)));
+
+        loop {
+            if out.len() == out.capacity() {
+                if out.len() >= max_size {
+                    return Err(format!(
+                        "decompressed frame exceeds the max frame size of {} bytes",
+                        max_size
+                    ));
+                }
+
+                out.reserve(out.capacity().min(max_size - out.len()));
+            }
+
+            let consumed = (self.inner.total_in() - start) as usize;
+            let written = out.len();
+            self.inner
+                .decompress_vec(&payload[consumed..], &mut out, FlushDecompress::Sync)
+                .map_err(|e| format!("failed to decompress frame: {}", e))?;
+
+            let now_consumed = (self.inner.total_in() - start) as usize;
+            if now_consumed == payload.len() && out.len() < out.capacity() {
+                break;
+            }
+
+            // no progress despite spare output means the input is incomplete
+            if now_consumed == consumed && out.len() == written {
+                return Err("failed to decompress frame: unexpected end of stream".to_string());
+            }
+        }
+
+        if out.len() > max_size {
+            return Err(format!(
+                "decompressed frame exceeds the max frame size of {} bytes",
+                max_size
+            ));
+        }
+
+        Ok(out)
+    }
+}
+
+impl Default for FrameDecompressor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CompressionConf, FrameCompressor, FrameDecompressor};
+
+    const CONF: CompressionConf = CompressionConf {
+        level: 6,
+        threshold: 10,
+    };
+
+    #[test]
+    fn test_compress_decompress() {
+        let mut compressor = FrameCompressor::new(CONF);
+        let mut decompressor = FrameDecompressor::new();
+
+        let payload = "compiling remote-pty-common v0.1.0\n"
+            .repeat(100)
+            .into_bytes();
+        let compressed = compressor.compress(&payload).unwrap().unwrap();
+        assert!(compressed.len() < payload.len() / 10);
+
+        let decompressed = decompressor.decompress(&compressed, 1024 * 1024).unwrap();
+        assert_eq!(decompressed, payload);
+    }
+
+    #[test]
+    fn test_compress_below_threshold() {
+        let mut compressor = FrameCompressor::new(CONF);
+
+        assert_eq!(compressor.compress(b"ls\n").unwrap(), None);
+    }
+
+    #[test]
+    fn test_compress_streaming_dictionary() {
+        let mut compressor = FrameCompressor::new(CONF);
+        let mut decompressor = FrameDecompressor::new();
+
+        let line = b"warning: unused variable: `foo` in src/main.rs\n";
+        let first = compressor.compress(line).unwrap().unwrap();
+        let second = compressor.compress(line).unwrap().unwrap();
+
+        // the second frame references the first rather than repeating it
+        assert!(second.len() < first.len() / 2);
+
+        assert_eq!(decompressor.decompress(&first, 1024).unwrap(), line);
+        assert_eq!(decompressor.decompress(&second, 1024).unwrap(), line);
+    }
+
+    #[test]
+    fn test_decompress_exceeding_max_size() {
+        let mut compressor = FrameCompressor::new(CONF);
+        let mut decompressor = FrameDecompressor::new();
+
+        let compressed = compressor.compress(&[0; 10000]).unwrap().unwrap();
+
+        assert!(decompressor.decompress(&compressed, 1000).is_err());
+    }
+}
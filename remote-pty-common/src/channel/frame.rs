@@ -0,0 +1,173 @@
+use std::{
+    env,
+    io::{self, Read},
+};
+
+// messages are written to the transport as frames consisting of a
+// 4 byte big endian payload length, a frame type byte and the payload
+pub const FRAME_HEADER_SIZE: usize = 5;
+
+// the max frame size used unless otherwise configured
+pub const DEFAULT_MAX_FRAME_SIZE: usize = 1024 * 1024;
+
+// the upper bound of the configurable max frame size, this is
+// also used to limit allocations when decoding a frame's payload
+pub const MAX_FRAME_SIZE_LIMIT: usize = 16 * 1024 * 1024;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum FrameType {
+    // an encoded channel message
+    Message,
+    // an encoded channel message compressed with the
+    // connection's deflate stream
+    CompressedMessage,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Frame {
+    pub typ: FrameType,
+    pub payload: Vec<u8>,
+}
+
+impl FrameType {
+    fn to_byte(self) -> u8 {
+        match self {
+            FrameType::Message => 1,
+            FrameType::CompressedMessage => 2,
+        }
+    }
+
+    fn from_byte(byte: u8) -> Option<Self> {
+        match byte {
+            1 => Some(FrameType::Message),
+            2 => Some(FrameType::CompressedMessage),
+            _ => None,
+        }
+    }
+}
+
+// reads the max frame size from the env, falling back to the default
+pub fn max_frame_size_from_env() -> Result<usize, String> {
+    let size = match env::var("RPTY_MAX_FRAME_SIZE") {
+        Ok(s) => s
+            .parse::<usize>()
+            .map_err(|_| "failed to parse number in RPTY_MAX_FRAME_SIZE")?,
+        Err(_) => return Ok(DEFAULT_MAX_FRAME_SIZE),
+    };
+
+    if size == 0 || size > MAX_FRAME_SIZE_LIMIT {
+        return Err(format!(
+            "RPTY_MAX_FRAME_SIZE must be between 1 and {} bytes",
+            MAX_FRAME_SIZE_LIMIT
+        ));
+    }
+
+    Ok(size)
+}
+
+// checks the payload can be sent in a single frame
+pub fn check_frame_size(len: usize, max_size: usize) -> Result<(), String> {
+    if len > max_size {
+        return Err(format!(
+            "frame of {} bytes exceeds the max frame size of {} bytes",
+            len, max_size
+        ));
+    }
+
+    Ok(())
+}
+
+// encodes the payload as a single frame so it can be written in one call
+pub fn encode_frame(typ: FrameType, payload: &[u8], max_size: usize) -> Result<Vec<u8>, String> {
+    check_frame_size(payload.len(), max_size)?;
+
+    let mut data = Vec::with_capacity(FRAME_HEADER_SIZE + payload.len());
+    data.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    data.push(typ.to_byte());
+    data.extend_from_slice(payload);
+
+    Ok(data)
+}
+
+// reads the next frame from the reader, frames exceeding the max size or
+// with an unknown type fail before the payload is read
+pub fn read_frame(reader: &mut impl Read, max_size: usize) -> io::Result<Frame> {
+    let mut header = [0u8; FRAME_HEADER_SIZE];
+    reader.read_exact(&mut header)?;
+
+    let len = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as usize;
+
+    if len > max_size {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "frame of {} bytes exceeds the max frame size of {} bytes",
+                len, max_size
+            ),
+        ));
+    }
+
+    let typ = FrameType::from_byte(header[4]).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("unknown frame type {}", header[4]),
+        )
+    })?;
+
+    let mut payload = vec![0u8; len];
+    reader.read_exact(&mut payload)?;
+
+    Ok(Frame { typ, payload })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, Cursor};
+
+    use super::{encode_frame, read_frame, Frame, FrameType};
+
+    #[test]
+    fn test_encode_read_frame() {
+        let data = encode_frame(FrameType::Message, &[1, 2, 3], 10).unwrap();
+        assert_eq!(data, vec![0, 0, 0, 3, 1, 1, 2, 3]);
+
+        let frame = read_frame(&mut Cursor::new(data), 10).unwrap();
+        assert_eq!(
+            frame,
+            Frame {
+                typ: FrameType::Message,
+                payload: vec![1, 2, 3]
+            }
+        );
+    }
+
+    #[test]
+    fn test_encode_oversized_frame() {
+        assert!(encode_frame(FrameType::Message, &[0; 11], 10).is_err());
+    }
+
+    #[test]
+    fn test_read_oversized_frame() {
+        // announces a 4GB payload which must not be allocated
+        let data = vec![0xff, 0xff, 0xff, 0xff, 1];
+
+        let err = read_frame(&mut Cursor::new(data), 10).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_read_unknown_frame_type() {
+        let data = vec![0, 0, 0, 1, 99, 0];
+
+        let err = read_frame(&mut Cursor::new(data), 10).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_read_truncated_frame() {
+        let data = vec![0, 0, 0, 3, 1, 1];
+
+        let err = read_frame(&mut Cursor::new(data), 10).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+}
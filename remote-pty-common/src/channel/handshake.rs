@@ -0,0 +1,242 @@
+use crate::{
+    log::debug,
+    proto::handshake::{Capabilities, HandshakeResponse, Hello},
+};
+
+use super::{
+    compress::{FrameCompressor, FrameDecompressor},
+    Channel, RemoteChannel,
+};
+
+impl RemoteChannel {
+    // performs the handshake from the connecting side of the channel
+    // this must be the first call made on a new channel
+    pub fn handshake(&mut self) -> Result<Capabilities, String> {
+        self.handshake_with(self.local_hello())
+    }
+
+    // accepts the handshake on the listening side of the channel
+    pub fn accept_handshake(&mut self) -> Result<Capabilities, String> {
+        self.accept_handshake_with(self.local_hello())
+    }
+
+    fn local_hello(&self) -> Hello {
+        let mut hello = Hello::local();
+
+        if self.compression.is_some() {
+            hello.capabilities = hello.capabilities.with_compression();
+        }
+
+        if self.psk.is_some() {
+            hello.capabilities = hello.capabilities.with_auth();
+        }
+
+        hello
+    }
+
+    fn handshake_with(&mut self, local: Hello) -> Result<Capabilities, String> {
+        let res = self.send::<Hello, HandshakeResponse>(Channel::HANDSHAKE, local.clone())?;
+
+        let remote = match res {
+            HandshakeResponse::Accepted(remote) => remote,
+            HandshakeResponse::Rejected(reason) => {
+                return Err(format!("remote rejected handshake: {}", reason))
+            }
+        };
+
+        local.check_compatible(&remote)?;
+
+        let caps = self.set_capabilities(&local, &remote);
+
+        // the master rejects the handshake if it requires
+        // authentication and no key is configured
+        if let Some(psk) = self.psk.clone() {
+            if !caps.supports_auth() {
+                return Err("master does not support authentication".to_string());
+            }
+
+            self.authenticate(&psk)?;
+        }
+
+        Ok(caps)
+    }
+
+    fn accept_handshake_with(&mut self, local: Hello) -> Result<Capabilities, String> {
+        let req = self.receive_request::<Hello>(Channel::HANDSHAKE)?;
+        let remote = req.payload;
+
+        let res = local.check_compatible(&remote).and_then(|_| {
+            match (&self.psk, remote.capabilities.supports_auth()) {
+                (Some(_), false) => Err("authentication is required".to_string()),
+                _ => Ok(()),
+            }
+        });
+
+        if let Err(reason) = res {
+            let _ = self.send_response(
+                Channel::HANDSHAKE,
+                req.id,
+                HandshakeResponse::Rejected(reason.clone()),
+            );
+            return Err(reason);
+        }
+
+        self.send_response(
+            Channel::HANDSHAKE,
+            req.id,
+            HandshakeResponse::Accepted(local.clone()),
+        )?;
+
+        let caps = self.set_capabilities(&local, &remote);
+
+        if let Some(psk) = self.psk.clone() {
+            self.accept_authentication(&psk)?;
+        }
+
+        Ok(caps)
+    }
+
+    fn set_capabilities(&mut self, local: &Hello, remote: &Hello) -> Capabilities {
+        let caps = local.capabilities.intersect(&remote.capabilities);
+
+        debug(format!(
+            "handshake complete: local v{}, remote v{}, capabilities {:?}",
+            local.version, remote.version, caps
+        ));
+
+        // frames sent after the handshake are compressed
+        if let (true, Some(conf)) = (caps.supports_compression(), self.compression) {
+            *self.compressor.lock().unwrap() = Some(FrameCompressor::new(conf));
+            *self.decompressor.lock().unwrap() = Some(FrameDecompressor::new());
+        }
+
+        *self.capabilities.lock().unwrap() = caps.clone();
+        caps
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use crate::{
+        channel::{
+            compress::CompressionConf, transport::mem::MemoryTransport, Channel, RemoteChannel,
+        },
+        proto::{
+            handshake::{Capabilities, Hello},
+            master::{PtyMasterCall, PtyMasterResponse, WriteStdinCall},
+        },
+    };
+
+    #[test]
+    fn test_handshake() {
+        let (t1, t2) = MemoryTransport::pair();
+
+        let mut c1 = RemoteChannel::new(t1);
+        let mut c2 = RemoteChannel::new(t2);
+
+        let accept_thread = thread::spawn(move || c2.accept_handshake().unwrap());
+
+        let caps = c1.handshake().unwrap();
+
+        assert_eq!(caps, Capabilities::local());
+        assert_eq!(c1.capabilities(), Capabilities::local());
+        assert_eq!(accept_thread.join().unwrap(), Capabilities::local());
+    }
+
+    #[test]
+    fn test_handshake_with_compression() {
+        let (t1, t2) = MemoryTransport::pair();
+
+        let mut c1 = RemoteChannel::new(t1);
+        let mut c2 = RemoteChannel::new(t2);
+
+        let conf = Some(CompressionConf {
+            level: 1,
+            threshold: 10,
+        });
+        c1.set_compression(conf);
+        c2.set_compression(conf);
+
+        let accept_thread = thread::spawn(move || {
+            c2.accept_handshake().unwrap();
+            c2.receive::<PtyMasterCall, PtyMasterResponse, _>(Channel::STDIN, |_| {
+                PtyMasterResponse::Success(1)
+            })
+            .unwrap();
+            c2
+        });
+
+        let caps = c1.handshake().unwrap();
+        assert!(caps.supports_compression());
+        assert!(c1.compressor.lock().unwrap().is_some());
+
+        let res = c1
+            .send::<PtyMasterCall, PtyMasterResponse>(
+                Channel::STDIN,
+                PtyMasterCall::WriteStdin(WriteStdinCall {
+                    data: b"hello world".repeat(100),
+                }),
+            )
+            .unwrap();
+        assert_eq!(res, PtyMasterResponse::Success(1));
+
+        let c2 = accept_thread.join().unwrap();
+        assert!(c2.capabilities().supports_compression());
+    }
+
+    #[test]
+    fn test_handshake_compression_not_offered() {
+        let (t1, t2) = MemoryTransport::pair();
+
+        let mut c1 = RemoteChannel::new(t1);
+        let mut c2 = RemoteChannel::new(t2);
+
+        c1.set_compression(Some(CompressionConf {
+            level: 1,
+            threshold: 10,
+        }));
+
+        let accept_thread = thread::spawn(move || c2.accept_handshake().unwrap());
+
+        let caps = c1.handshake().unwrap();
+
+        assert!(!caps.supports_compression());
+        assert!(c1.compressor.lock().unwrap().is_none());
+        assert!(!accept_thread.join().unwrap().supports_compression());
+    }
+
+    #[test]
+    fn test_handshake_incompatible_version() {
+        let (t1, t2) = MemoryTransport::pair();
+
+        let mut c1 = RemoteChannel::new(t1);
+        let mut c2 = RemoteChannel::new(t2);
+
+        let accept_thread = thread::spawn(move || {
+            c2.accept_handshake_with(Hello {
+                version: 5,
+                min_version: 4,
+                capabilities: Capabilities::local(),
+            })
+        });
+
+        let res = c1.handshake_with(Hello {
+            version: 2,
+            min_version: 1,
+            capabilities: Capabilities::local(),
+        });
+
+        let accept_res = accept_thread.join().unwrap();
+
+        assert_eq!(
+            accept_res,
+            Err("incompatible protocol versions: local v5 (supports >= v4), remote v2 (supports >= v1)".to_string())
+        );
+        assert_eq!(
+            res,
+            Err("remote rejected handshake: incompatible protocol versions: local v5 (supports >= v4), remote v2 (supports >= v1)".to_string())
+        );
+    }
+}
@@ -0,0 +1,231 @@
+use std::{
+    env,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::log::debug;
+
+use super::{Channel, Message, MessageMode, RemoteChannel};
+
+// configures periodic heartbeats used to detect when the
+// remote has disappeared without closing the connection
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct KeepaliveConf {
+    // the interval at which pings are sent
+    pub interval: Duration,
+    // the number of intervals without hearing from the remote
+    // before the connection is considered dead
+    pub max_missed: u32,
+}
+
+impl KeepaliveConf {
+    // reads the keepalive conf from the env, returns None if disabled
+    pub fn from_env() -> Result<Option<Self>, String> {
+        let interval = match env::var("RPTY_KEEPALIVE_INTERVAL_MS") {
+            Ok(i) => i
+                .parse::<u64>()
+                .map_err(|_| "failed to parse number in RPTY_KEEPALIVE_INTERVAL_MS")?,
+            Err(_) => return Ok(None),
+        };
+
+        if interval == 0 {
+            return Ok(None);
+        }
+
+        let max_missed = env::var("RPTY_KEEPALIVE_MAX_MISSED")
+            .unwrap_or_else(|_| "3".to_string())
+            .parse::<u32>()
+            .map_err(|_| "failed to parse number in RPTY_KEEPALIVE_MAX_MISSED")?;
+
+        Ok(Some(Self {
+            interval: Duration::from_millis(interval),
+            max_missed,
+        }))
+    }
+}
+
+impl RemoteChannel {
+    // starts a thread which periodically pings the remote and closes
+    // the channel if the remote stops responding.
+    // responses are only processed while another thread is receiving
+    // on the channel
+    pub fn start_keepalive(&self, conf: KeepaliveConf) {
+        let chan = self.clone();
+
+        thread::spawn(move || chan.keepalive(conf));
+    }
+
+    // the deadline is checked on every interval regardless of whether
+    // the previous ping could be written, as writes block on a full transport
+    fn keepalive(self, conf: KeepaliveConf) {
+        let pinging = Arc::new(AtomicBool::new(false));
+
+        loop {
+            thread::sleep(conf.interval);
+
+            // stop once every other handle to the channel has been dropped
+            if Arc::strong_count(&self.receiver) <= 1 {
+                break;
+            }
+
+            // closed channels could later be resumed
+            if self.is_closed() {
+                continue;
+            }
+
+            let elapsed = {
+                let state = self.receiver.state.lock().unwrap();
+                Instant::now().duration_since(state.last_received)
+            };
+
+            if elapsed >= conf.interval * conf.max_missed {
+                self.close(format!(
+                    "remote did not respond within {}ms",
+                    elapsed.as_millis()
+                ));
+                break;
+            }
+
+            // a ping is only needed while no other data is being written
+            // and at most one is outstanding if the write blocks
+            if pinging.swap(true, Ordering::AcqRel) {
+                continue;
+            }
+
+            let chan = self.clone();
+            let pinging = Arc::clone(&pinging);
+            thread::spawn(move || {
+                let id = chan.next_id.fetch_add(1, Ordering::Relaxed);
+
+                if let Err(err) = chan.try_write_msg(id, Channel::CONTROL, MessageMode::Request, ())
+                {
+                    debug(format!("failed to send ping: {}", err));
+                }
+
+                pinging.store(false, Ordering::Release);
+            });
+        }
+
+        debug("terminating keepalive");
+    }
+
+    // replies to pings from the remote, pongs require no handling
+    // as receiving any message marks the remote as alive.
+    // the pong is skipped while another thread is writing, that data
+    // shows the remote we are alive and the reader must not stall behind it
+    pub(super) fn handle_control_msg(&self, msg: Message) {
+        if msg.mode != MessageMode::Request {
+            return;
+        }
+
+        if let Err(err) = self.try_write_msg(msg.id, Channel::CONTROL, MessageMode::Response, ()) {
+            debug(format!("failed to send pong: {}", err));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        os::unix::net::UnixStream,
+        sync::mpsc::channel,
+        thread,
+        time::{Duration, Instant},
+    };
+
+    use crate::{
+        channel::{transport::unix_socket::UnixSocketTransport, Channel, RemoteChannel},
+        proto::master::{PtyMasterCall, PtyMasterResponse, WriteStdinCall},
+    };
+
+    use super::KeepaliveConf;
+
+    fn pair() -> (RemoteChannel, RemoteChannel) {
+        let (s1, s2) = UnixStream::pair().unwrap();
+
+        (
+            RemoteChannel::new(UnixSocketTransport::new(s1)),
+            RemoteChannel::new(UnixSocketTransport::new(s2)),
+        )
+    }
+
+    const CONF: KeepaliveConf = KeepaliveConf {
+        interval: Duration::from_millis(20),
+        max_missed: 3,
+    };
+
+    #[test]
+    fn test_keepalive_with_responsive_remote() {
+        let (mut c1, mut c2) = pair();
+        c1.start_keepalive(CONF);
+
+        // both sides are receiving so pings are answered
+        let receive_thread = {
+            let mut c1 = c1.clone();
+            thread::spawn(move || c1.receive_request::<PtyMasterCall>(Channel::STDIN))
+        };
+        let reply_thread = thread::spawn(move || {
+            c2.receive::<PtyMasterCall, PtyMasterResponse, _>(Channel::SIGNAL, |_| {
+                PtyMasterResponse::Success(1)
+            })
+        });
+
+        thread::sleep(CONF.interval * CONF.max_missed * 3);
+        assert!(!c1.is_closed());
+
+        let res = c1
+            .send::<PtyMasterCall, PtyMasterResponse>(
+                Channel::SIGNAL,
+                PtyMasterCall::WriteStdin(WriteStdinCall { data: vec![] }),
+            )
+            .unwrap();
+        assert_eq!(res, PtyMasterResponse::Success(1));
+
+        reply_thread.join().unwrap().unwrap();
+        drop(c1);
+        drop(receive_thread);
+    }
+
+    #[test]
+    fn test_keepalive_with_unresponsive_remote() {
+        let (c1, _c2) = pair();
+        let (sender, receiver) = channel();
+
+        c1.on_disconnect(move |reason| sender.send(reason.to_string()).unwrap());
+        c1.start_keepalive(CONF);
+
+        // the remote is never reading so will never respond to pings
+        let start = Instant::now();
+        let res = c1.clone().receive_request::<PtyMasterCall>(Channel::STDIN);
+
+        assert!(res.is_err());
+        assert!(c1.is_closed());
+        assert!(start.elapsed() >= CONF.interval * CONF.max_missed);
+
+        let reason = receiver.recv_timeout(Duration::from_secs(1)).unwrap();
+        assert!(reason.starts_with("remote did not respond"));
+    }
+
+    #[test]
+    fn test_keepalive_with_blocked_writer() {
+        let (c1, _c2) = pair();
+        let (sender, receiver) = channel();
+
+        c1.on_disconnect(move |reason| sender.send(reason.to_string()).unwrap());
+        c1.start_keepalive(CONF);
+
+        // a writer stuck on a full transport must not stop the deadline
+        let writer = c1.writer.lock().unwrap();
+
+        let reason = receiver.recv_timeout(Duration::from_secs(1)).unwrap();
+        assert!(reason.starts_with("remote did not respond"));
+        assert!(c1.is_closed());
+
+        drop(writer);
+    }
+}
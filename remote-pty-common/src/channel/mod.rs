@@ -1,55 +1,137 @@
+pub mod auth;
+pub mod compress;
+pub mod frame;
+pub mod handshake;
+pub mod keepalive;
 pub mod mock;
+pub mod mux;
+pub mod resume;
+pub mod stream;
 pub mod transport;
 
 use std::{
     any::type_name,
     fmt::Debug,
     io,
-    sync::{mpsc::channel, Arc, Condvar, Mutex, MutexGuard},
+    sync::{
+        atomic::{AtomicU32, AtomicUsize, Ordering},
+        mpsc::channel,
+        Arc, Condvar, Mutex, MutexGuard, TryLockError,
+    },
     thread,
-    time::Duration,
+    time::{Duration, Instant},
 };
 
-use bincode::{Decode, Encode};
+use bincode::{
+    config::{Configuration, Limit, LittleEndian, Varint, WriteFixedArrayLength},
+    Decode, Encode,
+};
 
-use crate::log::debug;
+#[cfg(target_os = "linux")]
+use crate::io::timeout::{timeout, TimeoutResult};
+use crate::{
+    log::debug,
+    proto::{self, handshake::Capabilities},
+};
 
-use self::transport::Transport;
+use self::{
+    auth::Psk,
+    compress::{CompressionConf, FrameCompressor, FrameDecompressor},
+    frame::{
+        check_frame_size, encode_frame, read_frame, FrameType, DEFAULT_MAX_FRAME_SIZE,
+        MAX_FRAME_SIZE_LIMIT,
+    },
+    transport::{ShutdownHandle, Transport},
+};
 
 // thread-safe channel used to send and receive commands from the remote side
 pub struct RemoteChannel {
-    // underlying transport for
-    reader: Arc<Mutex<dyn io::Read + Send>>,
-    writer: Arc<Mutex<dyn io::Write + Send>>,
+    // underlying transport for, can be replaced when resuming
+    reader: Arc<Mutex<Box<dyn io::Read + Send>>>,
+    writer: Arc<Mutex<Box<dyn io::Write + Send>>>,
     // encoding conf
-    conf: bincode::config::Configuration,
+    conf: EncodingConf,
+    // the max size of frames sent or received on the channel
+    max_frame_size: Arc<AtomicUsize>,
+    // compression offered during the handshake, disabled if none
+    compression: Option<CompressionConf>,
+    // the compression streams of the transport, set once compression
+    // is negotiated and replaced along with the transport
+    compressor: Arc<Mutex<Option<FrameCompressor>>>,
+    decompressor: Arc<Mutex<Option<FrameDecompressor>>>,
+    // key used to authenticate the remote during the handshake, if any
+    psk: Option<Arc<Psk>>,
     // used to wait for new messages
     receiver: Arc<MessageReceiver>,
+    // used to generate unique request ids
+    next_id: Arc<AtomicU32>,
+    // capabilities negotiated during the handshake
+    capabilities: Arc<Mutex<Capabilities>>,
+    // used to forcibly close the transport, if supported
+    shutdown: Arc<Mutex<Option<ShutdownHandle>>>,
+    // callbacks invoked once the channel is closed
+    disconnect_hooks: Arc<Mutex<Vec<DisconnectHook>>>,
+    // the max duration to wait for a response to a call
+    call_timeout: Option<Duration>,
 }
 
+pub type DisconnectHook = Box<dyn FnOnce(&str) + Send>;
+
+// decoding is limited so a malformed payload cannot cause
+// unbounded allocations
+type EncodingConf =
+    Configuration<LittleEndian, Varint, WriteFixedArrayLength, Limit<MAX_FRAME_SIZE_LIMIT>>;
+
 struct MessageReceiver {
-    queue: Mutex<Vec<Message>>,
+    state: Mutex<ReceiverState>,
     condvar: Condvar,
 }
 
+struct ReceiverState {
+    // messages received but not yet claimed by a thread
+    queue: Vec<Message>,
+    // whether a thread is currently reading from the transport
+    reading: bool,
+    // the time the last message was received from the remote
+    last_received: Instant,
+    // set to the reason the channel was closed, once closed
+    // all pending and future calls will fail
+    closed: Option<String>,
+    // ids of calls which timed out, their responses are discarded
+    // if they arrive later
+    abandoned: Vec<u32>,
+    // incremented each time the transport is replaced
+    generation: u64,
+}
+
 #[derive(Encode, Decode, PartialEq, Clone, Copy, Debug)]
 pub enum Channel {
+    // must remain the first variant so the handshake
+    // is decodable across protocol versions
+    HANDSHAKE,
     PGRP,
     PTY,
     STDIN,
     STDOUT,
     SIGNAL,
+    // used internally for heartbeats
+    CONTROL,
+    // streams of the processes connected to a broker
+    MUX,
 }
 
 // wrapper struct used for encoding/decoding messages in a generic format
-#[derive(Encode, Decode)]
+#[derive(Encode, Decode, Debug)]
 struct Message {
+    // used to correlate request/response pairs, responses
+    // carry the id of the request they are replying to
+    id: u32,
     chan: Channel,
     mode: MessageMode,
     data: Vec<u8>,
 }
 
-#[derive(Encode, Decode, PartialEq, Clone, Copy)]
+#[derive(Encode, Decode, PartialEq, Clone, Copy, Debug)]
 enum MessageMode {
     Request,
     Response,
@@ -57,20 +139,68 @@ enum MessageMode {
 
 impl RemoteChannel {
     pub fn new(transport: impl Transport) -> Self {
+        let shutdown = transport.shutdown_handle();
         let (reader, writer) = transport.split();
         Self {
-            reader: Arc::new(Mutex::from(reader)),
-            writer: Arc::new(Mutex::from(writer)),
-            conf: bincode::config::standard(),
+            reader: Arc::new(Mutex::new(reader)),
+            writer: Arc::new(Mutex::new(writer)),
+            conf: bincode::config::standard().with_limit::<MAX_FRAME_SIZE_LIMIT>(),
+            max_frame_size: Arc::new(AtomicUsize::new(DEFAULT_MAX_FRAME_SIZE)),
+            compression: None,
+            compressor: Arc::new(Mutex::new(None)),
+            decompressor: Arc::new(Mutex::new(None)),
+            psk: None,
             receiver: Arc::new(MessageReceiver {
-                queue: Mutex::new(vec![]),
+                state: Mutex::new(ReceiverState {
+                    queue: vec![],
+                    reading: false,
+                    last_received: Instant::now(),
+                    closed: None,
+                    abandoned: vec![],
+                    generation: 0,
+                }),
                 condvar: Condvar::new(),
             }),
+            next_id: Arc::new(AtomicU32::new(1)),
+            // until a handshake is performed we assume the remote
+            // supports the same capabilities as this build
+            capabilities: Arc::new(Mutex::new(Capabilities::local())),
+            shutdown: Arc::new(Mutex::new(shutdown)),
+            disconnect_hooks: Arc::new(Mutex::new(vec![])),
+            call_timeout: None,
         }
     }
 
+    // sets the max duration send will wait for a response before failing,
+    // this only applies to this handle and those cloned from it afterwards
+    pub fn set_call_timeout(&mut self, timeout: Option<Duration>) {
+        self.call_timeout = timeout;
+    }
+
+    // sets the max size of frames sent or received on the channel,
+    // receiving a larger frame closes the channel
+    pub fn set_max_frame_size(&self, size: usize) {
+        self.max_frame_size
+            .store(size.min(MAX_FRAME_SIZE_LIMIT), Ordering::Relaxed);
+    }
+
+    // offers compression to the remote during the handshake, frames are
+    // only compressed if the remote has also enabled compression
+    pub fn set_compression(&mut self, conf: Option<CompressionConf>) {
+        self.compression = conf;
+    }
+
+    // requires the remote to authenticate with the key during the handshake
+    pub fn set_psk(&mut self, psk: Option<Psk>) {
+        self.psk = psk.map(Arc::new);
+    }
+
+    // the capabilities supported by both sides of the channel
+    pub fn capabilities(&self) -> Capabilities {
+        self.capabilities.lock().unwrap().clone()
+    }
+
     // receives and responds to a command from the remote
-    // note: this only supports one thread receiving messages per chan
     pub fn receive<Req, Res, F>(&mut self, chan: Channel, handler: F) -> Result<(), String>
     where
         Req: Encode + Decode + Debug,
@@ -78,30 +208,36 @@ impl RemoteChannel {
         F: FnOnce(Req) -> Res,
     {
         let req = self.receive_request::<Req>(chan)?;
-        let res = handler(req);
-        self.send_response(chan, res)?;
+        let res = handler(req.payload);
+        self.send_response(chan, req.id, res)?;
 
         Ok(())
     }
 
-    pub fn receive_request<Req>(&mut self, chan: Channel) -> Result<Req, String>
+    // receives the next request on the chan, the returned id must
+    // be passed to send_response when replying to the request
+    pub fn receive_request<Req>(&mut self, chan: Channel) -> Result<proto::Message<Req>, String>
     where
         Req: Encode + Decode + Debug,
     {
-        self.read_msg(chan, MessageMode::Request)
+        self.read_msg(chan, MessageMode::Request, None, None)
     }
 
     // makes an synchronous RPC style call to the remote
-    // note: this only supports one thread sending messages per chan
+    // many threads can have calls in flight on the same chan as
+    // responses are matched by the id of the request
     pub fn send<Req, Res>(&mut self, chan: Channel, req: Req) -> Result<Res, String>
     where
         Req: Encode + Decode + Debug,
         Res: Encode + Decode + Debug,
     {
-        self.write_msg(chan, MessageMode::Request, req)?;
-        let res = self.read_msg(chan, MessageMode::Response)?;
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let deadline = self.call_timeout.map(|t| Instant::now() + t);
+
+        self.write_msg(id, chan, MessageMode::Request, req)?;
+        let res = self.read_msg(chan, MessageMode::Response, Some(id), deadline)?;
 
-        Ok(res)
+        Ok(res.payload)
     }
 
     pub fn timeout<F, R>(&mut self, timeout: Duration, op: F) -> Option<R>
@@ -121,139 +257,451 @@ impl RemoteChannel {
         res.ok()
     }
 
-    pub fn send_response<Res>(&mut self, chan: Channel, res: Res) -> Result<(), String>
+    // sends a request which the remote does not reply to
+    pub fn notify<Req>(&mut self, chan: Channel, req: Req) -> Result<(), String>
+    where
+        Req: Encode + Debug,
+    {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+
+        self.write_msg(id, chan, MessageMode::Request, req)
+    }
+
+    pub fn send_response<Res>(&mut self, chan: Channel, id: u32, res: Res) -> Result<(), String>
     where
         Res: Encode + Decode + Debug,
     {
-        self.write_msg(chan, MessageMode::Response, res)
+        self.write_msg(id, chan, MessageMode::Response, res)
+    }
+
+    // registers a callback which is invoked when the channel is closed
+    // due to the remote disconnecting or failing to respond to heartbeats
+    pub fn on_disconnect(&self, hook: impl FnOnce(&str) + Send + 'static) {
+        let closed = self.receiver.state.lock().unwrap().closed.clone();
+
+        match closed {
+            Some(reason) => hook(&reason),
+            None => self.disconnect_hooks.lock().unwrap().push(Box::new(hook)),
+        }
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.receiver.state.lock().unwrap().closed.is_some()
+    }
+
+    // closes the channel, failing any pending calls and
+    // notifying the registered disconnect hooks
+    fn close(&self, reason: String) {
+        {
+            let mut state = self.receiver.state.lock().unwrap();
+
+            if state.closed.is_some() {
+                return;
+            }
+
+            debug(format!("closing channel: {}", reason));
+            state.closed = Some(reason.clone());
+            self.receiver.condvar.notify_all();
+        }
+
+        // unblock the thread reading from the transport
+        if let Some(shutdown) = self.shutdown.lock().unwrap().as_ref() {
+            shutdown();
+        }
+
+        let hooks = std::mem::take(&mut *self.disconnect_hooks.lock().unwrap());
+        for hook in hooks {
+            hook(&reason);
+        }
     }
 
     // serialise and write the request to the underlying transport
-    fn write_msg<Req>(&mut self, chan: Channel, mode: MessageMode, req: Req) -> Result<(), String>
+    fn write_msg<Req>(
+        &self,
+        id: u32,
+        chan: Channel,
+        mode: MessageMode,
+        req: Req,
+    ) -> Result<(), String>
+    where
+        Req: Encode + Debug,
+    {
+        self.write_msg_locking(id, chan, mode, req, true)
+    }
+
+    // writes the message only if no other thread is writing, used for
+    // messages which must not stall the calling thread behind a full transport
+    fn try_write_msg<Req>(
+        &self,
+        id: u32,
+        chan: Channel,
+        mode: MessageMode,
+        req: Req,
+    ) -> Result<(), String>
+    where
+        Req: Encode + Debug,
+    {
+        self.write_msg_locking(id, chan, mode, req, false)
+    }
+
+    fn write_msg_locking<Req>(
+        &self,
+        id: u32,
+        chan: Channel,
+        mode: MessageMode,
+        req: Req,
+        block: bool,
+    ) -> Result<(), String>
     where
         Req: Encode + Debug,
     {
         debug(format!(
-            "sending {} message: {:?} {:?}",
+            "sending {} message: {:?} #{} {:?}",
             type_name::<Req>(),
             chan,
+            id,
             req
         ));
         let data = bincode::encode_to_vec(req, self.conf)
             .map_err(|e| format!("failed to encode req: {}", e))?;
 
-        let msg = Message { chan, mode, data };
+        let msg = Message {
+            id,
+            chan,
+            mode,
+            data,
+        };
         let data = bincode::encode_to_vec(msg, self.conf)
             .map_err(|e| format!("failed to encode message: {}", e))?;
 
-        let mut writer = self.writer.lock().unwrap();
-        writer
-            .write_all(data.as_slice())
-            .map_err(|e| format!("failed to send req: {}", e))?;
+        // the uncompressed size is checked as the remote
+        // must also be able to decompress the frame
+        let max_size = self.max_frame_size.load(Ordering::Relaxed);
+        check_frame_size(data.len(), max_size)?;
 
-        writer
-            .flush()
-            .map_err(|e| format!("failed to flush: {}", e))?;
+        let generation = {
+            let state = self.receiver.state.lock().unwrap();
 
-        Ok(())
+            if let Some(reason) = state.closed.as_ref() {
+                return Err(format!("channel closed: {}", reason));
+            }
+
+            state.generation
+        };
+
+        let res = {
+            let mut writer = match block {
+                true => self.writer.lock().unwrap(),
+                false => match self.writer.try_lock() {
+                    Ok(writer) => writer,
+                    Err(TryLockError::WouldBlock) => return Err("writer is busy".to_string()),
+                    Err(err) => panic!("{}", err),
+                },
+            };
+
+            self.encode_msg_frame(data.as_slice(), max_size)
+                .and_then(|frame| {
+                    writer
+                        .write_all(frame.as_slice())
+                        .map_err(|e| format!("failed to send req: {}", e))
+                })
+                .and_then(|_| {
+                    writer
+                        .flush()
+                        .map_err(|e| format!("failed to flush: {}", e))
+                })
+        };
+
+        // a failed write leaves the stream in an unknown state so
+        // the channel is closed, unless the transport has since been replaced
+        if let Err(err) = res.as_ref() {
+            if self.receiver.state.lock().unwrap().generation == generation {
+                self.close(err.clone());
+            }
+        }
+
+        res
     }
 
-    fn read_msg<Res>(&self, chan: Channel, mode: MessageMode) -> Result<Res, String>
+    // frames the encoded message, compressing it if negotiated.
+    // this must be called while holding the writer lock so frames are
+    // written in the same order they were compressed
+    fn encode_msg_frame(&self, data: &[u8], max_size: usize) -> Result<Vec<u8>, String> {
+        let compressed = match self.compressor.lock().unwrap().as_mut() {
+            Some(compressor) => compressor.compress(data)?,
+            None => None,
+        };
+
+        match compressed {
+            Some(compressed) => encode_frame(
+                FrameType::CompressedMessage,
+                compressed.as_slice(),
+                max_size,
+            ),
+            None => encode_frame(FrameType::Message, data, max_size),
+        }
+    }
+
+    // waits for a message matching the chan, mode and (optionally) id
+    // whichever thread is waiting reads the next message from the transport
+    // and queues it for the thread it is destined for.
+    // if a deadline is supplied, waiting stops once it has passed
+    fn read_msg<Res>(
+        &self,
+        chan: Channel,
+        mode: MessageMode,
+        id: Option<u32>,
+        deadline: Option<Instant>,
+    ) -> Result<proto::Message<Res>, String>
     where
         Res: Decode + Debug,
     {
+        let mut state = self.receiver.state.lock().unwrap();
+
         loop {
-            // ensure mutex is unlocked after checking if message is available
-            {
-                let queue = self.receiver.queue.lock().unwrap();
-                if let Some(res) = self.find_matching_message(queue, chan, mode)? {
+            // messages received before the channel was closed
+            // are still delivered
+            if let Some(res) = self.find_matching_message(&mut state, chan, mode, id)? {
+                debug(format!(
+                    "received {} message: {:?} #{} {:?}",
+                    type_name::<Res>(),
+                    chan,
+                    res.id,
+                    res.payload
+                ));
+                return Ok(res);
+            }
+
+            if let Some(reason) = state.closed.as_ref() {
+                return Err(format!("channel closed: {}", reason));
+            }
+
+            let remaining = deadline.map(|d| d.saturating_duration_since(Instant::now()));
+
+            if remaining == Some(Duration::ZERO) {
+                return Err(Self::abandon(&mut state, chan, id));
+            }
+
+            // if another thread is reading we wait until it notifies
+            // and then check the queued messages again as the received message
+            // could be for any thread!
+            if state.reading {
+                state = match remaining {
+                    Some(remaining) => {
+                        self.receiver
+                            .condvar
+                            .wait_timeout(state, remaining)
+                            .unwrap()
+                            .0
+                    }
+                    None => self.receiver.condvar.wait(state).unwrap(),
+                };
+                continue;
+            }
+
+            // ensure only single thread is trying to read the next message
+            state.reading = true;
+            let generation = state.generation;
+            drop(state);
+
+            let msg = self.read_next_msg(deadline);
+
+            state = self.receiver.state.lock().unwrap();
+            state.reading = false;
+
+            // trigger all waiting threads to check if the new message is for them
+            self.receiver.condvar.notify_all();
+
+            let msg = match msg {
+                Ok(msg) => msg,
+                // nothing was read so the stream is intact and
+                // can be read by the next waiting thread
+                Err(ReadError::TimedOut) => return Err(Self::abandon(&mut state, chan, id)),
+                // the transport was replaced while reading so the
+                // failure is no longer relevant
+                Err(ReadError::Failed(_)) if state.generation != generation => continue,
+                Err(ReadError::Failed(err)) => {
+                    // the stream cannot recover from a failed read so we close the
+                    // channel which will also fail any other waiting threads
+                    drop(state);
+                    self.close(err.clone());
+                    return Err(err);
+                }
+            };
+
+            state.last_received = Instant::now();
+
+            // discard late responses to calls which have timed out
+            if msg.mode == MessageMode::Response {
+                if let Some(idx) = state.abandoned.iter().position(|i| *i == msg.id) {
                     debug(format!(
-                        "received {} message: {:?} {:?}",
-                        type_name::<Res>(),
-                        chan,
-                        res
+                        "discarding late response: {:?} #{}",
+                        msg.chan, msg.id
                     ));
-                    return Ok(res);
+                    state.abandoned.remove(idx);
+                    continue;
                 }
             }
 
-            {
-                // ensure only single thread is trying to read the next message
-                let lock = self.reader.try_lock();
-
-                if lock.is_err() {
-                    // if another thread got the lock we will wait until it notifies
-                    // and then check the queued messages again as the received message
-                    // could be for any thread!
-                    let queue = self.receiver.queue.lock().unwrap();
-                    let queue = self.receiver.condvar.wait(queue).unwrap();
-
-                    if let Some(res) = self.find_matching_message(queue, chan, mode)? {
-                        debug(format!(
-                            "received {} message: {:?} {:?}",
-                            type_name::<Res>(),
-                            chan,
-                            res
-                        ));
-                        return Ok(res);
-                    }
+            // heartbeats are handled here so they are answered regardless
+            // of which thread is reading from the transport
+            if msg.chan == Channel::CONTROL {
+                drop(state);
+                self.handle_control_msg(msg);
+                state = self.receiver.state.lock().unwrap();
+                continue;
+            }
 
-                    continue;
+            state.queue.push(msg);
+        }
+    }
+
+    // reads the next message from the transport, if a deadline is supplied
+    // the read is interrupted once it passes
+    fn read_next_msg(&self, deadline: Option<Instant>) -> Result<Message, ReadError> {
+        let mut reader = DeadlineReader {
+            inner: self.reader.lock().unwrap(),
+            deadline,
+            read: 0,
+            timed_out: false,
+        };
+
+        let res = match deadline {
+            #[cfg(target_os = "linux")]
+            Some(deadline) => {
+                let remaining = deadline.saturating_duration_since(Instant::now());
+
+                if remaining.is_zero() {
+                    return Err(ReadError::TimedOut);
                 }
 
-                let reader = lock.unwrap();
-                // TODO: fix lifetime hack
-                let reader =
-                    unsafe { std::mem::transmute::<_, MutexGuard<'static, dyn io::Read>>(reader) };
-                let msg = bincode::decode_from_std_read(&mut LockedMutexReader(reader), self.conf)
-                    .map_err(|e| format!("failed to decode err: {}", e));
-
-                // if failed to read message notify other waiting threads
-                let msg = match msg {
-                    Ok(m) => m,
-                    Err(err) => {
-                        self.receiver.condvar.notify_all();
-                        return Err(err);
+                // the blocking read is interrupted by SIGALRM once the timeout expires
+                let res = timeout(remaining, || self.decode_next_msg(&mut reader));
+
+                match res {
+                    TimeoutResult::Ok(res) | TimeoutResult::Timeout(res) => res,
+                    TimeoutResult::Error(err) => {
+                        return Err(ReadError::Failed(format!(
+                            "failed to start timeout: {}",
+                            err
+                        )))
                     }
-                };
+                }
+            }
+            _ => self.decode_next_msg(&mut reader),
+        };
 
-                let mut queue = self.receiver.queue.lock().unwrap();
-                queue.push(msg);
+        match res {
+            Ok(msg) => Ok(msg),
+            Err(_) if reader.timed_out && reader.read == 0 => Err(ReadError::TimedOut),
+            Err(e) => Err(ReadError::Failed(e)),
+        }
+    }
 
-                // trigger all waiting threads to check if the new message is for them
-                self.receiver.condvar.notify_all();
-            }
+    fn decode_next_msg(&self, reader: &mut impl io::Read) -> Result<Message, String> {
+        let max_size = self.max_frame_size.load(Ordering::Relaxed);
+        let frame =
+            read_frame(reader, max_size).map_err(|e| format!("failed to read frame: {}", e))?;
+
+        let payload = match frame.typ {
+            FrameType::Message => frame.payload,
+            FrameType::CompressedMessage => match self.decompressor.lock().unwrap().as_mut() {
+                Some(decompressor) => {
+                    decompressor.decompress(frame.payload.as_slice(), max_size)?
+                }
+                None => return Err("received compressed frame without compression".to_string()),
+            },
+        };
+
+        let (msg, len) = bincode::decode_from_slice::<Message, _>(payload.as_slice(), self.conf)
+            .map_err(|e| format!("failed to decode err: {}", e))?;
+
+        if len != payload.len() {
+            return Err(format!(
+                "failed to decode err: {} trailing bytes in frame",
+                payload.len() - len
+            ));
+        }
+
+        Ok(msg)
+    }
+
+    // records the call as timed out so a late response is discarded
+    fn abandon(state: &mut ReceiverState, chan: Channel, id: Option<u32>) -> String {
+        if let Some(id) = id {
+            state.abandoned.push(id);
         }
+
+        debug(format!(
+            "timed out waiting for message: {:?} #{:?}",
+            chan, id
+        ));
+        format!("timed out waiting for response on {:?}", chan)
     }
 
     fn find_matching_message<Res>(
         &self,
-        mut queue: MutexGuard<Vec<Message>>,
+        state: &mut ReceiverState,
         chan: Channel,
         mode: MessageMode,
-    ) -> Result<Option<Res>, String>
+        id: Option<u32>,
+    ) -> Result<Option<proto::Message<Res>>, String>
     where
         Res: Decode,
     {
-        if let Some(idx) = queue.iter().position(|m| m.chan == chan && m.mode == mode) {
-            let msg = queue.remove(idx);
+        let idx = state
+            .queue
+            .iter()
+            .position(|m| m.chan == chan && m.mode == mode && (id.is_none() || id == Some(m.id)));
 
-            let (res, _) = bincode::decode_from_slice(msg.data.as_slice(), self.conf)
+        if let Some(idx) = idx {
+            let msg = state.queue.remove(idx);
+
+            let (payload, _) = bincode::decode_from_slice(msg.data.as_slice(), self.conf)
                 .map_err(|e| format!("failed to decode request: {}", e))?;
 
-            Ok(Some(res))
+            Ok(Some(proto::Message {
+                id: msg.id,
+                payload,
+            }))
         } else {
             Ok(None)
         }
     }
 }
 
-// not too sure why this is necessary but for some reason
-// the Read trait is not implemented on the mutex guard of the inner Read
-struct LockedMutexReader<'a>(MutexGuard<'a, dyn io::Read>);
-impl<'a> io::Read for LockedMutexReader<'a> {
+enum ReadError {
+    // the deadline passed before any data was read
+    TimedOut,
+    Failed(String),
+}
+
+// retries reads interrupted by signals until the deadline has passed
+struct DeadlineReader<'a> {
+    inner: MutexGuard<'a, Box<dyn io::Read + Send>>,
+    deadline: Option<Instant>,
+    // number of bytes read
+    read: usize,
+    timed_out: bool,
+}
+
+impl<'a> io::Read for DeadlineReader<'a> {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        self.0.read(buf)
+        loop {
+            match self.inner.read(buf) {
+                Ok(n) => {
+                    self.read += n;
+                    return Ok(n);
+                }
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {
+                    if matches!(self.deadline, Some(d) if Instant::now() >= d) {
+                        self.timed_out = true;
+                        return Err(io::Error::new(io::ErrorKind::TimedOut, "read timed out"));
+                    }
+                }
+                Err(err) => return Err(err),
+            }
+        }
     }
 }
 
@@ -263,17 +711,34 @@ impl Clone for RemoteChannel {
             reader: self.reader.clone(),
             writer: self.writer.clone(),
             conf: self.conf,
+            max_frame_size: self.max_frame_size.clone(),
+            compression: self.compression,
+            compressor: self.compressor.clone(),
+            decompressor: self.decompressor.clone(),
+            psk: self.psk.clone(),
             receiver: self.receiver.clone(),
+            next_id: self.next_id.clone(),
+            capabilities: self.capabilities.clone(),
+            shutdown: self.shutdown.clone(),
+            disconnect_hooks: self.disconnect_hooks.clone(),
+            call_timeout: self.call_timeout,
         }
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use std::thread;
+    use std::{
+        os::unix::net::UnixStream,
+        thread,
+        time::{Duration, Instant},
+    };
 
     use crate::{
-        channel::{transport::mem::MemoryTransport, Channel, RemoteChannel},
+        channel::{
+            transport::{mem::MemoryTransport, unix_socket::UnixSocketTransport},
+            Channel, RemoteChannel,
+        },
         proto::{
             master::{
                 PtyMasterCall, PtyMasterResponse, PtyMasterSignal, SignalCall, WriteStdinCall,
@@ -360,6 +825,126 @@ mod tests {
         reply_thread.join().expect("failed to join reply thread");
     }
 
+    #[test]
+    fn test_send_concurrent_same_chan() {
+        let (t1, t2) = MemoryTransport::pair();
+
+        let c1 = RemoteChannel::new(t1);
+        let mut c2 = RemoteChannel::new(t2);
+        let num_threads = 10;
+
+        // send reqs from many threads on the same chan
+        let send_threads = (0..num_threads)
+            .map(|i| {
+                let mut c1 = c1.clone();
+                thread::spawn(move || {
+                    let res = c1
+                        .send::<PtySlaveCall, PtySlaveResponse>(
+                            Channel::PTY,
+                            PtySlaveCall {
+                                fd: Fd(i),
+                                typ: PtySlaveCallType::GetAttr,
+                            },
+                        )
+                        .unwrap();
+
+                    assert_eq!(res, PtySlaveResponse::Success(i as _));
+                })
+            })
+            .collect::<Vec<_>>();
+
+        // receive all reqs before replying in reverse order
+        let mut reqs = (0..num_threads)
+            .map(|_| c2.receive_request::<PtySlaveCall>(Channel::PTY).unwrap())
+            .collect::<Vec<_>>();
+        reqs.reverse();
+
+        for req in reqs {
+            c2.send_response(
+                Channel::PTY,
+                req.id,
+                PtySlaveResponse::Success(req.payload.fd.0 as _),
+            )
+            .unwrap();
+        }
+
+        for t in send_threads {
+            t.join().expect("failed to join send thread");
+        }
+    }
+
+    #[test]
+    fn test_send_call_timeout() {
+        let (s1, s2) = UnixStream::pair().unwrap();
+
+        let mut c1 = RemoteChannel::new(UnixSocketTransport::new(s1));
+        let mut c2 = RemoteChannel::new(UnixSocketTransport::new(s2));
+        c1.set_call_timeout(Some(Duration::from_millis(100)));
+
+        let req = |i| PtySlaveCall {
+            fd: Fd(i),
+            typ: PtySlaveCallType::GetAttr,
+        };
+
+        // the remote is not replying so the call should time out
+        let start = Instant::now();
+        let res = c1.send::<PtySlaveCall, PtySlaveResponse>(Channel::PTY, req(1));
+        assert!(res.unwrap_err().starts_with("timed out"));
+        assert!(start.elapsed() >= Duration::from_millis(100));
+        assert!(!c1.is_closed());
+
+        // the late response should be discarded and not
+        // delivered to the next call
+        let reply_thread = thread::spawn(move || {
+            for _ in 0..2 {
+                c2.receive::<PtySlaveCall, PtySlaveResponse, _>(Channel::PTY, |req| {
+                    PtySlaveResponse::Success(req.fd.0 as _)
+                })
+                .unwrap();
+            }
+        });
+
+        let res = c1
+            .send::<PtySlaveCall, PtySlaveResponse>(Channel::PTY, req(2))
+            .unwrap();
+        assert_eq!(res, PtySlaveResponse::Success(2));
+
+        reply_thread.join().expect("failed to join reply thread");
+    }
+
+    #[test]
+    fn test_max_frame_size() {
+        let (s1, s2) = UnixStream::pair().unwrap();
+
+        let mut c1 = RemoteChannel::new(UnixSocketTransport::new(s1));
+        let mut c2 = RemoteChannel::new(UnixSocketTransport::new(s2));
+        c1.set_max_frame_size(1024);
+
+        let req = |len| PtySlaveCall {
+            fd: Fd(1),
+            typ: PtySlaveCallType::WriteStdout(WriteStdoutCall {
+                data: vec![0; len],
+                pgrp: 1,
+            }),
+        };
+
+        // oversized messages are rejected before they are sent
+        let res = c1.send::<PtySlaveCall, PtySlaveResponse>(Channel::STDOUT, req(2048));
+        assert!(res.unwrap_err().contains("exceeds the max frame size"));
+        assert!(!c1.is_closed());
+
+        // receiving an oversized frame closes the channel
+        let send_thread = thread::spawn(move || {
+            c2.send::<PtySlaveCall, PtySlaveResponse>(Channel::STDOUT, req(2048))
+        });
+
+        let res = c1.receive_request::<PtySlaveCall>(Channel::STDOUT);
+        assert!(res.unwrap_err().contains("exceeds the max frame size"));
+        assert!(c1.is_closed());
+
+        assert!(send_thread.join().unwrap().is_err());
+    }
+
     #[test]
     fn test_send_receive_multiple_types() {
         let (t1, t2) = MemoryTransport::pair();
@@ -522,6 +1107,7 @@ mod tests {
                                 fd: Fd(i),
                                 typ: PtySlaveCallType::WriteStdout(WriteStdoutCall {
                                     data: vec![1, 2, 3],
+                                    pgrp: 1,
                                 }),
                             },
                         )
@@ -586,6 +1172,7 @@ mod tests {
                                     fd: Fd(i),
                                     typ: PtySlaveCallType::WriteStdout(WriteStdoutCall {
                                         data: vec![1, 2, 3],
+                                        pgrp: 1,
                                     })
                                 }
                             );
@@ -0,0 +1,306 @@
+use std::{
+    collections::HashMap,
+    io::{self, Read, Write},
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        mpsc::{channel, Receiver, Sender},
+        Arc, Mutex,
+    },
+};
+
+use crate::proto::mux::{MuxFrame, MuxFrameType};
+
+use super::{
+    transport::{ShutdownHandle, Transport},
+    Channel, RemoteChannel,
+};
+
+// max bytes sent in a single frame, larger writes are split so the
+// frames of the processes always fit within the connection's max frame size
+const MAX_CHUNK_SIZE: usize = 16 * 1024;
+
+// multiplexes the connections of the processes of a session over a single
+// connection to the master. the broker opens a stream for each process
+// connected to it and the master accepts them as if they were connected
+// directly, so each stream carries a complete channel including its handshake
+#[derive(Clone)]
+pub struct Mux {
+    chan: RemoteChannel,
+    // senders of the data received on each open stream
+    streams: Arc<Mutex<HashMap<u32, Sender<Vec<u8>>>>>,
+    next_stream: Arc<AtomicU32>,
+}
+
+// the connection of a single process over the mux
+pub struct MuxStream {
+    mux: Mux,
+    id: u32,
+    receiver: Receiver<Vec<u8>>,
+}
+
+struct MuxReader {
+    receiver: Receiver<Vec<u8>>,
+    buf: Vec<u8>,
+}
+
+// buffers writes until flushed so each channel frame is sent together
+struct MuxWriter {
+    mux: Mux,
+    id: u32,
+    buf: Vec<u8>,
+}
+
+impl Mux {
+    pub fn new(chan: RemoteChannel) -> Self {
+        Self {
+            chan,
+            streams: Arc::new(Mutex::new(HashMap::new())),
+            next_stream: Arc::new(AtomicU32::new(1)),
+        }
+    }
+
+    // opens a stream for the process connected to the broker
+    pub fn open(&self, pid: u32) -> Result<MuxStream, String> {
+        let id = self.next_stream.fetch_add(1, Ordering::Relaxed);
+        let stream = self.stream(id);
+
+        if let Err(err) = self.send(id, MuxFrameType::Open { pid }) {
+            self.streams.lock().unwrap().remove(&id);
+            return Err(err);
+        }
+
+        Ok(stream)
+    }
+
+    // dispatches the data received for the open streams until the remote
+    // opens a new stream, returning it with the pid of its process.
+    // once the connection is closed all streams are closed
+    pub fn accept(&mut self) -> Result<(u32, MuxStream), String> {
+        loop {
+            let frame = match self.chan.receive_request::<MuxFrame>(Channel::MUX) {
+                Ok(req) => req.payload,
+                Err(err) => {
+                    self.streams.lock().unwrap().clear();
+                    return Err(err);
+                }
+            };
+
+            let mut streams = self.streams.lock().unwrap();
+
+            match frame.typ {
+                MuxFrameType::Open { pid } => {
+                    drop(streams);
+                    return Ok((pid, self.stream(frame.stream)));
+                }
+                // data for streams which have since been closed is discarded
+                MuxFrameType::Data(data) => {
+                    if let Some(sender) = streams.get(&frame.stream) {
+                        let _ = sender.send(data);
+                    }
+                }
+                MuxFrameType::Close => {
+                    streams.remove(&frame.stream);
+                }
+            }
+        }
+    }
+
+    fn stream(&self, id: u32) -> MuxStream {
+        let (sender, receiver) = channel();
+        self.streams.lock().unwrap().insert(id, sender);
+
+        MuxStream {
+            mux: self.clone(),
+            id,
+            receiver,
+        }
+    }
+
+    fn send(&self, id: u32, typ: MuxFrameType) -> Result<(), String> {
+        self.chan
+            .clone()
+            .notify(Channel::MUX, MuxFrame { stream: id, typ })
+    }
+
+    // closes the stream, the remote is notified unless it closed it first
+    fn close(&self, id: u32) {
+        if self.streams.lock().unwrap().remove(&id).is_some() {
+            let _ = self.send(id, MuxFrameType::Close);
+        }
+    }
+
+    fn is_open(&self, id: u32) -> bool {
+        self.streams.lock().unwrap().contains_key(&id)
+    }
+}
+
+impl Transport for MuxStream {
+    fn split(self) -> (Box<dyn Read + Send>, Box<dyn Write + Send>) {
+        (
+            Box::new(MuxReader {
+                receiver: self.receiver,
+                buf: vec![],
+            }),
+            Box::new(MuxWriter {
+                mux: self.mux,
+                id: self.id,
+                buf: vec![],
+            }),
+        )
+    }
+
+    // closing the stream unblocks the reader
+    fn shutdown_handle(&self) -> Option<ShutdownHandle> {
+        let (mux, id) = (self.mux.clone(), self.id);
+
+        Some(Arc::new(move || mux.close(id)))
+    }
+}
+
+impl Read for MuxReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.buf.is_empty() {
+            self.buf = match self.receiver.recv() {
+                Ok(data) => data,
+                // the stream was closed
+                Err(_) => return Ok(0),
+            };
+        }
+
+        let len = buf.len().min(self.buf.len());
+        buf[..len].copy_from_slice(&self.buf[..len]);
+        self.buf.drain(..len);
+
+        Ok(len)
+    }
+}
+
+impl Write for MuxWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buf.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if !self.mux.is_open(self.id) {
+            return Err(io::Error::from(io::ErrorKind::BrokenPipe));
+        }
+
+        for chunk in self.buf.chunks(MAX_CHUNK_SIZE) {
+            self.mux
+                .send(self.id, MuxFrameType::Data(chunk.to_vec()))
+                .map_err(|e| io::Error::new(io::ErrorKind::BrokenPipe, e))?;
+        }
+
+        self.buf.clear();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{Read, Write},
+        thread,
+    };
+
+    use crate::channel::{
+        transport::{mem::MemoryTransport, Transport},
+        Channel, RemoteChannel,
+    };
+
+    use super::{Mux, MAX_CHUNK_SIZE};
+
+    fn pair() -> (Mux, Mux) {
+        let (t1, t2) = MemoryTransport::pair();
+
+        (
+            Mux::new(RemoteChannel::new(t1)),
+            Mux::new(RemoteChannel::new(t2)),
+        )
+    }
+
+    #[test]
+    fn test_mux_streams() {
+        let (broker, mut master) = pair();
+
+        let first = broker.open(10).unwrap();
+        let second = broker.open(20).unwrap();
+
+        let (pid, first_remote) = master.accept().unwrap();
+        assert_eq!(pid, 10);
+        let (pid, second_remote) = master.accept().unwrap();
+        assert_eq!(pid, 20);
+
+        // data is only dispatched while accepting
+        let mut dispatcher = broker.clone();
+        thread::spawn(move || dispatcher.accept());
+        let mut master_dispatcher = master.clone();
+        thread::spawn(move || master_dispatcher.accept());
+
+        let (_, mut first_write) = first.split();
+        let (mut second_read, _) = second.split();
+        let (mut first_remote_read, _) = first_remote.split();
+        let (_, mut second_remote_write) = second_remote.split();
+
+        let large = vec![7u8; MAX_CHUNK_SIZE * 2 + 1];
+        first_write.write_all(&large).unwrap();
+        first_write.flush().unwrap();
+        let mut buf = vec![0u8; large.len()];
+        first_remote_read.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, large);
+
+        second_remote_write.write_all(b"to 20").unwrap();
+        second_remote_write.flush().unwrap();
+        let mut buf = [0u8; 5];
+        second_read.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"to 20");
+    }
+
+    #[test]
+    fn test_mux_close() {
+        let (broker, mut master) = pair();
+
+        let stream = broker.open(10).unwrap();
+        let shutdown = stream.shutdown_handle().unwrap();
+        let (_, remote) = master.accept().unwrap();
+        let (mut remote_read, _) = remote.split();
+
+        let mut master_dispatcher = master.clone();
+        thread::spawn(move || master_dispatcher.accept());
+
+        // the remote reads eof once the stream is closed
+        shutdown();
+        assert_eq!(remote_read.read(&mut [0u8; 1]).unwrap(), 0);
+
+        let (_, mut write) = stream.split();
+        write.write_all(b"closed").unwrap();
+        assert!(write.flush().is_err());
+    }
+
+    #[test]
+    fn test_mux_streams_are_channels() {
+        let (broker, mut master) = pair();
+
+        let master_thread = thread::spawn(move || {
+            let (_, stream) = master.accept().unwrap();
+            let mut dispatcher = master.clone();
+            thread::spawn(move || dispatcher.accept());
+
+            let mut chan = RemoteChannel::new(stream);
+            chan.accept_handshake().unwrap();
+            chan.receive::<u8, u8, _>(Channel::PTY, |req| req + 1)
+                .unwrap();
+        });
+
+        let stream = broker.open(10).unwrap();
+        let mut dispatcher = broker.clone();
+        thread::spawn(move || dispatcher.accept());
+
+        let mut chan = RemoteChannel::new(stream);
+        chan.handshake().unwrap();
+        assert_eq!(chan.send::<u8, u8>(Channel::PTY, 1).unwrap(), 2);
+
+        master_thread.join().unwrap();
+    }
+}
@@ -0,0 +1,144 @@
+use std::{
+    mem,
+    time::{Duration, Instant},
+};
+
+use crate::log::debug;
+
+use super::RemoteChannel;
+
+impl RemoteChannel {
+    // replaces the transport of this closed channel with the transport of other,
+    // which must be connected to the same remote session.
+    // all clones of this channel will use the new transport and threads
+    // waiting in wait_resumed are woken
+    pub fn resume_with(&self, other: RemoteChannel) -> Result<(), String> {
+        {
+            let mut state = self.receiver.state.lock().unwrap();
+
+            if state.closed.is_none() {
+                return Err("cannot resume channel which is still open".to_string());
+            }
+
+            // ensure failures from reads on the old transport are ignored
+            state.generation += 1;
+        }
+
+        mem::swap(
+            &mut *self.reader.lock().unwrap(),
+            &mut *other.reader.lock().unwrap(),
+        );
+        mem::swap(
+            &mut *self.writer.lock().unwrap(),
+            &mut *other.writer.lock().unwrap(),
+        );
+        mem::swap(
+            &mut *self.shutdown.lock().unwrap(),
+            &mut *other.shutdown.lock().unwrap(),
+        );
+        // the compression streams are tied to the transport
+        mem::swap(
+            &mut *self.compressor.lock().unwrap(),
+            &mut *other.compressor.lock().unwrap(),
+        );
+        mem::swap(
+            &mut *self.decompressor.lock().unwrap(),
+            &mut *other.decompressor.lock().unwrap(),
+        );
+        *self.capabilities.lock().unwrap() = other.capabilities();
+
+        let mut state = self.receiver.state.lock().unwrap();
+        state.closed = None;
+        state.last_received = Instant::now();
+        self.receiver.condvar.notify_all();
+
+        debug("resumed channel");
+        Ok(())
+    }
+
+    // blocks until the channel is resumed or the timeout expires,
+    // returns whether the channel is open
+    pub fn wait_resumed(&self, timeout: Duration) -> bool {
+        let state = self.receiver.state.lock().unwrap();
+
+        let (state, _) = self
+            .receiver
+            .condvar
+            .wait_timeout_while(state, timeout, |s| s.closed.is_some())
+            .unwrap();
+
+        state.closed.is_none()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{os::unix::net::UnixStream, thread, time::Duration};
+
+    use crate::{
+        channel::{transport::unix_socket::UnixSocketTransport, Channel, RemoteChannel},
+        proto::{
+            slave::{PtySlaveCall, PtySlaveCallType, PtySlaveResponse},
+            Fd,
+        },
+    };
+
+    fn pair() -> (RemoteChannel, RemoteChannel) {
+        let (s1, s2) = UnixStream::pair().unwrap();
+
+        (
+            RemoteChannel::new(UnixSocketTransport::new(s1)),
+            RemoteChannel::new(UnixSocketTransport::new(s2)),
+        )
+    }
+
+    fn call(chan: &mut RemoteChannel) -> Result<PtySlaveResponse, String> {
+        chan.send::<PtySlaveCall, PtySlaveResponse>(
+            Channel::PTY,
+            PtySlaveCall {
+                fd: Fd(0),
+                typ: PtySlaveCallType::GetAttr,
+            },
+        )
+    }
+
+    #[test]
+    fn test_resume_with_new_transport() {
+        let (mut c1, c2) = pair();
+
+        // simulate the transport being lost
+        drop(c2);
+        assert!(call(&mut c1).is_err());
+        assert!(c1.is_closed());
+
+        let waiting_thread = {
+            let c1 = c1.clone();
+            thread::spawn(move || c1.wait_resumed(Duration::from_secs(5)))
+        };
+
+        let (c3, mut c4) = pair();
+        c1.resume_with(c3).unwrap();
+        assert!(!c1.is_closed());
+        assert!(waiting_thread.join().unwrap());
+
+        let reply_thread = thread::spawn(move || {
+            c4.receive::<PtySlaveCall, PtySlaveResponse, _>(Channel::PTY, |_| {
+                PtySlaveResponse::Success(1)
+            })
+            .unwrap();
+        });
+
+        assert_eq!(call(&mut c1).unwrap(), PtySlaveResponse::Success(1));
+
+        reply_thread.join().expect("failed to join reply thread");
+    }
+
+    #[test]
+    fn test_resume_open_channel() {
+        let (c1, _c2) = pair();
+        let (c3, _c4) = pair();
+
+        assert!(c1.resume_with(c3).is_err());
+        assert!(c1.wait_resumed(Duration::from_millis(1)));
+    }
+}
@@ -0,0 +1,265 @@
+use std::{collections::VecDeque, env};
+
+use crate::{log::debug, proto::StreamCredit};
+
+// the window assumed by a sender until the receiver grants credit
+pub const DEFAULT_STREAM_WINDOW: u64 = 64 * 1024;
+
+// reads the window granted to the remote when receiving streams from the env
+pub fn stream_window_from_env() -> Result<u64, String> {
+    let window = match env::var("RPTY_STREAM_WINDOW") {
+        Ok(w) => w
+            .parse::<u64>()
+            .map_err(|_| "failed to parse number in RPTY_STREAM_WINDOW")?,
+        Err(_) => return Ok(DEFAULT_STREAM_WINDOW),
+    };
+
+    if window == 0 {
+        return Err("RPTY_STREAM_WINDOW must be greater than 0".to_string());
+    }
+
+    Ok(window)
+}
+
+// tracks how much of a stream can be sent before the
+// receiver must grant more credit
+#[derive(Debug, Clone)]
+pub struct SendWindow {
+    // total bytes sent
+    sent: u64,
+    // total bytes the receiver has reported as consumed
+    consumed: u64,
+    window: u64,
+}
+
+impl SendWindow {
+    pub fn new() -> Self {
+        Self {
+            sent: 0,
+            consumed: 0,
+            window: DEFAULT_STREAM_WINDOW,
+        }
+    }
+
+    // the number of bytes which can be sent without more credit
+    pub fn available(&self) -> usize {
+        (self.consumed + self.window).saturating_sub(self.sent) as usize
+    }
+
+    pub fn record_sent(&mut self, len: usize) {
+        self.sent += len as u64;
+    }
+
+    pub fn grant(&mut self, credit: StreamCredit) {
+        self.consumed = self.consumed.max(credit.consumed);
+        self.window = credit.window;
+    }
+
+    // resets the window after resuming, the data after the offset
+    // received by the remote must be resent
+    pub fn rewind(&mut self, received: u64) {
+        self.sent = received;
+        self.consumed = self.consumed.max(received);
+    }
+}
+
+impl Default for SendWindow {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// tracks how much of a stream has been consumed and when
+// the sender should be granted more credit
+#[derive(Debug, Clone)]
+pub struct ReceiveWindow {
+    // total bytes consumed
+    consumed: u64,
+    // the consumed offset when credit was last granted
+    granted: u64,
+    window: u64,
+}
+
+impl ReceiveWindow {
+    pub fn new(window: u64) -> Self {
+        Self {
+            consumed: 0,
+            granted: 0,
+            window,
+        }
+    }
+
+    pub fn credit(&self) -> StreamCredit {
+        StreamCredit {
+            consumed: self.consumed,
+            window: self.window,
+        }
+    }
+
+    // records the consumed bytes and returns the credit to grant the sender
+    // once half the window has drained, so not every chunk is acknowledged
+    pub fn consume(&mut self, len: usize) -> Option<StreamCredit> {
+        self.consumed += len as u64;
+
+        if self.consumed - self.granted < (self.window / 2).max(1) {
+            return None;
+        }
+
+        self.granted = self.consumed;
+        Some(self.credit())
+    }
+}
+
+// bounded buffer retaining the tail of a byte stream
+#[derive(Debug)]
+pub struct ReplayBuffer {
+    data: VecDeque<u8>,
+    // the offset in the stream of the first buffered byte
+    start: u64,
+    max_len: usize,
+}
+
+impl ReplayBuffer {
+    pub fn new(max_len: usize) -> Self {
+        Self {
+            data: VecDeque::new(),
+            start: 0,
+            max_len,
+        }
+    }
+
+    pub fn push(&mut self, data: &[u8]) {
+        self.data.extend(data);
+
+        if self.data.len() > self.max_len {
+            let overflow = self.data.len() - self.max_len;
+            debug(format!("replay buffer full, discarding {} bytes", overflow));
+            self.data.drain(..overflow);
+            self.start += overflow as u64;
+        }
+    }
+
+    // the offset of the end of the stream
+    pub fn end(&self) -> u64 {
+        self.start + self.data.len() as u64
+    }
+
+    // discards the data before the offset which has been received by the remote
+    pub fn ack(&mut self, offset: u64) {
+        let len = (offset.saturating_sub(self.start) as usize).min(self.data.len());
+        self.data.drain(..len);
+        self.start += len as u64;
+    }
+
+    // returns the buffered data after the offset
+    pub fn since(&self, offset: u64) -> Vec<u8> {
+        let skip = (offset.saturating_sub(self.start) as usize).min(self.data.len());
+
+        if offset < self.start {
+            debug(format!(
+                "replay buffer missing {} bytes",
+                self.start - offset
+            ));
+        }
+
+        self.data.iter().skip(skip).copied().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::proto::StreamCredit;
+
+    use super::{ReceiveWindow, ReplayBuffer, SendWindow, DEFAULT_STREAM_WINDOW};
+
+    #[test]
+    fn test_send_window() {
+        let mut window = SendWindow::new();
+        assert_eq!(window.available(), DEFAULT_STREAM_WINDOW as usize);
+
+        window.record_sent(DEFAULT_STREAM_WINDOW as usize);
+        assert_eq!(window.available(), 0);
+
+        window.grant(StreamCredit {
+            consumed: 10,
+            window: 100,
+        });
+        assert_eq!(window.available(), 0);
+
+        window.grant(StreamCredit {
+            consumed: DEFAULT_STREAM_WINDOW,
+            window: 100,
+        });
+        assert_eq!(window.available(), 100);
+
+        // stale credit is ignored
+        window.grant(StreamCredit {
+            consumed: 5,
+            window: 100,
+        });
+        assert_eq!(window.available(), 100);
+    }
+
+    #[test]
+    fn test_send_window_rewind() {
+        let mut window = SendWindow::new();
+        window.grant(StreamCredit {
+            consumed: 0,
+            window: 10,
+        });
+        window.record_sent(10);
+        assert_eq!(window.available(), 0);
+
+        // the remote received more than it reported as consumed
+        window.rewind(4);
+        assert_eq!(window.available(), 10);
+    }
+
+    #[test]
+    fn test_receive_window() {
+        let mut window = ReceiveWindow::new(10);
+        assert_eq!(
+            window.credit(),
+            StreamCredit {
+                consumed: 0,
+                window: 10
+            }
+        );
+
+        assert_eq!(window.consume(3), None);
+        assert_eq!(
+            window.consume(2),
+            Some(StreamCredit {
+                consumed: 5,
+                window: 10
+            })
+        );
+        assert_eq!(window.consume(4), None);
+    }
+
+    #[test]
+    fn test_replay_buffer_ack() {
+        let mut buf = ReplayBuffer::new(100);
+
+        buf.push(&[1, 2, 3]);
+        buf.push(&[4, 5]);
+        assert_eq!(buf.end(), 5);
+        assert_eq!(buf.since(0), vec![1, 2, 3, 4, 5]);
+        assert_eq!(buf.since(3), vec![4, 5]);
+
+        buf.ack(2);
+        assert_eq!(buf.end(), 5);
+        assert_eq!(buf.since(2), vec![3, 4, 5]);
+        assert_eq!(buf.since(5), Vec::<u8>::new());
+    }
+
+    #[test]
+    fn test_replay_buffer_bounded() {
+        let mut buf = ReplayBuffer::new(3);
+
+        buf.push(&[1, 2, 3, 4, 5]);
+        assert_eq!(buf.end(), 5);
+        // the oldest bytes are discarded once full
+        assert_eq!(buf.since(0), vec![3, 4, 5]);
+    }
+}
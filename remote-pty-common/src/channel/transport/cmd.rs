@@ -0,0 +1,107 @@
+use std::{
+    io,
+    os::{
+        fd::OwnedFd,
+        unix::{net::UnixStream, process::CommandExt},
+    },
+    process::{Command, Stdio},
+    thread,
+};
+
+use crate::log::debug;
+
+// spawns the command line with sh, returning a socket connected to its stdin
+// and stdout. a socket is used rather than pipes so the transport can be
+// shutdown and the helper sees eof on its stdin once the transport is closed
+pub fn spawn(cmdline: &str) -> io::Result<UnixStream> {
+    let (socket, helper) = UnixStream::pair()?;
+
+    let mut cmd = Command::new("/bin/sh");
+    cmd.arg("-c")
+        .arg(cmdline)
+        // the helper's stdio must not be intercepted
+        .env_remove("LD_PRELOAD")
+        .stdin(Stdio::from(OwnedFd::from(helper.try_clone()?)))
+        .stdout(Stdio::from(OwnedFd::from(helper)));
+
+    // the helper outlives the process which spawned it so must not hold
+    // its fds open, such as the pipes a shell uses to synchronise jobs
+    unsafe {
+        cmd.pre_exec(|| {
+            set_cloexec_from(3);
+            Ok(())
+        });
+    }
+
+    let mut child = cmd.spawn()?;
+
+    let pid = child.id();
+    debug(format!("spawned transport helper {}: {}", pid, cmdline));
+
+    // reap the helper once it exits
+    thread::spawn(move || match child.wait() {
+        Ok(status) => debug(format!("transport helper {} exited: {}", pid, status)),
+        Err(err) => debug(format!(
+            "failed to wait for transport helper {}: {}",
+            pid, err
+        )),
+    });
+
+    Ok(socket)
+}
+
+// marks the fds from the first fd as close on exec, must be async signal safe
+fn set_cloexec_from(first: u32) {
+    let res = unsafe {
+        libc::syscall(
+            libc::SYS_close_range,
+            first,
+            u32::MAX,
+            libc::CLOSE_RANGE_CLOEXEC,
+        )
+    };
+
+    // close_range is unsupported before linux 5.11
+    if res != 0 {
+        for fd in first as i32..1024 {
+            unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{Read, Write},
+        os::unix::{io::AsRawFd, net::UnixStream},
+    };
+
+    use super::spawn;
+
+    #[test]
+    fn test_spawn_does_not_inherit_fds() {
+        let (_keep, inherited) = UnixStream::pair().unwrap();
+        let fd = inherited.as_raw_fd();
+        unsafe { libc::fcntl(fd, libc::F_SETFD, 0) };
+
+        let mut socket = spawn(&format!("test -e /proc/self/fd/{} || echo closed", fd)).unwrap();
+
+        let mut out = String::new();
+        socket.read_to_string(&mut out).unwrap();
+
+        assert_eq!(out, "closed\n");
+    }
+
+    #[test]
+    fn test_spawn() {
+        let mut socket = spawn("tr a-z A-Z").unwrap();
+
+        socket.write_all(b"hello").unwrap();
+        socket.shutdown(std::net::Shutdown::Write).unwrap();
+
+        let mut out = String::new();
+        socket.read_to_string(&mut out).unwrap();
+
+        assert_eq!(out, "HELLO");
+    }
+}
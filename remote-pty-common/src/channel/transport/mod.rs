@@ -1,13 +1,31 @@
-use std::io::{Read, Write};
+use std::{
+    io::{Read, Write},
+    sync::Arc,
+};
 
 // generic duplex transport interface
 pub trait Transport {
     fn split(self) -> (Box<dyn Read + Send>, Box<dyn Write + Send>);
+
+    // returns a callback which forcibly closes the transport, unblocking
+    // any threads reading from it, if the transport supports it
+    fn shutdown_handle(&self) -> Option<ShutdownHandle> {
+        None
+    }
 }
 
+pub type ShutdownHandle = Arc<dyn Fn() + Send + Sync>;
+
 pub mod unix_socket;
 pub mod tcp;
+pub mod tls;
 pub mod mem;
 pub mod rw;
 pub mod conf;
+pub mod cmd;
 
+pub mod vsock;
+pub mod ws;
+pub mod proxy;
+pub mod reverse;
+pub mod relay;
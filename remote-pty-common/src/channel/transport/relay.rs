@@ -0,0 +1,233 @@
+use std::{
+    fmt,
+    io::{self, ErrorKind, Read, Write},
+    net::TcpStream,
+    str::FromStr,
+    time::Duration,
+};
+
+use crate::log::debug;
+
+use super::{
+    conf::{HostPort, TransportOptions},
+    reverse::wait_ready,
+    tcp,
+};
+
+// prefix of the line sent by each side when it connects to the relay
+const REGISTRATION_PREFIX: &str = "RPTY-RELAY";
+
+// max length of the registration line, including the key
+const MAX_REGISTRATION_LEN: usize = 512;
+
+// max duration the slave waits to be paired if no connect timeout is set
+const DEFAULT_PAIR_TIMEOUT: Duration = Duration::from_secs(10);
+
+// the side of the session a relayed connection belongs to,
+// each connection is paired with one from the other side
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub enum Role {
+    Master,
+    Slave,
+}
+
+impl Role {
+    pub fn peer(&self) -> Self {
+        match self {
+            Role::Master => Role::Slave,
+            Role::Slave => Role::Master,
+        }
+    }
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Role::Master => write!(f, "master"),
+            Role::Slave => write!(f, "slave"),
+        }
+    }
+}
+
+impl FromStr for Role {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "master" => Ok(Role::Master),
+            "slave" => Ok(Role::Slave),
+            _ => Err(format!("unknown relay role: {}", s)),
+        }
+    }
+}
+
+// session keys are sent on the registration line so cannot contain whitespace
+pub fn validate_key(key: &str) -> Result<(), String> {
+    if key.is_empty() {
+        return Err("missing relay session key".to_string());
+    }
+
+    if key.len() > MAX_REGISTRATION_LEN / 2 || key.chars().any(|c| c.is_whitespace()) {
+        return Err(format!("invalid relay session key: {}", key));
+    }
+
+    Ok(())
+}
+
+// connects to the relay and blocks until it has been paired with a
+// connection from the other side of the session, the returned stream
+// is spliced to the peer so anything written is received by it verbatim.
+// the master waits indefinitely as it is idle until a slave connects, the
+// slave times out rather than hanging the process if no master is waiting
+pub fn connect(
+    addr: &HostPort,
+    key: &str,
+    role: Role,
+    options: &TransportOptions,
+) -> io::Result<TcpStream> {
+    let mut socket = tcp::connect(addr, options)?;
+    socket.set_nodelay(options.nodelay)?;
+
+    register(&mut socket, role, key)?;
+    debug(format!("waiting for the relay to pair the {}", role.peer()));
+
+    let timeout = match role {
+        Role::Master => None,
+        Role::Slave => Some(options.connect_timeout.unwrap_or(DEFAULT_PAIR_TIMEOUT)),
+    };
+    socket.set_read_timeout(timeout)?;
+    wait_ready(&mut socket).map_err(|e| match e.kind() {
+        ErrorKind::WouldBlock | ErrorKind::TimedOut => io::Error::new(
+            ErrorKind::TimedOut,
+            format!("the relay did not pair the {} in time", role.peer()),
+        ),
+        _ => e,
+    })?;
+    socket.set_read_timeout(None)?;
+
+    Ok(socket)
+}
+
+pub fn register(stream: &mut impl Write, role: Role, key: &str) -> io::Result<()> {
+    validate_key(key).map_err(|e| io::Error::new(ErrorKind::InvalidInput, e))?;
+
+    stream.write_all(format!("{} {} {}\n", REGISTRATION_PREFIX, role, key).as_bytes())?;
+    stream.flush()
+}
+
+// reads the registration line sent by a connection to the relay, byte by
+// byte so nothing written after it is consumed
+pub fn read_registration(stream: &mut impl Read) -> io::Result<(Role, String)> {
+    let mut line = vec![];
+    let mut buf = [0u8];
+
+    loop {
+        stream.read_exact(&mut buf)?;
+
+        if buf[0] == b'\n' {
+            break;
+        }
+
+        if line.len() == MAX_REGISTRATION_LEN {
+            return Err(invalid_registration("registration is too long"));
+        }
+
+        line.push(buf[0]);
+    }
+
+    let line = String::from_utf8(line).map_err(|_| invalid_registration("invalid utf-8"))?;
+    let mut parts = line.split(' ');
+
+    if parts.next() != Some(REGISTRATION_PREFIX) {
+        return Err(invalid_registration(&line));
+    }
+
+    let (role, key) = match (parts.next(), parts.next(), parts.next()) {
+        (Some(role), Some(key), None) => (role, key),
+        _ => return Err(invalid_registration(&line)),
+    };
+
+    let role = role.parse().map_err(invalid_registration)?;
+    validate_key(key).map_err(invalid_registration)?;
+
+    Ok((role, key.to_string()))
+}
+
+fn invalid_registration(msg: impl fmt::Display) -> io::Error {
+    io::Error::new(
+        ErrorKind::InvalidData,
+        format!("invalid relay registration: {}", msg),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{Cursor, ErrorKind},
+        net::TcpListener,
+        thread,
+        time::Duration,
+    };
+
+    use crate::channel::transport::conf::{HostPort, TransportOptions};
+
+    use super::{connect, read_registration, register, validate_key, Role};
+
+    #[test]
+    fn test_registration() {
+        let mut buf = vec![];
+        register(&mut buf, Role::Slave, "session-1").unwrap();
+        buf.extend_from_slice(b"after");
+
+        let mut stream = Cursor::new(buf);
+        assert_eq!(
+            read_registration(&mut stream).unwrap(),
+            (Role::Slave, "session-1".to_string())
+        );
+        // bytes after the registration are left for the peer
+        assert_eq!(&stream.get_ref()[stream.position() as usize..], b"after");
+    }
+
+    #[test]
+    fn test_invalid_registration() {
+        let read = |s: &str| read_registration(&mut Cursor::new(s.as_bytes().to_vec()));
+
+        assert!(read("GET / HTTP/1.1\r\n").is_err());
+        assert!(read("RPTY-RELAY other key\n").is_err());
+        assert!(read("RPTY-RELAY master key extra\n").is_err());
+        assert!(read("RPTY-RELAY master key").is_err());
+        assert!(read(&format!("RPTY-RELAY master {}\n", "k".repeat(1024))).is_err());
+    }
+
+    #[test]
+    fn test_validate_key() {
+        assert!(validate_key("a1b2c3").is_ok());
+        assert!(validate_key("").is_err());
+        assert!(validate_key("a b").is_err());
+        assert!(register(&mut vec![], Role::Master, "").is_err());
+    }
+
+    #[test]
+    fn test_slave_pair_timeout() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = HostPort {
+            host: "127.0.0.1".to_string(),
+            port: listener.local_addr().unwrap().port(),
+        };
+        let options = TransportOptions {
+            connect_timeout: Some(Duration::from_millis(100)),
+            ..TransportOptions::default()
+        };
+
+        // the relay never pairs the connection
+        let relay_thread = thread::spawn(move || {
+            let (mut socket, _) = listener.accept().unwrap();
+            read_registration(&mut socket).unwrap();
+            socket
+        });
+
+        let err = connect(&addr, "key", Role::Slave, &options).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::TimedOut);
+        relay_thread.join().unwrap();
+    }
+}
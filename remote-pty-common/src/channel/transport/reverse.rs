@@ -0,0 +1,42 @@
+use std::io::{self, ErrorKind, Read, Write};
+
+// sent by a listening slave once it has accepted a connection from the
+// master, so the master only keeps one idle connection open at a time
+const READY: u8 = 0x01;
+
+pub fn notify_ready(stream: &mut impl Write) -> io::Result<()> {
+    stream.write_all(&[READY])?;
+    stream.flush()
+}
+
+// blocks until the slave has accepted the connection
+pub fn wait_ready(stream: &mut impl Read) -> io::Result<()> {
+    let mut buf = [0u8];
+    stream.read_exact(&mut buf)?;
+
+    if buf[0] != READY {
+        return Err(io::Error::new(
+            ErrorKind::InvalidData,
+            format!("unexpected ready byte {:#x}", buf[0]),
+        ));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::{notify_ready, wait_ready};
+
+    #[test]
+    fn test_ready() {
+        let mut buf = vec![];
+        notify_ready(&mut buf).unwrap();
+
+        assert!(wait_ready(&mut Cursor::new(buf)).is_ok());
+        assert!(wait_ready(&mut Cursor::new(vec![])).is_err());
+        assert!(wait_ready(&mut Cursor::new(b"HTTP".to_vec())).is_err());
+    }
+}
@@ -1,18 +1,29 @@
 use std::{
     io::{Read, Write},
+    sync::Arc,
 };
 
-use super::Transport;
+use super::{ShutdownHandle, Transport};
 
-#[derive(Debug)]
 pub struct ReadWriteTransport<R: Read + Send, W: Write + Send> {
     read: R,
     write: W,
+    shutdown: Option<ShutdownHandle>,
 }
 
 impl<R: Read + Send, W: Write + Send> ReadWriteTransport<R, W> {
     pub fn new(read: R, write: W) -> Self {
-        Self { read, write }
+        Self {
+            read,
+            write,
+            shutdown: None,
+        }
+    }
+
+    // sets the callback used to forcibly close the underlying reader/writer
+    pub fn with_shutdown(mut self, shutdown: impl Fn() + Send + Sync + 'static) -> Self {
+        self.shutdown = Some(Arc::new(shutdown));
+        self
     }
 }
 
@@ -20,4 +31,8 @@ impl<R: Read + Send + 'static, W: Write + Send + 'static> Transport for ReadWrit
     fn split(self) -> (Box<dyn Read + Send>, Box<dyn Write + Send>) {
         (Box::new(self.read), Box::new(self.write))
     }
+
+    fn shutdown_handle(&self) -> Option<ShutdownHandle> {
+        self.shutdown.clone()
+    }
 }
@@ -1,7 +1,58 @@
 
-use std::{net::TcpStream, io::{Read, Write}};
+use std::{
+    io::{self, ErrorKind, Read, Write},
+    net::{Shutdown, TcpStream, ToSocketAddrs},
+    sync::Arc,
+};
 
-use super::Transport;
+use crate::log::debug;
+
+use super::{
+    conf::{HostPort, TransportOptions},
+    ShutdownHandle, Transport,
+};
+
+// connects to the host through the configured proxy, if any
+pub fn connect(addr: &HostPort, options: &TransportOptions) -> io::Result<TcpStream> {
+    match options.proxy.resolve(&addr.host)? {
+        Some(proxy) => {
+            let socket = proxy.connect(addr, options)?;
+            socket.set_nodelay(options.nodelay)?;
+            Ok(socket)
+        }
+        None => connect_direct(addr, options),
+    }
+}
+
+// connects to each address the host resolves to until one succeeds
+pub fn connect_direct(addr: &HostPort, options: &TransportOptions) -> io::Result<TcpStream> {
+    let mut last_err = None;
+
+    for sock_addr in addr.to_socket_addrs()? {
+        let res = match options.connect_timeout {
+            Some(timeout) => TcpStream::connect_timeout(&sock_addr, timeout),
+            None => TcpStream::connect(sock_addr),
+        };
+
+        match res {
+            Ok(socket) => {
+                socket.set_nodelay(options.nodelay)?;
+                return Ok(socket);
+            }
+            Err(err) => {
+                debug(format!("failed to connect to {}: {}", sock_addr, err));
+                last_err = Some(err);
+            }
+        }
+    }
+
+    Err(last_err.unwrap_or_else(|| {
+        io::Error::new(
+            ErrorKind::NotFound,
+            format!("{} did not resolve to any addresses", addr),
+        )
+    }))
+}
 
 #[derive(Debug)]
 pub struct TcpTransport {
@@ -21,4 +72,61 @@ impl Transport for TcpTransport {
 
         (Box::new(reader), Box::new(writer))
     }
+
+    fn shutdown_handle(&self) -> Option<ShutdownHandle> {
+        let socket = self.socket.try_clone().ok()?;
+
+        Some(Arc::new(move || {
+            let _ = socket.shutdown(Shutdown::Both);
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+
+    use crate::channel::transport::conf::{HostPort, TransportOptions};
+
+    use super::connect;
+
+    #[test]
+    fn test_connect_resolves_host() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = HostPort {
+            host: "localhost".to_string(),
+            port: listener.local_addr().unwrap().port(),
+        };
+        let options = TransportOptions {
+            nodelay: true,
+            ..TransportOptions::default()
+        };
+
+        // localhost may resolve to ::1 first which is not listening
+        let socket = connect(&addr, &options).unwrap();
+
+        assert!(socket.nodelay().unwrap());
+        assert_eq!(socket.peer_addr().unwrap(), listener.local_addr().unwrap());
+    }
+
+    #[test]
+    fn test_connect_retries() {
+        let addr = HostPort {
+            host: "127.0.0.1".to_string(),
+            port: 1,
+        };
+        let options = TransportOptions {
+            retries: 2,
+            ..TransportOptions::default()
+        };
+
+        let mut attempts = 0;
+        let res = options.retry(|| {
+            attempts += 1;
+            connect(&addr, &options)
+        });
+
+        assert!(res.is_err());
+        assert_eq!(attempts, 3);
+    }
 }
@@ -0,0 +1,637 @@
+use std::{
+    env,
+    io::{self, Read, Write},
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+use ring::digest::{digest, SHA256};
+use rustls::{
+    client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+    crypto::{ring::default_provider, verify_tls12_signature, verify_tls13_signature},
+    server::WebPkiClientVerifier,
+    ClientConnection, Connection, DigitallySignedStruct, RootCertStore, ServerConnection,
+    SignatureScheme,
+};
+use rustls_pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer, ServerName, UnixTime};
+
+use super::{ShutdownHandle, Transport};
+
+pub use rustls::{ClientConfig, ServerConfig};
+
+// size of the buffer used to read encrypted data from the underlying stream
+const TLS_READ_BUFFER_SIZE: usize = 16 * 1024;
+
+// TLS settings used by the master to terminate connections
+#[derive(Debug, PartialEq, Clone)]
+pub struct TlsServerConf {
+    // path of the PEM encoded certificate chain presented to clients
+    pub cert: String,
+    // path of the PEM encoded private key of the certificate
+    pub key: String,
+    // path of the PEM encoded CA certificates used to authenticate
+    // clients, client certificates are not requested if none
+    pub client_ca: Option<String>,
+}
+
+// how the slave verifies the master's certificate
+#[derive(Debug, PartialEq, Clone)]
+pub enum TlsVerify {
+    // path of the PEM encoded CA certificates the master's certificate
+    // must chain to, the certificate must also match the host name
+    Ca(String),
+    // the SHA-256 digest of the master's certificate
+    Fingerprint([u8; 32]),
+}
+
+// TLS settings used by the slave to connect to the master
+#[derive(Debug, PartialEq, Clone)]
+pub struct TlsClientConf {
+    pub verify: TlsVerify,
+    // paths of the PEM encoded certificate chain and private key
+    // presented to the master, required when it authenticates clients
+    pub identity: Option<(String, String)>,
+}
+
+impl TlsServerConf {
+    // reads the TLS conf from the env, returns None if not configured
+    pub fn from_env() -> Result<Option<Self>, String> {
+        let cert = match env::var("RPTY_TLS_CERT") {
+            Ok(cert) => cert,
+            Err(_) => return Ok(None),
+        };
+
+        Ok(Some(Self {
+            cert,
+            key: env::var("RPTY_TLS_KEY").map_err(|_| "could not find env var RPTY_TLS_KEY")?,
+            client_ca: env::var("RPTY_TLS_CLIENT_CA").ok(),
+        }))
+    }
+
+    pub fn server_config(&self) -> Result<Arc<ServerConfig>, String> {
+        let builder = ServerConfig::builder();
+
+        let builder = match self.client_ca.as_ref() {
+            Some(path) => {
+                let verifier = WebPkiClientVerifier::builder(Arc::new(load_roots(path)?))
+                    .build()
+                    .map_err(|e| format!("failed to create client verifier: {}", e))?;
+
+                builder.with_client_cert_verifier(verifier)
+            }
+            None => builder.with_no_client_auth(),
+        };
+
+        let config = builder
+            .with_single_cert(load_certs(&self.cert)?, load_key(&self.key)?)
+            .map_err(|e| format!("invalid certificate or key: {}", e))?;
+
+        Ok(Arc::new(config))
+    }
+}
+
+impl TlsClientConf {
+    // reads the TLS conf from the env, returns None if not configured
+    pub fn from_env() -> Result<Option<Self>, String> {
+        let verify = match (env::var("RPTY_TLS_CA"), env::var("RPTY_TLS_FINGERPRINT")) {
+            (Ok(_), Ok(_)) => {
+                return Err("only one of RPTY_TLS_CA or RPTY_TLS_FINGERPRINT can be set".to_string())
+            }
+            (Ok(path), _) => TlsVerify::Ca(path),
+            (_, Ok(fingerprint)) => TlsVerify::Fingerprint(parse_fingerprint(&fingerprint)?),
+            _ => return Ok(None),
+        };
+
+        let identity = match (env::var("RPTY_TLS_CERT"), env::var("RPTY_TLS_KEY")) {
+            (Ok(cert), Ok(key)) => Some((cert, key)),
+            (Err(_), Err(_)) => None,
+            _ => return Err("RPTY_TLS_CERT and RPTY_TLS_KEY must be set together".to_string()),
+        };
+
+        Ok(Some(Self { verify, identity }))
+    }
+
+    pub fn client_config(&self) -> Result<Arc<ClientConfig>, String> {
+        let builder = ClientConfig::builder();
+
+        let builder = match &self.verify {
+            TlsVerify::Ca(path) => builder.with_root_certificates(load_roots(path)?),
+            TlsVerify::Fingerprint(fingerprint) => builder
+                .dangerous()
+                .with_custom_certificate_verifier(Arc::new(FingerprintVerifier {
+                    fingerprint: *fingerprint,
+                })),
+        };
+
+        let config = match &self.identity {
+            Some((cert, key)) => builder
+                .with_client_auth_cert(load_certs(cert)?, load_key(key)?)
+                .map_err(|e| format!("invalid client certificate or key: {}", e))?,
+            None => builder.with_no_client_auth(),
+        };
+
+        Ok(Arc::new(config))
+    }
+}
+
+// parses a hex encoded SHA-256 digest, optionally separated by colons
+pub fn parse_fingerprint(s: &str) -> Result<[u8; 32], String> {
+    let hex = s.replace(':', "");
+    let mut fingerprint = [0u8; 32];
+
+    if hex.len() != fingerprint.len() * 2 || !hex.is_ascii() {
+        return Err(format!("invalid SHA-256 fingerprint: {}", s));
+    }
+
+    for (i, byte) in fingerprint.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16)
+            .map_err(|_| format!("invalid SHA-256 fingerprint: {}", s))?;
+    }
+
+    Ok(fingerprint)
+}
+
+fn load_certs(path: &str) -> Result<Vec<CertificateDer<'static>>, String> {
+    let certs = CertificateDer::pem_file_iter(path)
+        .and_then(|i| i.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| format!("failed to load certificates from {}: {}", path, e))?;
+
+    if certs.is_empty() {
+        return Err(format!("no certificates found in {}", path));
+    }
+
+    Ok(certs)
+}
+
+fn load_key(path: &str) -> Result<PrivateKeyDer<'static>, String> {
+    PrivateKeyDer::from_pem_file(path)
+        .map_err(|e| format!("failed to load private key from {}: {}", path, e))
+}
+
+fn load_roots(path: &str) -> Result<RootCertStore, String> {
+    let mut roots = RootCertStore::empty();
+
+    for cert in load_certs(path)? {
+        roots
+            .add(cert)
+            .map_err(|e| format!("invalid CA certificate in {}: {}", path, e))?;
+    }
+
+    Ok(roots)
+}
+
+// accepts the server's certificate if it matches the pinned fingerprint,
+// the chain and host name are not checked as the certificate is trusted directly
+#[derive(Debug)]
+struct FingerprintVerifier {
+    fingerprint: [u8; 32],
+}
+
+impl ServerCertVerifier for FingerprintVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        if digest(&SHA256, end_entity.as_ref()).as_ref() != self.fingerprint {
+            return Err(rustls::Error::General(
+                "certificate does not match the pinned fingerprint".to_string(),
+            ));
+        }
+
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &default_provider().signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &default_provider().signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        default_provider()
+            .signature_verification_algorithms
+            .supported_schemes()
+    }
+}
+
+// transport encrypting the underlying read and write streams with TLS.
+// the handshake is completed before the transport is constructed
+pub struct TlsTransport<R: Read + Send, W: Write + Send> {
+    read: R,
+    session: Arc<TlsSession<W>>,
+    shutdown: Option<ShutdownHandle>,
+}
+
+// the TLS state shared by both halves of the transport
+struct TlsSession<W> {
+    conn: Mutex<Connection>,
+    write: Mutex<W>,
+}
+
+impl<R: Read + Send, W: Write + Send> TlsTransport<R, W> {
+    // performs the handshake as the client, the server's certificate
+    // is verified against the server name
+    pub fn connect(
+        read: R,
+        write: W,
+        config: Arc<ClientConfig>,
+        server_name: &str,
+    ) -> Result<Self, String> {
+        let server_name = ServerName::try_from(server_name.to_string())
+            .map_err(|e| format!("invalid server name {}: {}", server_name, e))?;
+        let conn = ClientConnection::new(config, server_name)
+            .map_err(|e| format!("failed to create tls connection: {}", e))?;
+
+        Self::handshake(read, write, conn.into())
+    }
+
+    // performs the handshake as the server
+    pub fn accept(read: R, write: W, config: Arc<ServerConfig>) -> Result<Self, String> {
+        let conn = ServerConnection::new(config)
+            .map_err(|e| format!("failed to create tls connection: {}", e))?;
+
+        Self::handshake(read, write, conn.into())
+    }
+
+    // sets the callback used to forcibly close the underlying streams
+    pub fn with_shutdown(mut self, shutdown: impl Fn() + Send + Sync + 'static) -> Self {
+        self.shutdown = Some(Arc::new(shutdown));
+        self
+    }
+
+    fn handshake(mut read: R, mut write: W, mut conn: Connection) -> Result<Self, String> {
+        while conn.is_handshaking() {
+            while conn.wants_write() {
+                conn.write_tls(&mut write)
+                    .map_err(|e| format!("failed to send tls handshake: {}", e))?;
+            }
+            write
+                .flush()
+                .map_err(|e| format!("failed to send tls handshake: {}", e))?;
+
+            if !conn.is_handshaking() {
+                break;
+            }
+
+            let n = conn
+                .read_tls(&mut read)
+                .map_err(|e| format!("failed to receive tls handshake: {}", e))?;
+
+            if n == 0 {
+                return Err("connection closed during tls handshake".to_string());
+            }
+
+            if let Err(err) = conn.process_new_packets() {
+                // attempt to notify the remote of the failure
+                let _ = conn.write_tls(&mut write);
+                return Err(format!("tls handshake failed: {}", err));
+            }
+        }
+
+        let session = Arc::new(TlsSession {
+            conn: Mutex::new(conn),
+            write: Mutex::new(write),
+        });
+        // send any remaining handshake messages
+        session
+            .send_pending(session.conn.lock().unwrap())
+            .map_err(|e| format!("failed to send tls handshake: {}", e))?;
+
+        Ok(Self {
+            read,
+            session,
+            shutdown: None,
+        })
+    }
+}
+
+impl<W: Write> TlsSession<W> {
+    // writes the encrypted data queued by the connection to the stream.
+    // the connection is only locked while encrypting so a blocked
+    // write does not prevent data from being received
+    fn send_pending(&self, mut conn: MutexGuard<Connection>) -> io::Result<()> {
+        let mut data = vec![];
+        while conn.wants_write() {
+            conn.write_tls(&mut data)?;
+        }
+
+        // the stream is locked before the connection is released
+        // so records are written in the order they were encrypted
+        let mut write = self.write.lock().unwrap();
+        drop(conn);
+
+        write.write_all(data.as_slice())?;
+        write.flush()
+    }
+}
+
+impl<R, W> Transport for TlsTransport<R, W>
+where
+    R: Read + Send + 'static,
+    W: Write + Send + 'static,
+{
+    fn split(self) -> (Box<dyn Read + Send>, Box<dyn Write + Send>) {
+        let reader = TlsReader {
+            read: self.read,
+            session: Arc::clone(&self.session),
+            buf: vec![0u8; TLS_READ_BUFFER_SIZE],
+            pos: 0,
+            len: 0,
+        };
+        let writer = TlsWriter {
+            session: self.session,
+        };
+
+        (Box::new(reader), Box::new(writer))
+    }
+
+    fn shutdown_handle(&self) -> Option<ShutdownHandle> {
+        self.shutdown.clone()
+    }
+}
+
+struct TlsReader<R, W> {
+    read: R,
+    session: Arc<TlsSession<W>>,
+    // encrypted data read from the stream which
+    // has not yet been passed to the connection
+    buf: Vec<u8>,
+    pos: usize,
+    len: usize,
+}
+
+impl<R: Read, W: Write> Read for TlsReader<R, W> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let mut conn = self.session.conn.lock().unwrap();
+
+            match conn.reader().read(buf) {
+                Ok(n) => return Ok(n),
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => {}
+                Err(err) => return Err(err),
+            }
+
+            if self.pos < self.len {
+                self.pos += conn.read_tls(&mut &self.buf[self.pos..self.len])?;
+                let res = conn.process_new_packets();
+
+                // alerts or replies to the received messages are sent to the remote
+                self.session.send_pending(conn)?;
+                res.map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+                continue;
+            }
+
+            // the connection is not locked while waiting for
+            // data so writes can continue
+            drop(conn);
+            self.pos = 0;
+            self.len = self.read.read(&mut self.buf)?;
+
+            if self.len == 0 {
+                let mut conn = self.session.conn.lock().unwrap();
+                conn.read_tls(&mut io::empty())?;
+                conn.process_new_packets()
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            }
+        }
+    }
+}
+
+struct TlsWriter<W> {
+    session: Arc<TlsSession<W>>,
+}
+
+impl<W: Write> Write for TlsWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut conn = self.session.conn.lock().unwrap();
+        let n = conn.writer().write(buf)?;
+        self.session.send_pending(conn)?;
+
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let conn = self.session.conn.lock().unwrap();
+        self.session.send_pending(conn)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        env, fs,
+        net::{TcpListener, TcpStream},
+        path::PathBuf,
+        process, thread,
+    };
+
+    use rcgen::{BasicConstraints, CertificateParams, IsCa, KeyPair};
+    use ring::digest::{digest, SHA256};
+
+    use crate::{
+        channel::{Channel, RemoteChannel},
+        proto::master::{PtyMasterCall, PtyMasterResponse, WriteStdinCall},
+    };
+
+    use super::{parse_fingerprint, TlsClientConf, TlsServerConf, TlsTransport, TlsVerify};
+
+    // a CA with server and client certificates signed by it
+    struct Pki {
+        dir: PathBuf,
+        server_fingerprint: [u8; 32],
+    }
+
+    impl Pki {
+        fn generate(name: &str) -> Self {
+            let dir = env::temp_dir().join(format!("rpty-tls-{}-{}", name, process::id()));
+            fs::create_dir_all(&dir).unwrap();
+
+            let ca_key = KeyPair::generate().unwrap();
+            let mut ca_params = CertificateParams::new(vec![]).unwrap();
+            ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+            let ca = ca_params.self_signed(&ca_key).unwrap();
+            fs::write(dir.join("ca.pem"), ca.pem()).unwrap();
+
+            let mut server_fingerprint = [0u8; 32];
+
+            for name in ["server", "client"] {
+                let key = KeyPair::generate().unwrap();
+                let cert = CertificateParams::new(vec!["localhost".to_string()])
+                    .unwrap()
+                    .signed_by(&key, &ca, &ca_key)
+                    .unwrap();
+
+                if name == "server" {
+                    server_fingerprint.copy_from_slice(digest(&SHA256, cert.der()).as_ref());
+                }
+
+                fs::write(dir.join(format!("{}.pem", name)), cert.pem()).unwrap();
+                fs::write(dir.join(format!("{}.key", name)), key.serialize_pem()).unwrap();
+            }
+
+            Self {
+                dir,
+                server_fingerprint,
+            }
+        }
+
+        fn path(&self, name: &str) -> String {
+            self.dir.join(name).to_str().unwrap().to_string()
+        }
+
+        fn server_conf(&self, client_auth: bool) -> TlsServerConf {
+            TlsServerConf {
+                cert: self.path("server.pem"),
+                key: self.path("server.key"),
+                client_ca: client_auth.then(|| self.path("ca.pem")),
+            }
+        }
+
+        fn client_identity(&self) -> Option<(String, String)> {
+            Some((self.path("client.pem"), self.path("client.key")))
+        }
+    }
+
+    impl Drop for Pki {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.dir);
+        }
+    }
+
+    // connects a client to a server over loopback and sends a single call
+    fn call(server: TlsServerConf, client: TlsClientConf) -> Result<PtyMasterResponse, String> {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let server_thread = thread::spawn(move || -> Result<(), String> {
+            let (socket, _) = listener.accept().unwrap();
+            let transport =
+                TlsTransport::accept(socket.try_clone().unwrap(), socket, server.server_config()?)?;
+
+            RemoteChannel::new(transport)
+                .receive::<PtyMasterCall, PtyMasterResponse, _>(Channel::STDIN, |_| {
+                    PtyMasterResponse::Success(1)
+                })
+        });
+
+        let res = (|| {
+            let socket = TcpStream::connect(addr).unwrap();
+            let transport = TlsTransport::connect(
+                socket.try_clone().unwrap(),
+                socket,
+                client.client_config()?,
+                "localhost",
+            )?;
+
+            RemoteChannel::new(transport).send::<PtyMasterCall, PtyMasterResponse>(
+                Channel::STDIN,
+                PtyMasterCall::WriteStdin(WriteStdinCall {
+                    data: b"secret".repeat(10000),
+                }),
+            )
+        })();
+
+        let server_res = server_thread.join().unwrap();
+        res.and(server_res.map(|_| PtyMasterResponse::Success(1)))
+    }
+
+    #[test]
+    fn test_tls_with_pinned_ca() {
+        let pki = Pki::generate("ca");
+
+        let res = call(
+            pki.server_conf(false),
+            TlsClientConf {
+                verify: TlsVerify::Ca(pki.path("ca.pem")),
+                identity: None,
+            },
+        );
+
+        assert_eq!(res, Ok(PtyMasterResponse::Success(1)));
+    }
+
+    #[test]
+    fn test_tls_with_pinned_fingerprint() {
+        let pki = Pki::generate("fingerprint");
+
+        let res = call(
+            pki.server_conf(false),
+            TlsClientConf {
+                verify: TlsVerify::Fingerprint(pki.server_fingerprint),
+                identity: None,
+            },
+        );
+        assert_eq!(res, Ok(PtyMasterResponse::Success(1)));
+
+        let res = call(
+            pki.server_conf(false),
+            TlsClientConf {
+                verify: TlsVerify::Fingerprint([0; 32]),
+                identity: None,
+            },
+        );
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn test_tls_with_client_auth() {
+        let pki = Pki::generate("mtls");
+
+        let res = call(
+            pki.server_conf(true),
+            TlsClientConf {
+                verify: TlsVerify::Ca(pki.path("ca.pem")),
+                identity: pki.client_identity(),
+            },
+        );
+        assert_eq!(res, Ok(PtyMasterResponse::Success(1)));
+
+        // clients without a certificate are rejected
+        let res = call(
+            pki.server_conf(true),
+            TlsClientConf {
+                verify: TlsVerify::Ca(pki.path("ca.pem")),
+                identity: None,
+            },
+        );
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn test_parse_fingerprint() {
+        let hex = "00:11:22:33:44:55:66:77:88:99:aa:bb:cc:dd:ee:ff:00:11:22:33:44:55:66:77:88:99:AA:BB:CC:DD:EE:FF";
+
+        let fingerprint = parse_fingerprint(hex).unwrap();
+        assert_eq!(fingerprint[..4], [0x00, 0x11, 0x22, 0x33]);
+        assert_eq!(fingerprint[31], 0xff);
+        assert_eq!(parse_fingerprint(&hex.replace(':', "")), Ok(fingerprint));
+
+        assert!(parse_fingerprint("0011").is_err());
+        assert!(parse_fingerprint(&"zz".repeat(32)).is_err());
+    }
+}
@@ -1,6 +1,49 @@
-use std::{os::unix::net::UnixStream, io::{Read, Write}};
+use std::{
+    io::{self, Read, Write},
+    mem,
+    net::Shutdown,
+    os::{
+        linux::net::SocketAddrExt,
+        unix::{
+            net::{SocketAddr, UnixStream},
+            prelude::AsRawFd,
+        },
+    },
+    sync::Arc,
+};
 
-use super::Transport;
+use super::{ShutdownHandle, Transport};
+
+// resolves the address of a unix socket, paths prefixed with @ are in
+// the linux abstract namespace so no file is created for the socket
+pub fn socket_addr(path: &str) -> io::Result<SocketAddr> {
+    match path.strip_prefix('@') {
+        Some(name) => SocketAddr::from_abstract_name(name),
+        None => SocketAddr::from_pathname(path),
+    }
+}
+
+// the credentials of the process which connected the socket
+pub fn peer_cred(socket: &UnixStream) -> io::Result<libc::ucred> {
+    let mut cred: libc::ucred = unsafe { mem::zeroed() };
+    let mut len = mem::size_of::<libc::ucred>() as libc::socklen_t;
+
+    let ret = unsafe {
+        libc::getsockopt(
+            socket.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_PEERCRED,
+            &mut cred as *mut libc::ucred as *mut libc::c_void,
+            &mut len,
+        )
+    };
+
+    if ret != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(cred)
+}
 
 #[derive(Debug)]
 pub struct UnixSocketTransport {
@@ -20,4 +63,12 @@ impl Transport for UnixSocketTransport {
 
         (Box::new(reader), Box::new(writer))
     }
+
+    fn shutdown_handle(&self) -> Option<ShutdownHandle> {
+        let socket = self.socket.try_clone().ok()?;
+
+        Some(Arc::new(move || {
+            let _ = socket.shutdown(Shutdown::Both);
+        }))
+    }
 }
@@ -0,0 +1,323 @@
+use std::{
+    io::{self, Read, Write},
+    mem,
+    net::Shutdown,
+    os::unix::prelude::{AsRawFd, FromRawFd, IntoRawFd, OwnedFd, RawFd},
+    sync::Arc,
+};
+
+use super::{ShutdownHandle, Transport};
+
+// the cid used by listeners to accept connections from any guest
+pub const VMADDR_CID_ANY: u32 = libc::VMADDR_CID_ANY;
+
+// a connected AF_VSOCK stream socket, used to reach the host
+// from microvm guests without a network stack
+#[derive(Debug)]
+pub struct VsockStream {
+    fd: OwnedFd,
+}
+
+impl VsockStream {
+    pub fn connect(cid: u32, port: u32) -> io::Result<Self> {
+        let fd = vsock_socket()?;
+        let addr = sockaddr(cid, port);
+
+        let res = unsafe {
+            libc::connect(
+                fd.as_raw_fd(),
+                &addr as *const libc::sockaddr_vm as *const libc::sockaddr,
+                mem::size_of::<libc::sockaddr_vm>() as libc::socklen_t,
+            )
+        };
+
+        if res != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(Self { fd })
+    }
+
+    pub fn try_clone(&self) -> io::Result<Self> {
+        Ok(Self {
+            fd: self.fd.try_clone()?,
+        })
+    }
+
+    pub fn shutdown(&self, how: Shutdown) -> io::Result<()> {
+        let how = match how {
+            Shutdown::Read => libc::SHUT_RD,
+            Shutdown::Write => libc::SHUT_WR,
+            Shutdown::Both => libc::SHUT_RDWR,
+        };
+
+        if unsafe { libc::shutdown(self.fd.as_raw_fd(), how) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(())
+    }
+}
+
+impl Read for VsockStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let res = unsafe { libc::read(self.fd.as_raw_fd(), buf.as_mut_ptr() as *mut _, buf.len()) };
+
+        if res < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(res as usize)
+    }
+}
+
+impl Write for VsockStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // avoid SIGPIPE if the remote has closed the connection
+        let res = unsafe {
+            libc::send(
+                self.fd.as_raw_fd(),
+                buf.as_ptr() as *const _,
+                buf.len(),
+                libc::MSG_NOSIGNAL,
+            )
+        };
+
+        if res < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(res as usize)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl AsRawFd for VsockStream {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd.as_raw_fd()
+    }
+}
+
+impl FromRawFd for VsockStream {
+    unsafe fn from_raw_fd(fd: RawFd) -> Self {
+        Self {
+            fd: OwnedFd::from_raw_fd(fd),
+        }
+    }
+}
+
+impl IntoRawFd for VsockStream {
+    fn into_raw_fd(self) -> RawFd {
+        self.fd.into_raw_fd()
+    }
+}
+
+#[derive(Debug)]
+pub struct VsockListener {
+    fd: OwnedFd,
+}
+
+impl VsockListener {
+    pub fn bind(cid: u32, port: u32, backlog: i32) -> io::Result<Self> {
+        let fd = vsock_socket()?;
+        let addr = sockaddr(cid, port);
+
+        let res = unsafe {
+            libc::bind(
+                fd.as_raw_fd(),
+                &addr as *const libc::sockaddr_vm as *const libc::sockaddr,
+                mem::size_of::<libc::sockaddr_vm>() as libc::socklen_t,
+            )
+        };
+
+        if res != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        if unsafe { libc::listen(fd.as_raw_fd(), backlog) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(Self { fd })
+    }
+
+    // returns the stream and the cid of the connecting guest
+    pub fn accept(&self) -> io::Result<(VsockStream, u32)> {
+        let mut addr: libc::sockaddr_vm = unsafe { mem::zeroed() };
+        let mut len = mem::size_of::<libc::sockaddr_vm>() as libc::socklen_t;
+
+        let fd = unsafe {
+            libc::accept4(
+                self.fd.as_raw_fd(),
+                &mut addr as *mut libc::sockaddr_vm as *mut libc::sockaddr,
+                &mut len,
+                libc::SOCK_CLOEXEC,
+            )
+        };
+
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok((unsafe { VsockStream::from_raw_fd(fd) }, addr.svm_cid))
+    }
+
+    // the bound port, useful when binding to any port
+    pub fn local_port(&self) -> io::Result<u32> {
+        let mut addr: libc::sockaddr_vm = unsafe { mem::zeroed() };
+        let mut len = mem::size_of::<libc::sockaddr_vm>() as libc::socklen_t;
+
+        let res = unsafe {
+            libc::getsockname(
+                self.fd.as_raw_fd(),
+                &mut addr as *mut libc::sockaddr_vm as *mut libc::sockaddr,
+                &mut len,
+            )
+        };
+
+        if res != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(addr.svm_port)
+    }
+}
+
+impl AsRawFd for VsockListener {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd.as_raw_fd()
+    }
+}
+
+impl IntoRawFd for VsockListener {
+    fn into_raw_fd(self) -> RawFd {
+        self.fd.into_raw_fd()
+    }
+}
+
+impl FromRawFd for VsockListener {
+    unsafe fn from_raw_fd(fd: RawFd) -> Self {
+        Self {
+            fd: OwnedFd::from_raw_fd(fd),
+        }
+    }
+}
+
+fn vsock_socket() -> io::Result<OwnedFd> {
+    let fd = unsafe { libc::socket(libc::AF_VSOCK, libc::SOCK_STREAM | libc::SOCK_CLOEXEC, 0) };
+
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(unsafe { OwnedFd::from_raw_fd(fd) })
+}
+
+fn sockaddr(cid: u32, port: u32) -> libc::sockaddr_vm {
+    let mut addr: libc::sockaddr_vm = unsafe { mem::zeroed() };
+    addr.svm_family = libc::AF_VSOCK as libc::sa_family_t;
+    addr.svm_cid = cid;
+    addr.svm_port = port;
+    addr
+}
+
+#[derive(Debug)]
+pub struct VsockTransport {
+    socket: VsockStream,
+}
+
+impl VsockTransport {
+    pub fn new(socket: VsockStream) -> Self {
+        Self { socket }
+    }
+}
+
+impl Transport for VsockTransport {
+    fn split(self) -> (Box<dyn Read + Send>, Box<dyn Write + Send>) {
+        let reader = self.socket.try_clone().unwrap();
+        let writer = self.socket;
+
+        (Box::new(reader), Box::new(writer))
+    }
+
+    fn shutdown_handle(&self) -> Option<ShutdownHandle> {
+        let socket = self.socket.try_clone().ok()?;
+
+        Some(Arc::new(move || {
+            let _ = socket.shutdown(Shutdown::Both);
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{Read, Write},
+        os::unix::{
+            net::UnixStream,
+            prelude::{FromRawFd, IntoRawFd},
+        },
+        thread,
+    };
+
+    use crate::channel::transport::Transport;
+
+    use super::{VsockListener, VsockStream, VsockTransport, VMADDR_CID_ANY};
+
+    // vsock loopback is not available in all environments so a
+    // socketpair stands in for the connected vsock streams
+    fn stream_pair() -> (VsockStream, VsockStream) {
+        let (a, b) = UnixStream::pair().unwrap();
+
+        unsafe {
+            (
+                VsockStream::from_raw_fd(a.into_raw_fd()),
+                VsockStream::from_raw_fd(b.into_raw_fd()),
+            )
+        }
+    }
+
+    #[test]
+    fn test_vsock_transport() {
+        let (a, b) = stream_pair();
+        let (mut read1, mut write1) = VsockTransport::new(a).split();
+        let (mut read2, mut write2) = VsockTransport::new(b).split();
+
+        write1.write_all(b"ping").unwrap();
+        let mut buf = [0u8; 4];
+        read2.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"ping");
+
+        write2.write_all(b"pong").unwrap();
+        read1.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"pong");
+    }
+
+    #[test]
+    fn test_vsock_shutdown_unblocks_reader() {
+        let (a, _b) = stream_pair();
+        let transport = VsockTransport::new(a);
+        let shutdown = transport.shutdown_handle().unwrap();
+        let (mut reader, _writer) = transport.split();
+
+        let read_thread = thread::spawn(move || reader.read(&mut [0u8; 1]).unwrap());
+        shutdown();
+
+        assert_eq!(read_thread.join().unwrap(), 0);
+    }
+
+    #[test]
+    fn test_vsock_listener_bind() {
+        let listener = match VsockListener::bind(VMADDR_CID_ANY, libc::VMADDR_PORT_ANY, 1) {
+            Ok(l) => l,
+            // the kernel may not support vsock
+            Err(err) if err.raw_os_error() == Some(libc::EAFNOSUPPORT) => return,
+            Err(err) => panic!("failed to bind: {}", err),
+        };
+
+        assert_ne!(listener.local_port().unwrap(), libc::VMADDR_PORT_ANY);
+    }
+}
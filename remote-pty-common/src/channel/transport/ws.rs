@@ -0,0 +1,573 @@
+use std::{
+    io::{self, BufRead, BufReader, Read, Write},
+    sync::{Arc, Mutex},
+};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use ring::digest::{digest, SHA1_FOR_LEGACY_USE_ONLY};
+
+use super::{conf::HostPort, ShutdownHandle, Transport};
+
+// appended to the client's key to derive the accept key (RFC 6455)
+const WS_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+// max size of the http request or response of the upgrade
+const MAX_HEAD_SIZE: u64 = 8192;
+
+const OP_CONTINUATION: u8 = 0x0;
+const OP_TEXT: u8 = 0x1;
+const OP_BINARY: u8 = 0x2;
+const OP_CLOSE: u8 = 0x8;
+const OP_PING: u8 = 0x9;
+const OP_PONG: u8 = 0xA;
+
+// transport carrying the channel over websocket binary messages, so it can
+// pass through http proxies and load balancers. each flush of the writer is
+// sent as a single message. the upgrade is completed before the transport
+// is constructed
+pub struct WsTransport<R: Read + Send, W: Write + Send> {
+    read: BufReader<R>,
+    sink: Arc<Mutex<WsSink<W>>>,
+    shutdown: Option<ShutdownHandle>,
+}
+
+impl<R: Read + Send, W: Write + Send> WsTransport<R, W> {
+    // performs the upgrade as the client, requesting the path on the host
+    pub fn connect(read: R, mut write: W, host: &HostPort, path: &str) -> Result<Self, String> {
+        let mut read = BufReader::new(read);
+        let key = STANDARD.encode(rand::random::<[u8; 16]>());
+
+        let req = format!(
+            "GET {} HTTP/1.1\r\n\
+            Host: {}\r\n\
+            Upgrade: websocket\r\n\
+            Connection: Upgrade\r\n\
+            Sec-WebSocket-Key: {}\r\n\
+            Sec-WebSocket-Version: 13\r\n\r\n",
+            path, host, key
+        );
+        write
+            .write_all(req.as_bytes())
+            .and_then(|_| write.flush())
+            .map_err(|e| format!("failed to send websocket upgrade: {}", e))?;
+
+        let (status, headers) = read_head(&mut read)?;
+
+        if status.split_whitespace().nth(1) != Some("101") {
+            return Err(format!("websocket upgrade failed: {}", status));
+        }
+
+        if !header_contains(&headers, "upgrade", "websocket") {
+            return Err("websocket upgrade failed: missing upgrade header".to_string());
+        }
+
+        if header(&headers, "sec-websocket-accept") != Some(accept_key(&key).as_str()) {
+            return Err("websocket upgrade failed: invalid accept key".to_string());
+        }
+
+        Ok(Self::new(read, write, true))
+    }
+
+    // performs the upgrade as the server, requests for other paths are rejected
+    pub fn accept(read: R, mut write: W, path: &str) -> Result<Self, String> {
+        let mut read = BufReader::new(read);
+
+        let (request, headers) = read_head(&mut read)?;
+        let mut parts = request.split_whitespace();
+        let method = parts.next().unwrap_or_default();
+        let target = parts.next().unwrap_or_default();
+        let target = target.split_once('?').map_or(target, |(p, _)| p);
+
+        if target != path {
+            return reject(
+                &mut write,
+                "404 Not Found",
+                format!("unknown path {}", target),
+            );
+        }
+
+        if method != "GET" {
+            return reject(&mut write, "405 Method Not Allowed", method.to_string());
+        }
+
+        if !header_contains(&headers, "upgrade", "websocket")
+            || header(&headers, "sec-websocket-version") != Some("13")
+        {
+            return reject(
+                &mut write,
+                "426 Upgrade Required",
+                "not a websocket upgrade",
+            );
+        }
+
+        let key = match header(&headers, "sec-websocket-key") {
+            Some(key) => key,
+            None => return reject(&mut write, "400 Bad Request", "missing websocket key"),
+        };
+
+        let res = format!(
+            "HTTP/1.1 101 Switching Protocols\r\n\
+            Upgrade: websocket\r\n\
+            Connection: Upgrade\r\n\
+            Sec-WebSocket-Accept: {}\r\n\r\n",
+            accept_key(key)
+        );
+        write
+            .write_all(res.as_bytes())
+            .and_then(|_| write.flush())
+            .map_err(|e| format!("failed to send websocket upgrade: {}", e))?;
+
+        Ok(Self::new(read, write, false))
+    }
+
+    // sets the callback used to forcibly close the underlying streams
+    pub fn with_shutdown(mut self, shutdown: impl Fn() + Send + Sync + 'static) -> Self {
+        self.shutdown = Some(Arc::new(shutdown));
+        self
+    }
+
+    fn new(read: BufReader<R>, write: W, client: bool) -> Self {
+        Self {
+            read,
+            sink: Arc::new(Mutex::new(WsSink { write, client })),
+            shutdown: None,
+        }
+    }
+}
+
+// the http request or status line and the headers with lowercase names
+type Head = (String, Vec<(String, String)>);
+
+fn read_head(read: &mut impl BufRead) -> Result<Head, String> {
+    let mut remaining = MAX_HEAD_SIZE;
+    let mut lines = vec![];
+
+    loop {
+        let mut line = vec![];
+        let n = read
+            .take(remaining)
+            .read_until(b'\n', &mut line)
+            .map_err(|e| format!("failed to receive websocket upgrade: {}", e))?;
+
+        if n == 0 || !line.ends_with(b"\n") {
+            return Err("connection closed during websocket upgrade".to_string());
+        }
+
+        remaining -= n as u64;
+        let line = String::from_utf8_lossy(&line).trim_end().to_string();
+
+        if line.is_empty() {
+            break;
+        }
+
+        lines.push(line);
+    }
+
+    if lines.is_empty() {
+        return Err("empty websocket upgrade".to_string());
+    }
+
+    let first = lines.remove(0);
+    let headers = lines
+        .into_iter()
+        .filter_map(|l| {
+            l.split_once(':')
+                .map(|(k, v)| (k.trim().to_ascii_lowercase(), v.trim().to_string()))
+        })
+        .collect();
+
+    Ok((first, headers))
+}
+
+fn header<'a>(headers: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    headers
+        .iter()
+        .find(|(k, _)| k == name)
+        .map(|(_, v)| v.as_str())
+}
+
+// checks the comma separated values of the header for the token
+fn header_contains(headers: &[(String, String)], name: &str, token: &str) -> bool {
+    headers
+        .iter()
+        .filter(|(k, _)| k == name)
+        .flat_map(|(_, v)| v.split(','))
+        .any(|v| v.trim().eq_ignore_ascii_case(token))
+}
+
+fn accept_key(key: &str) -> String {
+    STANDARD.encode(digest(
+        &SHA1_FOR_LEGACY_USE_ONLY,
+        format!("{}{}", key, WS_GUID).as_bytes(),
+    ))
+}
+
+fn reject<T>(write: &mut impl Write, status: &str, reason: impl Into<String>) -> Result<T, String> {
+    let reason = reason.into();
+    let res = format!(
+        "HTTP/1.1 {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+        status
+    );
+    let _ = write.write_all(res.as_bytes()).and_then(|_| write.flush());
+
+    Err(format!("rejected websocket upgrade: {}", reason))
+}
+
+// the write half shared with the reader so pings can be answered
+struct WsSink<W> {
+    write: W,
+    // frames sent by clients must be masked
+    client: bool,
+}
+
+impl<W: Write> WsSink<W> {
+    fn send(&mut self, opcode: u8, payload: &[u8]) -> io::Result<()> {
+        let mut frame = Vec::with_capacity(payload.len() + 14);
+        frame.push(0x80 | opcode);
+
+        let mask_bit = if self.client { 0x80 } else { 0 };
+        match payload.len() {
+            len if len < 126 => frame.push(mask_bit | len as u8),
+            len if len <= u16::MAX as usize => {
+                frame.push(mask_bit | 126);
+                frame.extend_from_slice(&(len as u16).to_be_bytes());
+            }
+            len => {
+                frame.push(mask_bit | 127);
+                frame.extend_from_slice(&(len as u64).to_be_bytes());
+            }
+        }
+
+        if self.client {
+            let mask: [u8; 4] = rand::random();
+            frame.extend_from_slice(&mask);
+            frame.extend(payload.iter().enumerate().map(|(i, b)| b ^ mask[i % 4]));
+        } else {
+            frame.extend_from_slice(payload);
+        }
+
+        self.write.write_all(frame.as_slice())?;
+        self.write.flush()
+    }
+}
+
+impl<R, W> Transport for WsTransport<R, W>
+where
+    R: Read + Send + 'static,
+    W: Write + Send + 'static,
+{
+    fn split(self) -> (Box<dyn Read + Send>, Box<dyn Write + Send>) {
+        let reader = WsReader {
+            read: self.read,
+            sink: Arc::clone(&self.sink),
+            remaining: 0,
+            mask: None,
+            offset: 0,
+            closed: false,
+        };
+        let writer = WsWriter {
+            sink: self.sink,
+            buf: vec![],
+        };
+
+        (Box::new(reader), Box::new(writer))
+    }
+
+    fn shutdown_handle(&self) -> Option<ShutdownHandle> {
+        self.shutdown.clone()
+    }
+}
+
+struct FrameHeader {
+    opcode: u8,
+    len: u64,
+    mask: Option<[u8; 4]>,
+}
+
+// reads the payloads of binary messages as a stream,
+// answering pings and close frames from the remote
+struct WsReader<R, W> {
+    read: BufReader<R>,
+    sink: Arc<Mutex<WsSink<W>>>,
+    // the unread payload of the current data frame
+    remaining: u64,
+    mask: Option<[u8; 4]>,
+    offset: usize,
+    closed: bool,
+}
+
+impl<R: Read, W: Write> WsReader<R, W> {
+    // reads the next frame header, returning None if the stream has ended
+    fn read_header(&mut self) -> io::Result<Option<FrameHeader>> {
+        let mut head = [0u8; 2];
+
+        if self.read.read(&mut head[..1])? == 0 {
+            return Ok(None);
+        }
+        self.read.read_exact(&mut head[1..])?;
+
+        let opcode = head[0] & 0x0F;
+        let len = match head[1] & 0x7F {
+            126 => {
+                let mut len = [0u8; 2];
+                self.read.read_exact(&mut len)?;
+                u16::from_be_bytes(len) as u64
+            }
+            127 => {
+                let mut len = [0u8; 8];
+                self.read.read_exact(&mut len)?;
+                u64::from_be_bytes(len)
+            }
+            len => len as u64,
+        };
+
+        let mask = if head[1] & 0x80 != 0 {
+            let mut mask = [0u8; 4];
+            self.read.read_exact(&mut mask)?;
+            Some(mask)
+        } else {
+            None
+        };
+
+        Ok(Some(FrameHeader { opcode, len, mask }))
+    }
+
+    fn read_control_payload(&mut self, len: u64, mask: Option<[u8; 4]>) -> io::Result<Vec<u8>> {
+        if len > 125 {
+            return Err(invalid_data("websocket control frame too large"));
+        }
+
+        let mut payload = vec![0u8; len as usize];
+        self.read.read_exact(&mut payload)?;
+        unmask(&mut payload, mask, 0);
+
+        Ok(payload)
+    }
+}
+
+impl<R: Read, W: Write> Read for WsReader<R, W> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            if self.closed || buf.is_empty() {
+                return Ok(0);
+            }
+
+            if self.remaining > 0 {
+                let len = buf.len().min(self.remaining as usize);
+                let n = self.read.read(&mut buf[..len])?;
+
+                if n == 0 {
+                    return Err(io::ErrorKind::UnexpectedEof.into());
+                }
+
+                unmask(&mut buf[..n], self.mask, self.offset);
+                self.remaining -= n as u64;
+                self.offset += n;
+                return Ok(n);
+            }
+
+            let FrameHeader { opcode, len, mask } = match self.read_header()? {
+                Some(header) => header,
+                None => return Ok(0),
+            };
+
+            match opcode {
+                OP_BINARY | OP_CONTINUATION => {
+                    self.remaining = len;
+                    self.mask = mask;
+                    self.offset = 0;
+                }
+                OP_CLOSE => {
+                    let payload = self.read_control_payload(len, mask)?;
+                    // echo the status code to complete the closing handshake
+                    let status = payload.get(..2).unwrap_or_default();
+                    let _ = self.sink.lock().unwrap().send(OP_CLOSE, status);
+                    self.closed = true;
+                }
+                OP_PING => {
+                    let payload = self.read_control_payload(len, mask)?;
+                    self.sink.lock().unwrap().send(OP_PONG, &payload)?;
+                }
+                OP_PONG => {
+                    self.read_control_payload(len, mask)?;
+                }
+                OP_TEXT => return Err(invalid_data("unexpected websocket text message")),
+                _ => return Err(invalid_data("unknown websocket opcode")),
+            }
+        }
+    }
+}
+
+fn unmask(data: &mut [u8], mask: Option<[u8; 4]>, offset: usize) {
+    if let Some(mask) = mask {
+        for (i, b) in data.iter_mut().enumerate() {
+            *b ^= mask[(offset + i) % 4];
+        }
+    }
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+// buffers writes until flushed so each channel frame is sent as one message
+struct WsWriter<W> {
+    sink: Arc<Mutex<WsSink<W>>>,
+    buf: Vec<u8>,
+}
+
+impl<W: Write> Write for WsWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buf.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.buf.is_empty() {
+            return Ok(());
+        }
+
+        let res = self.sink.lock().unwrap().send(OP_BINARY, &self.buf);
+        self.buf.clear();
+        res
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{Read, Write},
+        net::{TcpListener, TcpStream},
+        thread,
+    };
+
+    use crate::{
+        channel::{transport::conf::HostPort, Channel, RemoteChannel},
+        proto::master::{PtyMasterCall, PtyMasterResponse, WriteStdinCall},
+    };
+
+    use super::{accept_key, Transport, WsTransport};
+
+    fn connect(
+        listener: &TcpListener,
+        path: &str,
+    ) -> Result<WsTransport<TcpStream, TcpStream>, String> {
+        let socket = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let host = HostPort {
+            host: "localhost".to_string(),
+            port: listener.local_addr().unwrap().port(),
+        };
+
+        WsTransport::connect(socket.try_clone().unwrap(), socket, &host, path)
+    }
+
+    fn accept(
+        listener: &TcpListener,
+        path: &str,
+    ) -> Result<WsTransport<TcpStream, TcpStream>, String> {
+        let (socket, _) = listener.accept().unwrap();
+
+        WsTransport::accept(socket.try_clone().unwrap(), socket, path)
+    }
+
+    #[test]
+    fn test_accept_key() {
+        // the example from RFC 6455
+        assert_eq!(
+            accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+    }
+
+    #[test]
+    fn test_ws_call() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let server_listener = listener.try_clone().unwrap();
+
+        let server_thread = thread::spawn(move || -> Result<(), String> {
+            RemoteChannel::new(accept(&server_listener, "/pty")?)
+                .receive::<PtyMasterCall, PtyMasterResponse, _>(Channel::STDIN, |_| {
+                    PtyMasterResponse::Success(1)
+                })
+        });
+
+        let res = RemoteChannel::new(connect(&listener, "/pty").unwrap())
+            .send::<PtyMasterCall, PtyMasterResponse>(
+                Channel::STDIN,
+                PtyMasterCall::WriteStdin(WriteStdinCall {
+                    data: b"data".repeat(100000),
+                }),
+            );
+
+        assert_eq!(res, Ok(PtyMasterResponse::Success(1)));
+        assert_eq!(server_thread.join().unwrap(), Ok(()));
+    }
+
+    #[test]
+    fn test_ws_unknown_path() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let server_listener = listener.try_clone().unwrap();
+
+        let server_thread = thread::spawn(move || accept(&server_listener, "/pty").map(|_| ()));
+        let res = connect(&listener, "/other").map(|_| ());
+
+        assert_eq!(
+            res,
+            Err("websocket upgrade failed: HTTP/1.1 404 Not Found".to_string())
+        );
+        assert!(server_thread.join().unwrap().is_err());
+    }
+
+    #[test]
+    fn test_ws_ping_and_close() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let server_listener = listener.try_clone().unwrap();
+
+        let server_thread = thread::spawn(move || {
+            let (mut read, _write) = accept(&server_listener, "/").unwrap().split();
+            let mut buf = vec![];
+            read.read_to_end(&mut buf).unwrap();
+            buf
+        });
+
+        // a raw client sending a masked ping, a message split across frames then a close
+        let mut socket = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        socket
+            .write_all(
+                b"GET / HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\n\
+                Connection: Upgrade\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+                Sec-WebSocket-Version: 13\r\n\r\n",
+            )
+            .unwrap();
+        let mask = [1, 2, 3, 4];
+        let masked = |data: &[u8]| -> Vec<u8> {
+            data.iter()
+                .enumerate()
+                .map(|(i, b)| b ^ mask[i % 4])
+                .collect()
+        };
+        socket.write_all(&[0x89, 0x82]).unwrap();
+        socket.write_all(&mask).unwrap();
+        socket.write_all(&masked(b"hi")).unwrap();
+        socket.write_all(&[0x02, 0x83]).unwrap();
+        socket.write_all(&mask).unwrap();
+        socket.write_all(&masked(b"abc")).unwrap();
+        socket.write_all(&[0x80, 0x82]).unwrap();
+        socket.write_all(&mask).unwrap();
+        socket.write_all(&masked(b"de")).unwrap();
+        socket.write_all(&[0x88, 0x82]).unwrap();
+        socket.write_all(&mask).unwrap();
+        socket.write_all(&masked(&1000u16.to_be_bytes())).unwrap();
+
+        assert_eq!(server_thread.join().unwrap(), b"abcde");
+
+        let mut res = vec![];
+        socket.read_to_end(&mut res).unwrap();
+        let frames = res
+            .split_at(res.windows(4).position(|w| w == b"\r\n\r\n").unwrap() + 4)
+            .1;
+
+        // the unmasked pong followed by the close
+        assert_eq!(frames, [0x8A, 0x02, b'h', b'i', 0x88, 0x02, 0x03, 0xE8]);
+    }
+}
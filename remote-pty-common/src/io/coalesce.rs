@@ -0,0 +1,126 @@
+use std::{
+    io::{self, Read},
+    os::unix::prelude::{AsRawFd, RawFd},
+    time::{Duration, Instant},
+};
+
+// how long to wait for further data after a read so bursts
+// of small writes are sent as a single message
+pub const DEFAULT_COALESCE_WINDOW: Duration = Duration::from_millis(2);
+
+// reads into the buffer, blocking until data is available, then continues
+// reading any data which arrives within the window until the buffer is full
+pub fn read_coalesced<R>(reader: &mut R, buf: &mut [u8], window: Duration) -> io::Result<usize>
+where
+    R: Read + AsRawFd,
+{
+    let mut n = reader.read(buf)?;
+
+    if n == 0 {
+        return Ok(0);
+    }
+
+    let deadline = Instant::now() + window;
+
+    while n < buf.len() {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+
+        if !poll_readable(reader.as_raw_fd(), remaining)? {
+            break;
+        }
+
+        match reader.read(&mut buf[n..]) {
+            Ok(0) => break,
+            Ok(len) => n += len,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            // the data already read is returned, the error will
+            // reoccur on the next read
+            Err(_) => break,
+        }
+    }
+
+    Ok(n)
+}
+
+// waits up to the timeout for the fd to become readable
+pub fn poll_readable(fd: RawFd, timeout: Duration) -> io::Result<bool> {
+    let mut pfd = libc::pollfd {
+        fd,
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    // round up so short windows are not truncated to a non-blocking poll
+    let timeout_ms = timeout.as_micros().div_ceil(1000) as libc::c_int;
+
+    let res = unsafe { libc::poll(&mut pfd as *mut _, 1, timeout_ms) };
+
+    match res {
+        -1 => {
+            let err = io::Error::last_os_error();
+
+            if err.kind() == io::ErrorKind::Interrupted {
+                Ok(false)
+            } else {
+                Err(err)
+            }
+        }
+        0 => Ok(false),
+        _ => Ok(true),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::Write, os::unix::net::UnixStream, thread, time::Duration};
+
+    use super::read_coalesced;
+
+    #[test]
+    fn test_read_coalesced_burst() {
+        let (mut s1, mut s2) = UnixStream::pair().unwrap();
+
+        let write_thread = thread::spawn(move || {
+            for i in 0..4u8 {
+                s1.write_all(&[i]).unwrap();
+                thread::sleep(Duration::from_millis(1));
+            }
+        });
+
+        let mut buf = [0u8; 16];
+        let n = read_coalesced(&mut s2, &mut buf, Duration::from_millis(500)).unwrap();
+        assert_eq!(&buf[..n], &[0, 1, 2, 3]);
+
+        write_thread.join().unwrap();
+    }
+
+    #[test]
+    fn test_read_coalesced_after_window() {
+        let (mut s1, mut s2) = UnixStream::pair().unwrap();
+
+        s1.write_all(&[1]).unwrap();
+        let write_thread = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(100));
+            s1.write_all(&[2]).unwrap();
+        });
+
+        let mut buf = [0u8; 16];
+        let n = read_coalesced(&mut s2, &mut buf, Duration::from_millis(1)).unwrap();
+        assert_eq!(&buf[..n], &[1]);
+
+        let n = read_coalesced(&mut s2, &mut buf, Duration::from_millis(1)).unwrap();
+        assert_eq!(&buf[..n], &[2]);
+
+        write_thread.join().unwrap();
+    }
+
+    #[test]
+    fn test_read_coalesced_full_buffer() {
+        let (mut s1, mut s2) = UnixStream::pair().unwrap();
+
+        s1.write_all(&[1, 2, 3]).unwrap();
+
+        let mut buf = [0u8; 2];
+        let n = read_coalesced(&mut s2, &mut buf, Duration::from_millis(500)).unwrap();
+        assert_eq!(&buf[..n], &[1, 2]);
+    }
+}
@@ -1,2 +1,3 @@
+pub mod coalesce;
 #[cfg(target_os = "linux")]
-pub mod timeout;
\ No newline at end of file
+pub mod timeout;
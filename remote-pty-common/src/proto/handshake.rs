@@ -0,0 +1,231 @@
+use bincode::{Decode, Encode};
+
+use crate::proto::{master::PtyMasterSignal, slave::IoctlCall};
+
+// the version of the wire protocol implemented by this build
+// this must be incremented whenever the encoding of any message changes
+pub const PROTOCOL_VERSION: u32 = 6;
+
+// the oldest version of the protocol this build can interoperate with
+pub const MIN_PROTOCOL_VERSION: u32 = 6;
+
+// exchanged by both sides when a connection is established before
+// any other messages are sent.
+// the layout of this struct must never change so that it can always be
+// decoded by a peer running any other version of the protocol
+#[derive(Encode, Decode, PartialEq, Debug, Clone)]
+pub struct Hello {
+    pub version: u32,
+    pub min_version: u32,
+    pub capabilities: Capabilities,
+}
+
+#[derive(Encode, Decode, PartialEq, Debug, Clone)]
+pub enum HandshakeResponse {
+    Accepted(Hello),
+    Rejected(String),
+}
+
+// exchanged after the handshake when authentication was negotiated.
+// each side sends a random nonce and proves knowledge of the pre-shared
+// key with a HMAC over both nonces, the master proves itself first
+#[derive(Encode, Decode, PartialEq, Debug, Clone)]
+pub enum AuthCall {
+    // sent by the slave with its nonce
+    Challenge([u8; 32]),
+    // the slave's proof over the nonces
+    Proof([u8; 32]),
+}
+
+#[derive(Encode, Decode, PartialEq, Debug, Clone)]
+pub enum AuthResponse {
+    // the master's nonce and its proof over the nonces
+    Challenge { nonce: [u8; 32], proof: [u8; 32] },
+    Accepted,
+    Rejected(String),
+}
+
+// the set of optional features supported by a peer
+// capabilities are encoded as namespaced names (eg "ioctl:TIOCGETD")
+// rather than enums so unknown capabilities from newer peers can be ignored
+#[derive(Encode, Decode, PartialEq, Debug, Clone, Default)]
+pub struct Capabilities {
+    names: Vec<String>,
+}
+
+// frames may be compressed using a deflate stream
+const COMPRESSION_DEFLATE: &str = "compression:deflate";
+
+// peers authenticate each other with a pre-shared key
+const AUTH_PSK: &str = "auth:psk";
+
+// processes can be multiplexed over a single connection by a broker
+const MUX_BROKER: &str = "mux:broker";
+
+// the master returns the session of the terminal
+const CALL_GET_SID: &str = "call:GetSid";
+
+// the master tracks the session of each process and which
+// session the terminal is the controlling terminal of
+const SESSION_CTTY: &str = "session:ctty";
+
+impl Hello {
+    pub fn local() -> Self {
+        Self {
+            version: PROTOCOL_VERSION,
+            min_version: MIN_PROTOCOL_VERSION,
+            capabilities: Capabilities::local(),
+        }
+    }
+
+    // checks if each side supports the protocol version of the other
+    pub fn check_compatible(&self, remote: &Hello) -> Result<(), String> {
+        if remote.version >= self.min_version && self.version >= remote.min_version {
+            return Ok(());
+        }
+
+        Err(format!(
+            "incompatible protocol versions: local v{} (supports >= v{}), remote v{} (supports >= v{})",
+            self.version, self.min_version, remote.version, remote.min_version
+        ))
+    }
+}
+
+impl Capabilities {
+    // the capabilities supported by this build
+    pub fn local() -> Self {
+        let ioctls = IoctlCall::NAMES.iter().map(|i| format!("ioctl:{}", i));
+        let signals = PtyMasterSignal::NAMES
+            .iter()
+            .map(|i| format!("signal:{}", i));
+
+        Self {
+            names: ioctls
+                .chain(signals)
+                .chain([
+                    MUX_BROKER.to_string(),
+                    CALL_GET_SID.to_string(),
+                    SESSION_CTTY.to_string(),
+                ])
+                .collect(),
+        }
+    }
+
+    // the capabilities supported by both sides of the connection
+    pub fn intersect(&self, other: &Capabilities) -> Self {
+        Self {
+            names: self
+                .names
+                .iter()
+                .filter(|i| other.names.contains(i))
+                .cloned()
+                .collect(),
+        }
+    }
+
+    pub fn supports_ioctl(&self, call: &IoctlCall) -> bool {
+        self.supports(format!("ioctl:{}", call.name()))
+    }
+
+    pub fn supports_signal(&self, signal: &PtyMasterSignal) -> bool {
+        self.supports(format!("signal:{}", signal.name()))
+    }
+
+    // compression is opt-in so is only advertised once enabled
+    pub fn with_compression(mut self) -> Self {
+        self.names.push(COMPRESSION_DEFLATE.to_string());
+        self
+    }
+
+    pub fn supports_compression(&self) -> bool {
+        self.supports(COMPRESSION_DEFLATE.to_string())
+    }
+
+    // authentication is only advertised when a key is configured
+    pub fn with_auth(mut self) -> Self {
+        self.names.push(AUTH_PSK.to_string());
+        self
+    }
+
+    pub fn supports_auth(&self) -> bool {
+        self.supports(AUTH_PSK.to_string())
+    }
+
+    pub fn supports_mux(&self) -> bool {
+        self.supports(MUX_BROKER.to_string())
+    }
+
+    pub fn supports_get_sid(&self) -> bool {
+        self.supports(CALL_GET_SID.to_string())
+    }
+
+    pub fn supports_ctty(&self) -> bool {
+        self.supports(SESSION_CTTY.to_string())
+    }
+
+    fn supports(&self, name: String) -> bool {
+        self.names.contains(&name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::proto::{master::PtyMasterSignal, slave::IoctlCall};
+
+    use super::{Capabilities, Hello};
+
+    #[test]
+    fn test_local_capabilities() {
+        let caps = Capabilities::local();
+
+        assert!(caps.supports_ioctl(&IoctlCall::TIOCSETD(1)));
+        assert!(caps.supports_signal(&PtyMasterSignal::SIGWINCH));
+    }
+
+    #[test]
+    fn test_intersect_capabilities() {
+        let local = Capabilities::local();
+        let remote = Capabilities {
+            names: vec!["ioctl:FIONREAD".to_string(), "ioctl:TIOCNEW".to_string()],
+        };
+
+        let caps = local.intersect(&remote);
+
+        assert!(caps.supports_ioctl(&IoctlCall::FIONREAD));
+        assert!(!caps.supports_ioctl(&IoctlCall::TIOCGETD));
+        assert!(!caps.supports_signal(&PtyMasterSignal::SIGWINCH));
+        assert_eq!(caps.names, vec!["ioctl:FIONREAD".to_string()]);
+    }
+
+    #[test]
+    fn test_intersect_compression() {
+        let local = Capabilities::local().with_compression();
+
+        assert!(local.supports_compression());
+        assert!(local.intersect(&local.clone()).supports_compression());
+        assert!(!local
+            .intersect(&Capabilities::local())
+            .supports_compression());
+    }
+
+    #[test]
+    fn test_check_compatible() {
+        let local = Hello {
+            version: 3,
+            min_version: 2,
+            capabilities: Capabilities::default(),
+        };
+
+        let remote = |version, min_version| Hello {
+            version,
+            min_version,
+            capabilities: Capabilities::default(),
+        };
+
+        assert!(local.check_compatible(&remote(3, 2)).is_ok());
+        assert!(local.check_compatible(&remote(2, 1)).is_ok());
+        assert!(local.check_compatible(&remote(5, 3)).is_ok());
+        assert!(local.check_compatible(&remote(1, 1)).is_err());
+        assert!(local.check_compatible(&remote(5, 4)).is_err());
+    }
+}
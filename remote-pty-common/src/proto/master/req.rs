@@ -1,9 +1,17 @@
 use bincode::{Decode, Encode};
 
+use crate::proto::StreamCredit;
+
 #[derive(Encode, Decode, PartialEq, Debug, Clone)]
 pub enum PtyMasterCall {
     Signal(SignalCall),
+    // stdin is streamed without responses, limited by the credit
+    // granted by the client
     WriteStdin(WriteStdinCall),
+    // grants the client credit to send more stdout
+    StdoutCredit(StreamCredit),
+    // sent along with stdin while the terminal is in packet mode
+    PacketStatus(PacketStatusCall),
 }
 
 #[derive(Encode, Decode, PartialEq, Debug, Clone)]
@@ -20,9 +28,46 @@ pub enum PtyMasterSignal {
     SIGCONT,
     SIGTTOU,
     SIGTTIN,
+    SIGHUP,
+}
+
+impl PtyMasterSignal {
+    // names of the signals supported by this build
+    pub const NAMES: &'static [&'static str] = &[
+        "SIGWINCH", "SIGINT", "SIGTERM", "SIGCONT", "SIGTTOU", "SIGTTIN", "SIGHUP",
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::SIGWINCH => "SIGWINCH",
+            Self::SIGINT => "SIGINT",
+            Self::SIGTERM => "SIGTERM",
+            Self::SIGCONT => "SIGCONT",
+            Self::SIGTTOU => "SIGTTOU",
+            Self::SIGTTIN => "SIGTTIN",
+            Self::SIGHUP => "SIGHUP",
+        }
+    }
 }
 
 #[derive(Encode, Decode, PartialEq, Debug, Clone)]
 pub struct WriteStdinCall {
     pub data: Vec<u8>
 }
+
+// @see https://man7.org/linux/man-pages/man2/TIOCPKT.2const.html
+#[derive(Encode, Decode, PartialEq, Debug, Clone)]
+pub struct PacketStatusCall {
+    pub status: u8
+}
+
+impl PacketStatusCall {
+    // prefixes data read from the terminal in packet mode
+    pub const TIOCPKT_DATA: u8 = 0x00;
+    pub const TIOCPKT_FLUSHREAD: u8 = 0x01;
+    pub const TIOCPKT_FLUSHWRITE: u8 = 0x02;
+    pub const TIOCPKT_STOP: u8 = 0x04;
+    pub const TIOCPKT_START: u8 = 0x08;
+    pub const TIOCPKT_NOSTOP: u8 = 0x10;
+    pub const TIOCPKT_DOSTOP: u8 = 0x20;
+}
@@ -1,5 +1,7 @@
 pub mod slave;
 pub mod master;
+pub mod handshake;
+pub mod mux;
 
 mod msg;
 pub use msg::*;
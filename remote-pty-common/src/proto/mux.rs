@@ -0,0 +1,18 @@
+use bincode::{Decode, Encode};
+
+// sent in both directions on the mux channel of a broker's connection,
+// each frame belongs to the stream of a single slave process
+#[derive(Encode, Decode, PartialEq, Debug, Clone)]
+pub struct MuxFrame {
+    // assigned by the broker when the stream is opened
+    pub stream: u32,
+    pub typ: MuxFrameType,
+}
+
+#[derive(Encode, Decode, PartialEq, Debug, Clone)]
+pub enum MuxFrameType {
+    // a process connected to the broker
+    Open { pid: u32 },
+    Data(Vec<u8>),
+    Close,
+}
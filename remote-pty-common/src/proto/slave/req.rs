@@ -1,6 +1,6 @@
 use bincode::{Decode, Encode};
 
-use crate::proto::{Termios, WinSize, Fd};
+use crate::proto::{SessionToken, StreamCredit, Termios, WinSize, Fd};
 
 // @see https://pubs.opengroup.org/onlinepubs/7908799/xsh/termios.h.html
 #[derive(Encode, Decode, PartialEq, Debug, Clone)]
@@ -31,7 +31,32 @@ pub enum PtySlaveCallType {
     // @see https://man7.org/linux/man-pages/man3/tcgetpgrp.3.html
     GetProcGroup,
     SetProgGroup(TcSetProcGroupCall),
+    // stdout is streamed without responses, limited by the credit
+    // granted by the master
     WriteStdout(WriteStdoutCall),
+    // responds once all previously streamed stdout has been written
+    FlushStdout,
+    // grants the master credit to send more stdin
+    StdinCredit(StreamCredit),
+    // sent instead of registering a process by a broker which
+    // multiplexes the connections of the session's processes
+    RegisterBroker,
+    // equivalent to ioctl(fd, TIOCGSID, *sid)
+    // @see https://pubs.opengroup.org/onlinepubs/007904975/functions/tcgetsid.html
+    GetSid,
+    // registers the process along with its session, used instead of
+    // RegisterProcess when the master models the controlling terminal
+    RegisterSessionProcess(RegisterSessionProcessCall),
+    // sent once the process has created a new session with setsid,
+    // which detaches it from the controlling terminal
+    // @see https://man7.org/linux/man-pages/man2/setsid.2.html
+    SetSession,
+    // equivalent to ioctl(fd, TIOCSCTTY, steal)
+    SetCtty(SetCttyCall),
+    // equivalent to ioctl(fd, TIOCNOTTY)
+    ReleaseCtty,
+    // reattaches a process to its session after the transport was lost
+    ResumeSession(ResumeSessionCall),
 }
 
 #[derive(Encode, Decode, PartialEq, Debug, Clone)]
@@ -40,6 +65,21 @@ pub struct RegisterProcessCall {
     pub pgrp: u32,
 }
 
+#[derive(Encode, Decode, PartialEq, Debug, Clone)]
+pub struct RegisterSessionProcessCall {
+    pub pid: u32,
+    pub pgrp: u32,
+    pub sid: u32,
+}
+
+#[derive(Encode, Decode, PartialEq, Debug, Clone)]
+pub struct ResumeSessionCall {
+    pub pid: u32,
+    pub token: SessionToken,
+    // total bytes of stdin received from the master
+    pub stdin_received: u64,
+}
+
 #[derive(Encode, Decode, PartialEq, Debug, Clone)]
 pub struct SetProcessGroupCall {
     pub pid: u32,
@@ -105,6 +145,19 @@ pub enum IoctlCall {
     TIOCOUTQ,
     TIOCGETD,
     TIOCSETD(u32),
+    // inserts the byte into the input queue as if it were typed
+    TIOCSTI(TiocStiCall),
+    // enables or disables packet mode
+    TIOCPKT(bool),
+    TIOCGPKT,
+    // reads and modifies the bitmask of modem lines
+    TIOCMGET,
+    TIOCMSET(u32),
+    TIOCMBIC(u32),
+    TIOCMBIS(u32),
+    // turns the break on and off
+    TIOCSBRK,
+    TIOCCBRK,
 }
 
 #[derive(Encode, Decode, PartialEq, Debug, Clone)]
@@ -112,9 +165,54 @@ pub struct TcSetProcGroupCall {
     pub pid: u32
 }
 
+// @see https://man7.org/linux/man-pages/man2/TIOCSCTTY.2const.html
+#[derive(Encode, Decode, PartialEq, Debug, Clone)]
+pub struct SetCttyCall {
+    // takes the terminal from another session, only
+    // permitted if the process has CAP_SYS_ADMIN
+    pub steal: bool
+}
+
+// @see https://man7.org/linux/man-pages/man2/TIOCSTI.2const.html
+#[derive(Encode, Decode, PartialEq, Debug, Clone)]
+pub struct TiocStiCall {
+    pub c: u8,
+    // fakes input on a terminal which is not the controlling
+    // terminal, only permitted if the process has CAP_SYS_ADMIN
+    pub privileged: bool
+}
+
 #[derive(Encode, Decode, PartialEq, Debug, Clone)]
 pub struct WriteStdoutCall {
-    pub data: Vec<u8>
+    pub data: Vec<u8>,
+    // the process group of the writer when the data was sent
+    pub pgrp: u32
+}
+
+impl IoctlCall {
+    // names of the ioctl's supported by this build
+    pub const NAMES: &'static [&'static str] = &[
+        "FIONREAD", "TIOCOUTQ", "TIOCGETD", "TIOCSETD", "TIOCSTI", "TIOCPKT", "TIOCGPKT",
+        "TIOCMGET", "TIOCMSET", "TIOCMBIC", "TIOCMBIS", "TIOCSBRK", "TIOCCBRK",
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::FIONREAD => "FIONREAD",
+            Self::TIOCOUTQ => "TIOCOUTQ",
+            Self::TIOCGETD => "TIOCGETD",
+            Self::TIOCSETD(_) => "TIOCSETD",
+            Self::TIOCSTI(_) => "TIOCSTI",
+            Self::TIOCPKT(_) => "TIOCPKT",
+            Self::TIOCGPKT => "TIOCGPKT",
+            Self::TIOCMGET => "TIOCMGET",
+            Self::TIOCMSET(_) => "TIOCMSET",
+            Self::TIOCMBIC(_) => "TIOCMBIC",
+            Self::TIOCMBIS(_) => "TIOCMBIS",
+            Self::TIOCSBRK => "TIOCSBRK",
+            Self::TIOCCBRK => "TIOCCBRK",
+        }
+    }
 }
 
 impl PtySlaveCallType {
@@ -129,13 +227,30 @@ impl PtySlaveCallType {
             Self::Flush(_) => true,
             Self::SendBreak(_) => true,
             Self::SetWinSize(_) => true,
+            // the kernel permits background processes to fake input
+            Self::Ioctl(IoctlCall::TIOCSTI(_)) => false,
             Self::Ioctl(_) => true,
             // allow procs to steal the terminal which happens during shell forking
             Self::SetProgGroup(_) => false,
+            // checked against the pgrp the stdout was written by
             Self::WriteStdout(_) => true,
             _ => false
         }
     }
+
+    // determines if the terminal must be the controlling terminal of
+    // the calling process to perform this call
+    pub fn requires_ctty(&self) -> bool {
+        matches!(
+            self,
+            Self::GetProcGroup | Self::SetProgGroup(_) | Self::GetSid | Self::ReleaseCtty
+        )
+    }
+
+    // whether the call is a stream message which is not replied to
+    pub fn is_notification(&self) -> bool {
+        matches!(self, Self::WriteStdout(_) | Self::StdinCredit(_))
+    }
 }
 
 #[cfg(test)]
@@ -159,4 +274,14 @@ mod tests {
             .0
         );
     }
-}
\ No newline at end of file
+    #[test]
+    fn encode_existing_variant() {
+        let config = bincode::config::standard();
+
+        // variants are appended so existing ones keep their discriminant
+        assert_eq!(
+            bincode::encode_to_vec(PtySlaveCallType::GetAttr, config).unwrap(),
+            vec![2]
+        );
+    }
+}
@@ -1,6 +1,6 @@
 use bincode::{Decode, Encode};
 
-use crate::proto::{Termios, WinSize};
+use crate::proto::{SessionToken, Termios, WinSize};
 
 #[derive(Encode, Decode, PartialEq, Debug, Clone)]
 pub enum PtySlaveResponse {
@@ -10,6 +10,8 @@ pub enum PtySlaveResponse {
     Ioctl(IoctlResponse),
     GetProcGroup(ProcGroupResponse),
     Error(TcError),
+    GetSid(SidResponse),
+    Session(SessionResponse),
 }
 
 // @see https://pubs.opengroup.org/onlinepubs/7908799/xsh/tcgetattr.html
@@ -33,6 +35,7 @@ pub enum TcError {
     ENOTTY,
     EINTR,
     EIO,
+    EPERM,
 }
 
 #[derive(Encode, Decode, PartialEq, Debug, Clone)]
@@ -51,6 +54,22 @@ pub struct ProcGroupResponse {
     pub pid: i32
 }
 
+#[derive(Encode, Decode, PartialEq, Debug, Clone)]
+pub struct SidResponse {
+    pub sid: i32
+}
+
+// returned when registering or resuming a process if the master
+// supports session resumption
+#[derive(Encode, Decode, PartialEq, Debug, Clone)]
+pub struct SessionResponse {
+    pub token: SessionToken,
+    // how long the master retains the session after a disconnect
+    pub resume_timeout_ms: u64,
+    // total bytes of stdout received from the process
+    pub stdout_received: u64,
+}
+
 #[cfg(test)]
 mod tests {
     use crate::proto::{
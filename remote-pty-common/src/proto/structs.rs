@@ -409,6 +409,19 @@ pub struct WinSize {
 #[derive(Encode, Decode, PartialEq, Debug, Clone)]
 pub struct Fd(pub i32);
 
+// identifies a process's session with the master
+#[derive(Encode, Decode, PartialEq, Eq, Hash, Debug, Clone, Copy)]
+pub struct SessionToken(pub [u8; 16]);
+
+// grants the sender of a stream permission to have up to window
+// bytes in flight beyond the consumed offset
+#[derive(Encode, Decode, PartialEq, Eq, Debug, Clone, Copy)]
+pub struct StreamCredit {
+    // total bytes of the stream consumed by the receiver
+    pub consumed: u64,
+    pub window: u64,
+}
+
 #[cfg(test)]
 mod tests {
     use std::ptr;
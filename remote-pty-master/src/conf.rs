@@ -0,0 +1,150 @@
+use std::{env, time::Duration};
+
+use remote_pty_common::channel::{
+    auth::Psk,
+    compress::CompressionConf,
+    frame::{max_frame_size_from_env, DEFAULT_MAX_FRAME_SIZE},
+    keepalive::KeepaliveConf,
+    stream::{stream_window_from_env, DEFAULT_STREAM_WINDOW},
+    transport::tls::TlsServerConf,
+};
+
+#[derive(Debug, Clone)]
+pub struct Conf {
+    // heartbeat settings for client connections, disabled if none
+    pub keepalive: Option<KeepaliveConf>,
+    // how long sessions are retained after a client disconnects
+    // so it can resume, disabled if none
+    pub resume_timeout: Option<Duration>,
+    // the max size of frames exchanged with clients
+    pub max_frame_size: usize,
+    // window of stdout clients may send before being granted more credit
+    pub stream_window: u64,
+    // compression offered to clients, disabled if none
+    pub compression: Option<CompressionConf>,
+    // certificate used when listening on a tls transport
+    pub tls: Option<TlsServerConf>,
+    // key clients must prove knowledge of before registering, disabled if none
+    pub psk: Option<Psk>,
+    // restrictions applied when listening on a unix socket
+    pub unix: UnixListenerConf,
+}
+
+impl Conf {
+    pub fn from_env() -> Result<Self, String> {
+        Ok(Self {
+            keepalive: KeepaliveConf::from_env()?,
+            resume_timeout: match env::var("RPTY_RESUME_TIMEOUT_MS") {
+                Ok(ms) => Some(
+                    ms.parse::<u64>()
+                        .map_err(|_| "failed to parse number in RPTY_RESUME_TIMEOUT_MS")?,
+                )
+                .filter(|ms| *ms > 0)
+                .map(Duration::from_millis),
+                Err(_) => None,
+            },
+            max_frame_size: max_frame_size_from_env()?,
+            stream_window: stream_window_from_env()?,
+            compression: CompressionConf::from_env()?,
+            tls: TlsServerConf::from_env()?,
+            psk: Psk::from_env()?,
+            unix: UnixListenerConf::from_env()?,
+        })
+    }
+}
+
+impl Default for Conf {
+    fn default() -> Self {
+        Self {
+            keepalive: None,
+            resume_timeout: None,
+            max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+            stream_window: DEFAULT_STREAM_WINDOW,
+            compression: None,
+            tls: None,
+            psk: None,
+            unix: UnixListenerConf::default(),
+        }
+    }
+}
+
+// restricts which local processes can connect to a unix socket
+#[derive(Debug, Clone, Default)]
+pub struct UnixListenerConf {
+    // permissions of the socket file, the umask applies if none.
+    // abstract sockets have no permissions so this is ignored for them
+    pub mode: Option<u32>,
+    // peers must run as one of the uids or gids,
+    // any peer is accepted if both are empty
+    pub allowed_uids: Vec<u32>,
+    pub allowed_gids: Vec<u32>,
+}
+
+impl UnixListenerConf {
+    pub fn from_env() -> Result<Self, String> {
+        let mode = match env::var("RPTY_SOCKET_MODE") {
+            Ok(mode) => Some(
+                u32::from_str_radix(&mode, 8)
+                    .map_err(|_| "failed to parse octal number in RPTY_SOCKET_MODE")?,
+            ),
+            Err(_) => None,
+        };
+
+        if mode.map(|m| m > 0o777).unwrap_or(false) {
+            return Err("RPTY_SOCKET_MODE must be between 000 and 777".to_string());
+        }
+
+        Ok(Self {
+            mode,
+            allowed_uids: parse_ids("RPTY_ALLOWED_UIDS")?,
+            allowed_gids: parse_ids("RPTY_ALLOWED_GIDS")?,
+        })
+    }
+
+    // whether a peer running as the uid and gid may connect
+    pub fn is_allowed(&self, uid: u32, gid: u32) -> bool {
+        self.allows_any() || self.allowed_uids.contains(&uid) || self.allowed_gids.contains(&gid)
+    }
+
+    pub fn allows_any(&self) -> bool {
+        self.allowed_uids.is_empty() && self.allowed_gids.is_empty()
+    }
+}
+
+// parses a comma separated list of ids from the env var
+fn parse_ids(var: &str) -> Result<Vec<u32>, String> {
+    match env::var(var) {
+        Ok(ids) => ids
+            .split(',')
+            .map(|id| id.trim().parse::<u32>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| format!("failed to parse numbers in {}", var)),
+        Err(_) => Ok(vec![]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::UnixListenerConf;
+
+    #[test]
+    fn test_unix_listener_allows_any() {
+        let conf = UnixListenerConf::default();
+
+        assert!(conf.allows_any());
+        assert!(conf.is_allowed(1000, 1000));
+    }
+
+    #[test]
+    fn test_unix_listener_allowed_ids() {
+        let conf = UnixListenerConf {
+            mode: None,
+            allowed_uids: vec![1000],
+            allowed_gids: vec![50],
+        };
+
+        assert!(conf.is_allowed(1000, 1000));
+        assert!(conf.is_allowed(1001, 50));
+        assert!(!conf.is_allowed(1001, 1001));
+    }
+}
@@ -8,7 +8,15 @@ pub struct Context {
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct TerminalState {
-    pub pgrp: Option<u32>
+    pub pgrp: Option<u32>,
+    // the session leader, the first process to register
+    pub sid: Option<u32>,
+    // whether status changes are sent to the foreground process
+    pub packet: bool,
+    // emulated modem lines and break, used unless
+    // the terminal is backed by a serial device
+    pub modem: u32,
+    pub brk: bool
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -58,7 +66,16 @@ impl Context {
 impl TerminalState {
     pub fn new() -> Self {
         Self {
-            pgrp: None
+            pgrp: None,
+            sid: None,
+            packet: false,
+            // the line is connected and ready
+            modem: (libc::TIOCM_DTR
+                | libc::TIOCM_RTS
+                | libc::TIOCM_CTS
+                | libc::TIOCM_DSR
+                | libc::TIOCM_CAR) as _,
+            brk: false
         }
     }
 }
@@ -1,16 +1,29 @@
-use remote_pty_common::proto::slave::{
-    IoctlCall, IoctlResponse, IoctlValueResponse, PtySlaveResponse,
+use remote_pty_common::{
+    log::debug,
+    proto::slave::{IoctlCall, IoctlResponse, IoctlValueResponse, PtySlaveResponse, TcError},
 };
 
 use crate::context::Context;
 
-use super::common::handle_error;
+use super::{
+    common::handle_error,
+    modem::{handle_break, handle_modem},
+};
 
 // @see https://man7.org/linux/man-pages/man4/tty_ioctl.4.html
 pub fn handle_ioctl(ctx: &Context, req: IoctlCall) -> PtySlaveResponse {
     match req {
         IoctlCall::FIONREAD | IoctlCall::TIOCOUTQ | IoctlCall::TIOCGETD => ioctl_get_int(ctx, req),
         IoctlCall::TIOCSETD(_) => ioctl_set_int(ctx, req),
+        // the input is injected into the stdin stream by the server
+        IoctlCall::TIOCSTI(_) => PtySlaveResponse::Error(TcError::EINVAL),
+        IoctlCall::TIOCPKT(packet) => ioctl_set_packet(ctx, packet),
+        IoctlCall::TIOCGPKT => ioctl_get_packet(ctx),
+        IoctlCall::TIOCMGET
+        | IoctlCall::TIOCMSET(_)
+        | IoctlCall::TIOCMBIC(_)
+        | IoctlCall::TIOCMBIS(_) => handle_modem(ctx, req),
+        IoctlCall::TIOCSBRK | IoctlCall::TIOCCBRK => handle_break(ctx, req),
     }
 }
 
@@ -50,10 +63,30 @@ pub fn ioctl_set_int(ctx: &Context, req: IoctlCall) -> PtySlaveResponse {
     PtySlaveResponse::Success(ret as _)
 }
 
+// packet mode is tracked for the virtual terminal rather than
+// set on the pty as the master does not read it in packet mode
+pub fn ioctl_set_packet(ctx: &Context, packet: bool) -> PtySlaveResponse {
+    let mut state = ctx.state.lock().expect("failed to lock terminal state");
+    state.packet = packet;
+
+    debug(format!("set packet mode to {}", packet));
+
+    PtySlaveResponse::Success(0)
+}
+
+pub fn ioctl_get_packet(ctx: &Context) -> PtySlaveResponse {
+    let state = ctx.state.lock().expect("failed to lock terminal state");
+
+    PtySlaveResponse::Ioctl(IoctlResponse {
+        ret: 0,
+        val: IoctlValueResponse::Int(state.packet as _),
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use remote_pty_common::proto::slave::{
-        IoctlCall, IoctlResponse, IoctlValueResponse, PtySlaveResponse, TcError,
+        IoctlCall, IoctlResponse, IoctlValueResponse, PtySlaveResponse, TcError, TiocStiCall,
     };
 
     use crate::{context::Context, handler::handle_ioctl};
@@ -126,4 +159,39 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn test_ioctl_set_packet_mode() {
+        let ctx = Context::openpty().unwrap();
+
+        let ret = handle_ioctl(&ctx, IoctlCall::TIOCPKT(true));
+        assert_eq!(ret, PtySlaveResponse::Success(0));
+
+        match handle_ioctl(&ctx, IoctlCall::TIOCGPKT) {
+            PtySlaveResponse::Ioctl(IoctlResponse {
+                ret,
+                val: IoctlValueResponse::Int(v),
+            }) => {
+                assert_eq!(ret, 0);
+                assert_eq!(v, 1);
+            }
+            res @ _ => {
+                dbg!(res);
+                unreachable!()
+            }
+        }
+    }
+
+    #[test]
+    fn test_ioctl_tiocsti_is_handled_by_server() {
+        let ctx = Context::openpty().unwrap();
+        let req = IoctlCall::TIOCSTI(TiocStiCall {
+            c: b'a',
+            privileged: false,
+        });
+
+        let ret = handle_ioctl(&ctx, req);
+
+        assert_eq!(ret, PtySlaveResponse::Error(TcError::EINVAL));
+    }
 }
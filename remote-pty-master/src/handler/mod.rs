@@ -14,6 +14,8 @@ mod tcgetpgrp;
 pub use tcgetpgrp::*;
 mod tcsetpgrp;
 pub use tcsetpgrp::*;
+mod tcgetsid;
+pub use tcgetsid::*;
 mod tcdrain;
 pub use tcdrain::*;
 mod tcflow;
@@ -24,10 +26,14 @@ mod tcsendbreak;
 pub use tcsendbreak::*;
 mod ioctl;
 pub use ioctl::*;
+mod packet;
+pub use packet::*;
+mod modem;
+pub use modem::*;
 
 use remote_pty_common::{
     log::debug,
-    proto::slave::{PtySlaveCall, PtySlaveCallType, PtySlaveResponse},
+    proto::slave::{PtySlaveCall, PtySlaveCallType, PtySlaveResponse, TcError},
 };
 
 use crate::context::Context;
@@ -52,6 +58,16 @@ impl RemotePtyHandlers {
             PtySlaveCallType::GetProcGroup => handle_tcgetpgrp(ctx),
             PtySlaveCallType::SetProgGroup(req) => handle_tcsetpgrp(ctx, req),
             PtySlaveCallType::WriteStdout(_) => todo!(),
+            PtySlaveCallType::GetSid => handle_tcgetsid(ctx),
+            // handled by the server as they depend on the connected clients
+            PtySlaveCallType::ResumeSession(_)
+            | PtySlaveCallType::FlushStdout
+            | PtySlaveCallType::StdinCredit(_)
+            | PtySlaveCallType::RegisterBroker
+            | PtySlaveCallType::RegisterSessionProcess(_)
+            | PtySlaveCallType::SetSession
+            | PtySlaveCallType::SetCtty(_)
+            | PtySlaveCallType::ReleaseCtty => PtySlaveResponse::Error(TcError::EINVAL),
         };
      
         debug(format!("response: {:?}", res));
@@ -0,0 +1,158 @@
+use remote_pty_common::{
+    log::debug,
+    proto::slave::{IoctlCall, IoctlResponse, IoctlValueResponse, PtySlaveResponse},
+};
+
+use crate::context::Context;
+
+use super::common::handle_error;
+
+const TIOCM_OUT1: u32 = 0x2000;
+const TIOCM_OUT2: u32 = 0x4000;
+const TIOCM_LOOP: u32 = 0x8000;
+
+// like the kernel only the output lines can be modified,
+// the remaining lines are inputs from the modem
+const TIOCM_SETTABLE: u32 =
+    libc::TIOCM_DTR as u32 | libc::TIOCM_RTS as u32 | TIOCM_OUT1 | TIOCM_OUT2 | TIOCM_LOOP;
+
+// @see https://man7.org/linux/man-pages/man2/TIOCMSET.2const.html
+pub fn handle_modem(ctx: &Context, req: IoctlCall) -> PtySlaveResponse {
+    if is_serial(ctx) {
+        return modem_passthrough(ctx, req);
+    }
+
+    let mut state = ctx.state.lock().expect("failed to lock terminal state");
+
+    match req {
+        IoctlCall::TIOCMGET => {
+            return PtySlaveResponse::Ioctl(IoctlResponse {
+                ret: 0,
+                val: IoctlValueResponse::Int(state.modem as _),
+            })
+        }
+        IoctlCall::TIOCMSET(bits) => {
+            state.modem = (state.modem & !TIOCM_SETTABLE) | (bits & TIOCM_SETTABLE)
+        }
+        IoctlCall::TIOCMBIC(bits) => state.modem &= !(bits & TIOCM_SETTABLE),
+        IoctlCall::TIOCMBIS(bits) => state.modem |= bits & TIOCM_SETTABLE,
+        _ => unreachable!(),
+    }
+
+    debug(format!("set modem lines to {:#x}", state.modem));
+
+    PtySlaveResponse::Success(0)
+}
+
+fn modem_passthrough(ctx: &Context, req: IoctlCall) -> PtySlaveResponse {
+    let (cmd, mut bits) = match req {
+        IoctlCall::TIOCMGET => (libc::TIOCMGET, 0),
+        IoctlCall::TIOCMSET(bits) => (libc::TIOCMSET, bits as libc::c_int),
+        IoctlCall::TIOCMBIC(bits) => (libc::TIOCMBIC, bits as libc::c_int),
+        IoctlCall::TIOCMBIS(bits) => (libc::TIOCMBIS, bits as libc::c_int),
+        _ => unreachable!(),
+    };
+
+    let ret = unsafe { libc::ioctl(ctx.pty.master as _, cmd as _, &mut bits as *mut _) };
+
+    if ret != 0 {
+        return handle_error(ctx);
+    }
+
+    match req {
+        IoctlCall::TIOCMGET => PtySlaveResponse::Ioctl(IoctlResponse {
+            ret: ret as _,
+            val: IoctlValueResponse::Int(bits as _),
+        }),
+        _ => PtySlaveResponse::Success(ret as _),
+    }
+}
+
+// @see https://man7.org/linux/man-pages/man2/TIOCSBRK.2const.html
+pub fn handle_break(ctx: &Context, req: IoctlCall) -> PtySlaveResponse {
+    let brk = matches!(req, IoctlCall::TIOCSBRK);
+
+    if is_serial(ctx) {
+        let cmd = if brk { libc::TIOCSBRK } else { libc::TIOCCBRK };
+        let ret = unsafe { libc::ioctl(ctx.pty.master as _, cmd as _) };
+
+        if ret != 0 {
+            return handle_error(ctx);
+        }
+
+        return PtySlaveResponse::Success(ret as _);
+    }
+
+    let mut state = ctx.state.lock().expect("failed to lock terminal state");
+    state.brk = brk;
+
+    debug(format!("set break to {}", brk));
+
+    PtySlaveResponse::Success(0)
+}
+
+// serial devices report their modem lines unlike ptys
+fn is_serial(ctx: &Context) -> bool {
+    let mut bits = 0 as libc::c_int;
+
+    unsafe {
+        libc::ioctl(
+            ctx.pty.master as _,
+            libc::TIOCMGET as _,
+            &mut bits as *mut _,
+        ) == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use remote_pty_common::proto::slave::{
+        IoctlCall, IoctlResponse, IoctlValueResponse, PtySlaveResponse,
+    };
+
+    use crate::{
+        context::Context,
+        handler::{handle_break, handle_modem},
+    };
+
+    fn get_modem(ctx: &Context) -> i64 {
+        match handle_modem(ctx, IoctlCall::TIOCMGET) {
+            PtySlaveResponse::Ioctl(IoctlResponse {
+                ret: 0,
+                val: IoctlValueResponse::Int(bits),
+            }) => bits as _,
+            res => panic!("unexpected response {:?}", res),
+        }
+    }
+
+    #[test]
+    fn test_modem_lines_with_pty() {
+        let ctx = Context::openpty().unwrap();
+        let dtr = libc::TIOCM_DTR as i64;
+        let cts = libc::TIOCM_CTS as i64;
+
+        assert_eq!(get_modem(&ctx) & (dtr | cts), dtr | cts);
+
+        let ret = handle_modem(&ctx, IoctlCall::TIOCMBIC(libc::TIOCM_DTR as _));
+        assert_eq!(ret, PtySlaveResponse::Success(0));
+        assert_eq!(get_modem(&ctx) & dtr, 0);
+
+        // input lines are not modified
+        let ret = handle_modem(&ctx, IoctlCall::TIOCMSET(libc::TIOCM_DTR as _));
+        assert_eq!(ret, PtySlaveResponse::Success(0));
+        assert_eq!(get_modem(&ctx) & (dtr | cts), dtr | cts);
+    }
+
+    #[test]
+    fn test_break_with_pty() {
+        let ctx = Context::openpty().unwrap();
+
+        let ret = handle_break(&ctx, IoctlCall::TIOCSBRK);
+        assert_eq!(ret, PtySlaveResponse::Success(0));
+        assert!(ctx.state.lock().unwrap().brk);
+
+        let ret = handle_break(&ctx, IoctlCall::TIOCCBRK);
+        assert_eq!(ret, PtySlaveResponse::Success(0));
+        assert!(!ctx.state.lock().unwrap().brk);
+    }
+}
@@ -0,0 +1,140 @@
+use remote_pty_common::proto::{
+    master::PacketStatusCall,
+    slave::{
+        PtySlaveCallType, PtySlaveResponse, TcFlowAction, TcFlowCall, TcFlushCall,
+        TcFlushQueueSelector, TcGetAttrResponse,
+    },
+    Termios, TermiosControlChar, TermiosInputMode,
+};
+
+use crate::context::Context;
+
+use super::handle_tcgetattr;
+
+// the status reported to the foreground process in packet mode once the call
+// succeeds, which mirrors the status bytes of the linux pty driver
+// @see https://man7.org/linux/man-pages/man2/TIOCPKT.2const.html
+pub fn packet_status(ctx: &Context, typ: &PtySlaveCallType) -> Option<u8> {
+    let state = ctx.state.lock().expect("failed to lock terminal state");
+
+    if !state.packet {
+        return None;
+    }
+
+    drop(state);
+
+    match typ {
+        PtySlaveCallType::Flush(TcFlushCall { queue_selector }) => Some(match queue_selector {
+            TcFlushQueueSelector::TCIFLUSH => PacketStatusCall::TIOCPKT_FLUSHREAD,
+            TcFlushQueueSelector::TCOFLUSH => PacketStatusCall::TIOCPKT_FLUSHWRITE,
+            TcFlushQueueSelector::TCIOFLUSH => {
+                PacketStatusCall::TIOCPKT_FLUSHREAD | PacketStatusCall::TIOCPKT_FLUSHWRITE
+            }
+        }),
+        PtySlaveCallType::Flow(TcFlowCall {
+            action: TcFlowAction::TCOOFF,
+        }) => Some(PacketStatusCall::TIOCPKT_STOP),
+        PtySlaveCallType::Flow(TcFlowCall {
+            action: TcFlowAction::TCOON,
+        }) => Some(PacketStatusCall::TIOCPKT_START),
+        // reported when the terminal starts or stops using ^S/^Q for flow control
+        PtySlaveCallType::SetAttr(req) => {
+            let old = match handle_tcgetattr(ctx) {
+                PtySlaveResponse::GetAttr(TcGetAttrResponse { termios, .. }) => termios,
+                _ => return None,
+            };
+
+            match (flow_control(&old), flow_control(&req.termios)) {
+                (false, true) => Some(PacketStatusCall::TIOCPKT_DOSTOP),
+                (true, false) => Some(PacketStatusCall::TIOCPKT_NOSTOP),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+fn flow_control(termios: &Termios) -> bool {
+    termios.c_iflag.contains(&TermiosInputMode::IXON)
+        && termios.c_cc.get(&TermiosControlChar::VSTOP) == Some(&0x13)
+        && termios.c_cc.get(&TermiosControlChar::VSTART) == Some(&0x11)
+}
+
+#[cfg(test)]
+mod tests {
+    use remote_pty_common::proto::{
+        master::PacketStatusCall,
+        slave::{
+            PtySlaveCallType, PtySlaveResponse, TcFlowAction, TcFlowCall, TcFlushCall,
+            TcFlushQueueSelector, TcGetAttrResponse, TcSetAttrActions, TcSetAttrCall,
+        },
+        TermiosInputMode,
+    };
+
+    use crate::{
+        context::Context,
+        handler::{handle_tcgetattr, packet_status},
+    };
+
+    #[test]
+    fn test_packet_status_without_packet_mode() {
+        let ctx = Context::openpty().unwrap();
+        let req = PtySlaveCallType::Flow(TcFlowCall {
+            action: TcFlowAction::TCOOFF,
+        });
+
+        assert_eq!(packet_status(&ctx, &req), None);
+    }
+
+    #[test]
+    fn test_packet_status_flush_and_flow() {
+        let ctx = Context::openpty().unwrap();
+        ctx.state.lock().unwrap().packet = true;
+
+        let flush = PtySlaveCallType::Flush(TcFlushCall {
+            queue_selector: TcFlushQueueSelector::TCIOFLUSH,
+        });
+        let stop = PtySlaveCallType::Flow(TcFlowCall {
+            action: TcFlowAction::TCOOFF,
+        });
+
+        assert_eq!(
+            packet_status(&ctx, &flush),
+            Some(PacketStatusCall::TIOCPKT_FLUSHREAD | PacketStatusCall::TIOCPKT_FLUSHWRITE)
+        );
+        assert_eq!(
+            packet_status(&ctx, &stop),
+            Some(PacketStatusCall::TIOCPKT_STOP)
+        );
+    }
+
+    #[test]
+    fn test_packet_status_flow_control_change() {
+        let ctx = Context::openpty().unwrap();
+        ctx.state.lock().unwrap().packet = true;
+
+        let mut termios = match handle_tcgetattr(&ctx) {
+            PtySlaveResponse::GetAttr(TcGetAttrResponse { termios, .. }) => termios,
+            res => panic!("unexpected response {:?}", res),
+        };
+        let ixon = termios.c_iflag.contains(&TermiosInputMode::IXON);
+        termios.c_iflag.retain(|f| *f != TermiosInputMode::IXON);
+        if !ixon {
+            termios.c_iflag.push(TermiosInputMode::IXON);
+        }
+
+        let req = PtySlaveCallType::SetAttr(TcSetAttrCall {
+            optional_actions: TcSetAttrActions::TCSANOW,
+            termios,
+        });
+
+        // openpty defaults to ^S/^Q so toggling IXON changes flow control
+        assert_eq!(
+            packet_status(&ctx, &req),
+            Some(match ixon {
+                true => PacketStatusCall::TIOCPKT_NOSTOP,
+                false => PacketStatusCall::TIOCPKT_DOSTOP,
+            })
+        );
+    }
+}
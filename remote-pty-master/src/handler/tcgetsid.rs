@@ -0,0 +1,46 @@
+use remote_pty_common::{
+    log::debug,
+    proto::slave::{PtySlaveResponse, SidResponse, TcError},
+};
+
+use crate::context::Context;
+
+// the session is remote so the first registered process is
+// treated as the leader of the terminal's session
+pub fn handle_tcgetsid(ctx: &Context) -> PtySlaveResponse {
+    let state = ctx.state.lock().expect("failed to lock terminal state");
+
+    // @see https://pubs.opengroup.org/onlinepubs/007904975/functions/tcgetsid.html
+    // the terminal is not a controlling terminal without a session
+    let sid = match state.sid {
+        Some(sid) => sid as _,
+        None => return PtySlaveResponse::Error(TcError::ENOTTY),
+    };
+
+    debug(format!("returned session {}", sid));
+
+    PtySlaveResponse::GetSid(SidResponse { sid })
+}
+
+#[cfg(test)]
+mod tests {
+    use remote_pty_common::proto::slave::{PtySlaveResponse, SidResponse, TcError};
+
+    use crate::{context::Context, handler::handle_tcgetsid};
+
+    #[test]
+    fn test_tcgetsid() {
+        let ctx = Context::openpty().unwrap();
+        assert_eq!(
+            handle_tcgetsid(&ctx),
+            PtySlaveResponse::Error(TcError::ENOTTY)
+        );
+
+        ctx.state.lock().unwrap().sid = Some(123);
+
+        assert_eq!(
+            handle_tcgetsid(&ctx),
+            PtySlaveResponse::GetSid(SidResponse { sid: 123 })
+        );
+    }
+}
@@ -1,3 +1,4 @@
+pub mod conf;
 pub mod context;
 pub mod handler;
 pub mod server;
\ No newline at end of file
@@ -1,9 +1,13 @@
-use std::{env, fs};
+use std::env;
 
-use remote_pty_common::channel::transport::conf::TransportType;
+use remote_pty_common::channel::transport::conf::TransportConf;
 use remote_pty_master::{
+    conf::Conf,
     context::Context,
-    server::{listener::bind_listener, Server},
+    server::{
+        listener::{activated_listeners, bind_listener, connect_listener},
+        Server,
+    },
 };
 
 // runs the master side of the remote pty
@@ -15,26 +19,56 @@ use remote_pty_master::{
 // RPTY_DEBUG=1 cargo run --target x86_64-unknown-linux-musl -- unix:/tmp/pty.sock
 // run slave
 // RPTY_DEBUG=1 LD_PRELOAD=/tmp/x86_64-unknown-linux-gnu/release/libremote_pty_slave.linked.so RPTY_TRANSPORT=unix:/tmp/pty.sock bash
+//
+// or to connect to a slave listening with RPTY_TRANSPORT=tcp://0.0.0.0:7000?listen=1
+// cargo run -- --connect tcp:slave-host:7000
+//
+// any number of transports can be served together, along with
+// the sockets passed by systemd socket activation
+// cargo run -- --listen unix:/tmp/pty.sock --listen tcp:0.0.0.0:7000
 fn main() {
     if unsafe { libc::isatty(libc::STDIN_FILENO) } != 1 {
         panic!("stdin is not a tty");
     }
 
-    let mut args = env::args();
-    let _ = args.next();
-    let transport = args
-        .next()
-        .expect("expected pty transport")
-        .parse::<TransportType>()
-        .expect("could not parse transport");
+    let conf = Conf::from_env().unwrap_or_else(|e| panic!("could not parse conf: {}", e));
+    let mut listeners = activated_listeners(&conf)
+        .unwrap_or_else(|e| panic!("could not adopt activated sockets: {}", e));
+
+    // a bare transport is bound as with --listen
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        // the master connects to a listening slave in reverse mode
+        let (reverse, spec) = match arg.as_str() {
+            "--listen" => (
+                false,
+                args.next().expect("expected transport after --listen"),
+            ),
+            "--connect" => (
+                true,
+                args.next().expect("expected transport after --connect"),
+            ),
+            _ => (false, arg),
+        };
+
+        let transport = spec
+            .parse::<TransportConf>()
+            .expect("could not parse transport");
+
+        listeners.push(if reverse {
+            connect_listener(transport, &conf)
+                .unwrap_or_else(|e| panic!("could not connect to slave: {}", e))
+        } else {
+            bind_listener(transport, &conf)
+                .unwrap_or_else(|e| panic!("could not bind listener: {}", e))
+        });
+    }
 
-    if let TransportType::Unix(path) = &transport {
-        let _ = fs::remove_file(path);
+    if listeners.is_empty() {
+        panic!("expected pty transport");
     }
-    let listener =
-        bind_listener(transport).unwrap_or_else(|e| panic!("could not bind listener: {}", e));
 
     let ctx = Context::from_pair(libc::STDIN_FILENO, libc::STDIN_FILENO);
 
-    let _ = Server::new(ctx, listener).start().join();
+    let _ = Server::new(ctx, listeners, conf).start().join();
 }
@@ -10,13 +10,22 @@ use std::{
 use remote_pty_common::{
     channel::{Channel, RemoteChannel},
     log::debug,
-    proto::slave::{PtySlaveCall, PtySlaveCallType, PtySlaveResponse, TcError},
+    proto::slave::{
+        PtySlaveCall, PtySlaveCallType, PtySlaveResponse, RegisterProcessCall, TcError,
+    },
 };
 
-use super::{listener::Listener, Client, ClientEvent, ClientEventType, Event};
+use crate::conf::Conf;
+
+use super::{
+    listener::{Connection, Listener, MuxListener},
+    session::Session,
+    Client, ClientEvent, ClientEventType, Event, ResumeRequest,
+};
 
 pub(crate) struct Acceptor {
     listener: Box<dyn Listener + Send>,
+    conf: Conf,
     terminate: Arc<AtomicBool>,
     sender: Sender<Event>,
 }
@@ -24,11 +33,13 @@ pub(crate) struct Acceptor {
 impl Acceptor {
     pub(crate) fn new(
         listener: Box<dyn Listener + Send>,
+        conf: &Conf,
         terminate: &Arc<AtomicBool>,
         sender: &Sender<Event>,
     ) -> Self {
         Self {
             listener,
+            conf: conf.clone(),
             terminate: Arc::clone(terminate),
             sender: sender.clone(),
         }
@@ -42,7 +53,7 @@ impl Acceptor {
         while !self.terminate.load(Ordering::Relaxed) {
             let res = self.listener.accept();
 
-            let chan = match res {
+            let conn = match res {
                 Ok(c) => c,
                 Err(err) => {
                     debug(format!("error while accepting connection: {}", err));
@@ -51,54 +62,153 @@ impl Acceptor {
             };
 
             debug("received connection");
-            self.handle_connection(chan);
+            self.handle_connection(conn);
         }
 
         debug("terminating acceptor");
     }
 
-    fn handle_connection(&self, mut chan: RemoteChannel) {
+    fn handle_connection(&self, conn: Connection) {
         let sender = self.sender.clone();
+        let conf = self.conf.clone();
+        let terminate = Arc::clone(&self.terminate);
 
         thread::spawn(move || {
-            let chan_send = chan.clone();
-
-            let res = chan.receive::<PtySlaveCall, PtySlaveResponse, _>(Channel::PGRP, |req| {
-                let req = match req {
-                    PtySlaveCall {
-                        fd: _,
-                        typ: PtySlaveCallType::RegisterProcess(req),
-                    } => req,
-                    req => {
-                        debug(format!(
-                            "unexpected request while accepting connection: {:?}",
-                            req
-                        ));
-                        return PtySlaveResponse::Error(TcError::EIO);
-                    }
-                };
+            // a failed handshake only affects that client
+            let mut chan = match conn.establish() {
+                Ok(chan) => chan,
+                Err(err) => {
+                    debug(format!("failed to establish connection: {}", err));
+                    return;
+                }
+            };
 
-                let res = sender.send(Event::ClientEvent(ClientEvent {
-                    client_pid: req.pid,
-                    event: ClientEventType::Registered(Client {
-                        chan: chan_send,
-                        pgrp: req.pgrp,
+            chan.set_max_frame_size(conf.max_frame_size);
+            chan.set_compression(conf.compression);
+            chan.set_psk(conf.psk.clone());
+
+            // ensure the client speaks a compatible protocol and is
+            // authenticated before accepting any other messages
+            if let Err(err) = chan.accept_handshake() {
+                debug(format!("failed to complete handshake: {}", err));
+                return;
+            }
+
+            if let Some(keepalive) = conf.keepalive {
+                chan.start_keepalive(keepalive);
+            }
+
+            let req = match chan.receive_request::<PtySlaveCall>(Channel::PGRP) {
+                Ok(req) => req,
+                Err(err) => {
+                    debug(format!("failed to register new process: {}", err));
+                    return;
+                }
+            };
+
+            let (id, req) = (req.id, req.payload);
+
+            let res = match req.typ {
+                PtySlaveCallType::RegisterProcess(req) => {
+                    Self::register_process(&conf, &sender, chan.clone(), req, None)
+                }
+                PtySlaveCallType::RegisterSessionProcess(req) => Self::register_process(
+                    &conf,
+                    &sender,
+                    chan.clone(),
+                    RegisterProcessCall {
                         pid: req.pid,
-                    }),
-                }));
-
-                match res {
-                    Ok(_) => PtySlaveResponse::Success(0),
-                    Err(err) => {
-                        debug(format!("failed send registered event: {}", err));
-                        PtySlaveResponse::Error(TcError::EIO)
+                        pgrp: req.pgrp,
+                    },
+                    Some(req.sid),
+                ),
+                // the server validates the session and responds once
+                // the client has been reattached
+                PtySlaveCallType::ResumeSession(req) => {
+                    let res = sender.send(Event::ClientEvent(Box::new(ClientEvent {
+                        client_pid: req.pid,
+                        event: ClientEventType::Resume(ResumeRequest {
+                            chan: chan.clone(),
+                            id,
+                            req,
+                        }),
+                    })));
+
+                    match res {
+                        Ok(_) => return,
+                        Err(err) => {
+                            debug(format!("failed send resume event: {}", err));
+                            PtySlaveResponse::Error(TcError::EIO)
+                        }
                     }
                 }
-            });
+                // the processes multiplexed by the broker are accepted
+                // on this thread until its connection is closed
+                PtySlaveCallType::RegisterBroker => {
+                    if let Err(err) =
+                        chan.send_response(Channel::PGRP, id, PtySlaveResponse::Success(0))
+                    {
+                        debug(format!("failed to register broker: {}", err));
+                        return;
+                    }
+
+                    debug("accepting processes from broker");
+                    let listener = Box::new(MuxListener::new(chan));
+                    Self::new(listener, &conf, &terminate, &sender).work();
+                    return;
+                }
+                typ => {
+                    debug(format!(
+                        "unexpected request while accepting connection: {:?}",
+                        typ
+                    ));
+                    PtySlaveResponse::Error(TcError::EIO)
+                }
+            };
 
-            if let Err(err) = res {
+            if let Err(err) = chan.send_response(Channel::PGRP, id, res) {
                 debug(format!("failed to register new process: {}", err));
             }
         });
     }
+
+    fn register_process(
+        conf: &Conf,
+        sender: &Sender<Event>,
+        chan: RemoteChannel,
+        req: RegisterProcessCall,
+        sid: Option<u32>,
+    ) -> PtySlaveResponse {
+        // sessions are only created if the client is able to resume them
+        let (session, res) = match conf.resume_timeout {
+            Some(timeout) => {
+                let session = Session::new();
+                let res = PtySlaveResponse::Session(session.response(timeout));
+                (Some(session), res)
+            }
+            None => (None, PtySlaveResponse::Success(0)),
+        };
+
+        let sent = sender.send(Event::ClientEvent(Box::new(ClientEvent {
+            client_pid: req.pid,
+            event: ClientEventType::Registered(
+                Client {
+                    chan,
+                    pgrp: req.pgrp,
+                    pid: req.pid,
+                    sid,
+                    ctty: true,
+                },
+                session,
+            ),
+        })));
+
+        match sent {
+            Ok(_) => res,
+            Err(err) => {
+                debug(format!("failed send registered event: {}", err));
+                PtySlaveResponse::Error(TcError::EIO)
+            }
+        }
+    }
 }
@@ -1,60 +1,855 @@
-use std::{io::Result, net::TcpListener, os::unix::net::UnixListener};
+use std::{
+    env, fs,
+    io::{Error, ErrorKind, Result},
+    mem,
+    net::{Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    os::unix::{
+        net::{UnixListener, UnixStream},
+        prelude::{AsRawFd, FromRawFd, RawFd},
+    },
+    process,
+    sync::Arc,
+    thread,
+    time::Duration,
+};
 
-use remote_pty_common::channel::{
-    transport::{conf::TransportType, tcp::TcpTransport, unix_socket::UnixSocketTransport},
-    RemoteChannel,
+use remote_pty_common::{
+    channel::{
+        mux::Mux,
+        transport::{
+            cmd,
+            conf::{HostPort, TransportConf, TransportOptions, TransportType},
+            relay::{self, Role},
+            reverse::wait_ready,
+            tcp::{self, TcpTransport},
+            tls::{ServerConfig, TlsTransport},
+            unix_socket::{self, peer_cred, UnixSocketTransport},
+            vsock::{VsockListener, VsockStream, VsockTransport},
+            ws::WsTransport,
+            Transport,
+        },
+        RemoteChannel,
+    },
+    log::debug,
 };
+use socket2::{Domain, Socket, Type};
+
+use crate::conf::{Conf, UnixListenerConf};
+
+// max duration a client can take to complete the tls or websocket
+// handshake, websocket connections are not accepted while it is in progress
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+// max duration between attempts to connect to a listening slave
+const MAX_DIAL_BACKOFF: Duration = Duration::from_secs(2);
+
+// the first fd passed by systemd socket activation, following stdio
+const LISTEN_FDS_START: RawFd = 3;
 
 // generic listener interface to accept incoming connections to the server
 pub trait Listener {
-    fn accept(&mut self) -> Result<RemoteChannel>;
+    fn accept(&mut self) -> Result<Connection>;
+}
+
+// an accepted connection, the tls handshake is deferred so it runs on
+// the connection's thread rather than blocking the accept loop
+pub enum Connection {
+    Ready(RemoteChannel),
+    Tls(TcpStream, Arc<ServerConfig>, bool),
+}
+
+impl Connection {
+    // completes any pending handshake of the connection
+    pub fn establish(self) -> std::result::Result<RemoteChannel, String> {
+        match self {
+            Self::Ready(chan) => Ok(chan),
+            Self::Tls(socket, config, nodelay) => tls_handshake(socket, &config, nodelay),
+        }
+    }
 }
 
 pub struct UnixSocketListener {
     listener: UnixListener,
+    conf: UnixListenerConf,
 }
 
 impl UnixSocketListener {
-    pub fn new(listener: UnixListener) -> Self {
-        Self { listener }
+    pub fn new(listener: UnixListener, conf: UnixListenerConf) -> Self {
+        Self { listener, conf }
+    }
+
+    // binds the socket replacing any stale socket file, paths
+    // prefixed with @ are bound in the abstract namespace
+    pub fn bind(path: &str, conf: UnixListenerConf, backlog: i32) -> Result<Self> {
+        let addr = unix_socket::socket_addr(path)?;
+
+        if !path.starts_with('@') {
+            let _ = fs::remove_file(path);
+        }
+
+        let listener = match conf.mode {
+            // the umask is restricted while binding so the socket
+            // is never accessible with wider permissions
+            Some(mode) => {
+                let umask = unsafe { libc::umask(!mode & 0o777) };
+                let res = UnixListener::bind_addr(&addr);
+                unsafe { libc::umask(umask) };
+                res?
+            }
+            None => UnixListener::bind_addr(&addr)?,
+        };
+
+        // listening again updates the backlog of the bound socket
+        if unsafe { libc::listen(listener.as_raw_fd(), backlog) } != 0 {
+            return Err(Error::last_os_error());
+        }
+
+        Ok(Self::new(listener, conf))
+    }
+
+    fn check_peer(&self, socket: &UnixStream) -> std::result::Result<(), String> {
+        if self.conf.allows_any() {
+            return Ok(());
+        }
+
+        let cred =
+            peer_cred(socket).map_err(|e| format!("failed to get peer credentials: {}", e))?;
+
+        if !self.conf.is_allowed(cred.uid, cred.gid) {
+            return Err(format!(
+                "peer pid {} uid {} gid {} is not allowed",
+                cred.pid, cred.uid, cred.gid
+            ));
+        }
+
+        Ok(())
     }
 }
 
 impl Listener for UnixSocketListener {
-    fn accept(&mut self) -> Result<RemoteChannel> {
-        let (socket, _) = self.listener.accept()?;
+    fn accept(&mut self) -> Result<Connection> {
+        loop {
+            let (socket, _) = self.listener.accept()?;
 
-        Ok(RemoteChannel::new(UnixSocketTransport::new(socket)))
+            // rejected peers are closed before any message is read
+            match self.check_peer(&socket) {
+                Ok(_) => {
+                    let chan = RemoteChannel::new(UnixSocketTransport::new(socket));
+                    return Ok(Connection::Ready(chan));
+                }
+                Err(err) => debug(format!("rejected unix socket connection: {}", err)),
+            }
+        }
     }
 }
 
 pub struct TcpSocketListener {
     listener: TcpListener,
+    nodelay: bool,
 }
 
 impl TcpSocketListener {
-    pub fn new(listener: TcpListener) -> Self {
-        Self { listener }
+    pub fn new(listener: TcpListener, nodelay: bool) -> Self {
+        Self { listener, nodelay }
     }
 }
 
 impl Listener for TcpSocketListener {
-    fn accept(&mut self) -> Result<RemoteChannel> {
+    fn accept(&mut self) -> Result<Connection> {
         let (socket, _) = self.listener.accept()?;
+        socket.set_nodelay(self.nodelay)?;
+
+        Ok(Connection::Ready(RemoteChannel::new(TcpTransport::new(
+            socket,
+        ))))
+    }
+}
+
+pub struct VsockSocketListener {
+    listener: VsockListener,
+}
+
+impl VsockSocketListener {
+    pub fn new(listener: VsockListener) -> Self {
+        Self { listener }
+    }
+}
 
-        Ok(RemoteChannel::new(TcpTransport::new(socket)))
+impl Listener for VsockSocketListener {
+    fn accept(&mut self) -> Result<Connection> {
+        let (socket, cid) = self.listener.accept()?;
+        debug(format!("accepted vsock connection from cid {}", cid));
+
+        Ok(Connection::Ready(RemoteChannel::new(VsockTransport::new(
+            socket,
+        ))))
     }
 }
 
-pub fn bind_listener(transport: TransportType) -> Result<Box<dyn Listener + Send>> {
-    let listener = match transport {
-        TransportType::Unix(path) => {
-            Box::new(UnixSocketListener::new(UnixListener::bind(path)?)) as Box<dyn Listener + Send>
+pub struct TlsSocketListener {
+    listener: TcpListener,
+    config: Arc<ServerConfig>,
+    nodelay: bool,
+}
+
+impl TlsSocketListener {
+    pub fn new(listener: TcpListener, config: Arc<ServerConfig>, nodelay: bool) -> Self {
+        Self {
+            listener,
+            config,
+            nodelay,
         }
-        TransportType::Tcp(addr) => {
-            Box::new(TcpSocketListener::new(TcpListener::bind(addr)?)) as Box<dyn Listener + Send>
+    }
+}
+
+impl Listener for TlsSocketListener {
+    fn accept(&mut self) -> Result<Connection> {
+        let (socket, addr) = self.listener.accept()?;
+        debug(format!("accepted tls connection from {}", addr));
+
+        Ok(Connection::Tls(
+            socket,
+            Arc::clone(&self.config),
+            self.nodelay,
+        ))
+    }
+}
+
+// accepts websocket upgrades on the path, over tls if configured
+pub struct WsSocketListener {
+    listener: TcpListener,
+    path: String,
+    tls: Option<Arc<ServerConfig>>,
+    nodelay: bool,
+}
+
+impl WsSocketListener {
+    pub fn new(
+        listener: TcpListener,
+        path: String,
+        tls: Option<Arc<ServerConfig>>,
+        nodelay: bool,
+    ) -> Self {
+        Self {
+            listener,
+            path,
+            tls,
+            nodelay,
+        }
+    }
+}
+
+impl Listener for WsSocketListener {
+    fn accept(&mut self) -> Result<Connection> {
+        loop {
+            let (socket, addr) = self.listener.accept()?;
+
+            // a failed upgrade only affects that client
+            match ws_handshake(socket, &self.path, self.tls.as_ref(), self.nodelay) {
+                Ok(chan) => return Ok(Connection::Ready(chan)),
+                Err(err) => debug(format!("websocket upgrade from {} failed: {}", addr, err)),
+            }
+        }
+    }
+}
+
+// accepts the processes a slave's broker multiplexes over its connection
+pub struct MuxListener {
+    mux: Mux,
+}
+
+impl MuxListener {
+    pub fn new(chan: RemoteChannel) -> Self {
+        Self {
+            mux: Mux::new(chan),
+        }
+    }
+}
+
+impl Listener for MuxListener {
+    fn accept(&mut self) -> Result<Connection> {
+        let (pid, stream) = self
+            .mux
+            .accept()
+            .map_err(|e| Error::new(ErrorKind::ConnectionAborted, e))?;
+        debug(format!("broker opened stream for pid {}", pid));
+
+        Ok(Connection::Ready(RemoteChannel::new(stream)))
+    }
+}
+
+// connects to a listening slave rather than accepting connections, used when
+// only the slave host can accept inbound connections or through a relay when
+// neither host can. each connection is idle until a slave process accepts it,
+// so only one is open at a time
+pub struct SlaveDialer {
+    transport: TransportConf,
+    tls: Option<Arc<ServerConfig>>,
+}
+
+impl SlaveDialer {
+    pub fn new(transport: TransportConf, tls: Option<Arc<ServerConfig>>) -> Self {
+        Self { transport, tls }
+    }
+
+    fn dial(&self) -> std::result::Result<RemoteChannel, String> {
+        let options = &self.transport.options;
+        let ready = |res: Result<()>| res.map_err(|e| format!("slave did not accept: {}", e));
+        let connect_tcp = |addr: &HostPort| {
+            let mut socket = tcp::connect(addr, options)
+                .map_err(|e| format!("failed to connect to {}: {}", addr, e))?;
+            ready(wait_ready(&mut socket))?;
+            Ok::<_, String>(socket)
+        };
+        let tls = || {
+            self.tls
+                .as_ref()
+                .ok_or_else(|| "tls transport requires RPTY_TLS_CERT and RPTY_TLS_KEY".to_string())
+        };
+
+        match &self.transport.typ {
+            TransportType::Unix(path) => {
+                let mut socket = unix_socket::socket_addr(path)
+                    .and_then(|a| UnixStream::connect_addr(&a))
+                    .map_err(|e| format!("failed to connect to {}: {}", path, e))?;
+                ready(wait_ready(&mut socket))?;
+
+                Ok(RemoteChannel::new(UnixSocketTransport::new(socket)))
+            }
+            TransportType::Tcp(addr) => {
+                Ok(RemoteChannel::new(TcpTransport::new(connect_tcp(addr)?)))
+            }
+            TransportType::Tls(addr) => tls_handshake(connect_tcp(addr)?, tls()?, options.nodelay),
+            TransportType::Ws(addr, path) => {
+                ws_handshake(connect_tcp(addr)?, path, None, options.nodelay)
+            }
+            TransportType::Wss(addr, path) => {
+                ws_handshake(connect_tcp(addr)?, path, Some(tls()?), options.nodelay)
+            }
+            TransportType::Vsock(cid, port) => {
+                let mut socket = VsockStream::connect(*cid, *port)
+                    .map_err(|e| format!("failed to connect to vsock {}:{}: {}", cid, port, e))?;
+                ready(wait_ready(&mut socket))?;
+
+                Ok(RemoteChannel::new(VsockTransport::new(socket)))
+            }
+            // the helper's stdio is connected to the slave's listener
+            TransportType::Cmd(cmdline) => {
+                let mut socket = cmd::spawn(cmdline)
+                    .map_err(|e| format!("failed to spawn {}: {}", cmdline, e))?;
+                ready(wait_ready(&mut socket))?;
+
+                Ok(RemoteChannel::new(UnixSocketTransport::new(socket)))
+            }
+            // the relay pairs the connection with a slave process
+            TransportType::Relay(addr, key) => {
+                let socket = relay::connect(addr, key, Role::Master, options)
+                    .map_err(|e| format!("failed to connect to relay {}: {}", addr, e))?;
+
+                match options.tls {
+                    true => tls_handshake(socket, tls()?, options.nodelay),
+                    false => Ok(RemoteChannel::new(TcpTransport::new(socket))),
+                }
+            }
+            TransportType::Fd(_) => {
+                Err("the transport cannot be used to connect to slaves".to_string())
+            }
+        }
+    }
+}
+
+impl Listener for SlaveDialer {
+    fn accept(&mut self) -> Result<Connection> {
+        let mut backoff = Duration::from_millis(100);
+
+        // the slave may not be listening yet or between sessions
+        loop {
+            match self.dial() {
+                Ok(chan) => return Ok(Connection::Ready(chan)),
+                Err(err) => {
+                    debug(format!("failed to connect to slave, retrying: {}", err));
+                    thread::sleep(backoff);
+                    backoff = (backoff * 2).min(MAX_DIAL_BACKOFF);
+                }
+            }
+        }
+    }
+}
+
+// performs the tls handshake as the server
+fn tls_handshake(
+    socket: TcpStream,
+    config: &Arc<ServerConfig>,
+    nodelay: bool,
+) -> std::result::Result<RemoteChannel, String> {
+    let clone = || {
+        socket
+            .try_clone()
+            .map_err(|e| format!("failed to clone socket: {}", e))
+    };
+
+    socket
+        .set_nodelay(nodelay)
+        .map_err(|e| format!("failed to set nodelay: {}", e))?;
+    socket
+        .set_read_timeout(Some(HANDSHAKE_TIMEOUT))
+        .map_err(|e| format!("failed to set timeout: {}", e))?;
+    let transport = TlsTransport::accept(clone()?, clone()?, Arc::clone(config))?;
+    socket
+        .set_read_timeout(None)
+        .map_err(|e| format!("failed to clear timeout: {}", e))?;
+
+    Ok(RemoteChannel::new(transport.with_shutdown(move || {
+        let _ = socket.shutdown(Shutdown::Both);
+    })))
+}
+
+// accepts the websocket upgrade on the path, over tls if configured
+fn ws_handshake(
+    socket: TcpStream,
+    path: &str,
+    tls: Option<&Arc<ServerConfig>>,
+    nodelay: bool,
+) -> std::result::Result<RemoteChannel, String> {
+    let clone = || {
+        socket
+            .try_clone()
+            .map_err(|e| format!("failed to clone socket: {}", e))
+    };
+
+    socket
+        .set_nodelay(nodelay)
+        .map_err(|e| format!("failed to set nodelay: {}", e))?;
+    socket
+        .set_read_timeout(Some(HANDSHAKE_TIMEOUT))
+        .map_err(|e| format!("failed to set timeout: {}", e))?;
+    let transport = match tls {
+        Some(config) => {
+            let (read, write) =
+                TlsTransport::accept(clone()?, clone()?, Arc::clone(config))?.split();
+            WsTransport::accept(read, write, path)?
+        }
+        None => {
+            // boxed to match the type of the tls streams
+            let (read, write) = (Box::new(clone()?) as _, Box::new(clone()?) as _);
+            WsTransport::accept(read, write, path)?
+        }
+    };
+    socket
+        .set_read_timeout(None)
+        .map_err(|e| format!("failed to clear timeout: {}", e))?;
+
+    Ok(RemoteChannel::new(transport.with_shutdown(move || {
+        let _ = socket.shutdown(Shutdown::Both);
+    })))
+}
+
+// binds the first address the host resolves to which is available
+fn bind_tcp(addr: &HostPort, options: &TransportOptions) -> Result<TcpListener> {
+    let mut last_err = None;
+
+    for sock_addr in addr.to_socket_addrs()? {
+        match bind_tcp_addr(sock_addr, options) {
+            Ok(listener) => return Ok(listener),
+            Err(err) => {
+                debug(format!("failed to bind {}: {}", sock_addr, err));
+                last_err = Some(err);
+            }
+        }
+    }
+
+    Err(last_err.unwrap_or_else(|| {
+        Error::new(
+            ErrorKind::NotFound,
+            format!("{} did not resolve to any addresses", addr),
+        )
+    }))
+}
+
+fn bind_tcp_addr(addr: SocketAddr, options: &TransportOptions) -> Result<TcpListener> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, None)?;
+    socket.set_reuse_address(options.reuseaddr)?;
+    socket.bind(&addr.into())?;
+    socket.listen(options.backlog)?;
+
+    Ok(socket.into())
+}
+
+fn tls_server_config(conf: &Conf) -> Result<Arc<ServerConfig>> {
+    conf.tls
+        .as_ref()
+        .ok_or_else(|| {
+            Error::new(
+                ErrorKind::InvalidInput,
+                "tls transport requires RPTY_TLS_CERT and RPTY_TLS_KEY",
+            )
+        })?
+        .server_config()
+        .map_err(|e| Error::new(ErrorKind::InvalidInput, e))
+}
+
+// connects to slaves listening on the transport rather than binding it
+pub fn connect_listener(transport: TransportConf, conf: &Conf) -> Result<Box<dyn Listener + Send>> {
+    let tls = match transport.typ {
+        TransportType::Tls(_) | TransportType::Wss(..) => Some(tls_server_config(conf)?),
+        TransportType::Relay(..) if transport.options.tls => Some(tls_server_config(conf)?),
+        TransportType::Fd(_) => {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "the transport cannot be used to connect to slaves",
+            ))
+        }
+        _ => None,
+    };
+
+    Ok(Box::new(SlaveDialer::new(transport, tls)))
+}
+
+pub fn bind_listener(transport: TransportConf, conf: &Conf) -> Result<Box<dyn Listener + Send>> {
+    // both sides connect out to the relay so the master never listens
+    if let TransportType::Relay(..) = transport.typ {
+        return connect_listener(transport, conf);
+    }
+
+    let options = transport.options;
+
+    let listener = match transport.typ {
+        TransportType::Unix(path) => Box::new(UnixSocketListener::bind(
+            &path,
+            conf.unix.clone(),
+            options.backlog,
+        )?) as Box<dyn Listener + Send>,
+        TransportType::Tcp(addr) => Box::new(TcpSocketListener::new(
+            bind_tcp(&addr, &options)?,
+            options.nodelay,
+        )) as Box<dyn Listener + Send>,
+        TransportType::Tls(addr) => Box::new(TlsSocketListener::new(
+            bind_tcp(&addr, &options)?,
+            tls_server_config(conf)?,
+            options.nodelay,
+        )) as Box<dyn Listener + Send>,
+        TransportType::Ws(addr, path) => Box::new(WsSocketListener::new(
+            bind_tcp(&addr, &options)?,
+            path,
+            None,
+            options.nodelay,
+        )) as Box<dyn Listener + Send>,
+        TransportType::Wss(addr, path) => Box::new(WsSocketListener::new(
+            bind_tcp(&addr, &options)?,
+            path,
+            Some(tls_server_config(conf)?),
+            options.nodelay,
+        )) as Box<dyn Listener + Send>,
+        TransportType::Vsock(cid, port) => {
+            let listener = VsockListener::bind(cid, port, options.backlog)?;
+
+            Box::new(VsockSocketListener::new(listener)) as Box<dyn Listener + Send>
+        }
+        TransportType::Cmd(_) | TransportType::Fd(_) | TransportType::Relay(..) => {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "the transport can only be used to connect to the master",
+            ))
         }
     };
 
     Ok(listener)
 }
+
+// adopts the sockets passed by systemd socket activation. the variables
+// are removed so they are not inherited by processes of the master
+pub fn activated_listeners(conf: &Conf) -> Result<Vec<Box<dyn Listener + Send>>> {
+    let pid = env::var("LISTEN_PID").ok();
+    let fds = env::var("LISTEN_FDS").ok();
+
+    env::remove_var("LISTEN_PID");
+    env::remove_var("LISTEN_FDS");
+    env::remove_var("LISTEN_FDNAMES");
+
+    (0..listen_fds(pid, fds)?)
+        .map(|i| adopt_listener(LISTEN_FDS_START + i as RawFd, conf))
+        .collect()
+}
+
+// the number of sockets passed to this process, the fds were
+// passed to another process if the pid does not match
+fn listen_fds(pid: Option<String>, fds: Option<String>) -> Result<u32> {
+    let (pid, fds) = match (pid, fds) {
+        (Some(pid), Some(fds)) => (pid, fds),
+        _ => return Ok(0),
+    };
+
+    if pid.parse::<u32>().ok() != Some(process::id()) {
+        debug(format!("ignoring sockets passed to pid {}", pid));
+        return Ok(0);
+    }
+
+    match fds.parse::<u32>() {
+        Ok(count) if count > 0 => Ok(count),
+        _ => Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("invalid number of sockets in LISTEN_FDS: {}", fds),
+        )),
+    }
+}
+
+// wraps an inherited listening socket according to its address family,
+// tcp sockets are served over tls if a certificate is configured
+fn adopt_listener(fd: RawFd, conf: &Conf) -> Result<Box<dyn Listener + Send>> {
+    // inherited fds are not closed on exec
+    if unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) } != 0 {
+        return Err(Error::last_os_error());
+    }
+
+    let listener = match socket_domain(fd)? {
+        libc::AF_UNIX => Box::new(UnixSocketListener::new(
+            unsafe { UnixListener::from_raw_fd(fd) },
+            conf.unix.clone(),
+        )) as Box<dyn Listener + Send>,
+        libc::AF_INET | libc::AF_INET6 => {
+            let listener = unsafe { TcpListener::from_raw_fd(fd) };
+            let nodelay = TransportOptions::default().nodelay;
+
+            match conf.tls {
+                Some(_) => Box::new(TlsSocketListener::new(
+                    listener,
+                    tls_server_config(conf)?,
+                    nodelay,
+                )) as Box<dyn Listener + Send>,
+                None => Box::new(TcpSocketListener::new(listener, nodelay)),
+            }
+        }
+        libc::AF_VSOCK => Box::new(VsockSocketListener::new(unsafe {
+            VsockListener::from_raw_fd(fd)
+        })) as Box<dyn Listener + Send>,
+        domain => {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("unsupported address family {} of fd {}", domain, fd),
+            ))
+        }
+    };
+
+    debug(format!("adopted activated socket fd {}", fd));
+    Ok(listener)
+}
+
+fn socket_domain(fd: RawFd) -> Result<libc::c_int> {
+    let mut domain: libc::c_int = 0;
+    let mut len = mem::size_of::<libc::c_int>() as libc::socklen_t;
+
+    let res = unsafe {
+        libc::getsockopt(
+            fd,
+            libc::SOL_SOCKET,
+            libc::SO_DOMAIN,
+            &mut domain as *mut libc::c_int as *mut libc::c_void,
+            &mut len,
+        )
+    };
+
+    if res != 0 {
+        return Err(Error::last_os_error());
+    }
+
+    Ok(domain)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs,
+        net::{TcpListener, TcpStream},
+        os::unix::{
+            fs::PermissionsExt,
+            net::{UnixListener, UnixStream},
+            prelude::IntoRawFd,
+        },
+        thread,
+    };
+
+    use remote_pty_common::channel::transport::{
+        conf::{HostPort, TransportOptions, DEFAULT_BACKLOG},
+        reverse::notify_ready,
+        tls::TlsServerConf,
+        unix_socket,
+        ws::WsTransport,
+    };
+
+    use crate::conf::{Conf, UnixListenerConf};
+
+    use super::{
+        adopt_listener, bind_tcp, listen_fds, Listener, SlaveDialer, UnixSocketListener,
+        WsSocketListener,
+    };
+
+    fn listener(conf: UnixListenerConf) -> UnixSocketListener {
+        let path = format!("@rpty-test-listener-{}", rand::random::<u64>());
+
+        UnixSocketListener::bind(&path, conf, DEFAULT_BACKLOG).unwrap()
+    }
+
+    #[test]
+    fn test_bind_abstract() {
+        let path = format!("@rpty-test-abstract-{}", rand::random::<u64>());
+        let listener =
+            UnixSocketListener::bind(&path, UnixListenerConf::default(), DEFAULT_BACKLOG).unwrap();
+
+        let addr = unix_socket::socket_addr(&path).unwrap();
+        let _client = UnixStream::connect_addr(&addr).unwrap();
+
+        assert!(listener.listener.accept().is_ok());
+    }
+
+    #[test]
+    fn test_bind_mode() {
+        let path = format!("/tmp/rpty-test-mode-{}.sock", rand::random::<u64>());
+        let conf = UnixListenerConf {
+            mode: Some(0o600),
+            ..UnixListenerConf::default()
+        };
+
+        // stale sockets are replaced
+        let _stale = UnixListener::bind(&path).unwrap();
+        let _listener = UnixSocketListener::bind(&path, conf, DEFAULT_BACKLOG).unwrap();
+
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(mode & 0o777, 0o600);
+    }
+
+    #[test]
+    fn test_check_peer_allowed() {
+        let uid = unsafe { libc::getuid() };
+        let listener = listener(UnixListenerConf {
+            mode: None,
+            allowed_uids: vec![uid],
+            allowed_gids: vec![],
+        });
+
+        let (socket, _peer) = UnixStream::pair().unwrap();
+
+        assert_eq!(listener.check_peer(&socket), Ok(()));
+    }
+
+    #[test]
+    fn test_check_peer_rejected() {
+        let (uid, gid) = unsafe { (libc::getuid(), libc::getgid()) };
+        let listener = listener(UnixListenerConf {
+            mode: None,
+            allowed_uids: vec![uid + 1],
+            allowed_gids: vec![gid + 1],
+        });
+
+        let (socket, _peer) = UnixStream::pair().unwrap();
+
+        assert!(listener.check_peer(&socket).is_err());
+    }
+
+    #[test]
+    fn test_bind_tcp_options() {
+        let addr = HostPort {
+            host: "localhost".to_string(),
+            port: 0,
+        };
+        let options = TransportOptions {
+            backlog: 1,
+            reuseaddr: false,
+            ..TransportOptions::default()
+        };
+
+        let listener = bind_tcp(&addr, &options).unwrap();
+        let socket = socket2::SockRef::from(&listener);
+
+        assert!(!socket.reuse_address().unwrap());
+    }
+
+    #[test]
+    fn test_ws_listener_skips_failed_upgrades() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = HostPort {
+            host: "127.0.0.1".to_string(),
+            port: listener.local_addr().unwrap().port(),
+        };
+        let mut listener = WsSocketListener::new(listener, "/pty".to_string(), None, true);
+
+        let client_thread = thread::spawn(move || {
+            let connect = |path| {
+                let socket = TcpStream::connect((addr.host.as_str(), addr.port)).unwrap();
+                WsTransport::connect(socket.try_clone().unwrap(), socket, &addr, path)
+            };
+
+            (connect("/other").is_err(), connect("/pty").is_ok())
+        });
+
+        assert!(listener.accept().is_ok());
+        assert_eq!(client_thread.join().unwrap(), (true, true));
+    }
+
+    #[test]
+    fn test_slave_dialer_waits_for_ready() {
+        let path = format!("@rpty-test-dialer-{}", rand::random::<u64>());
+        let listener = UnixListener::bind_addr(&unix_socket::socket_addr(&path).unwrap()).unwrap();
+        let mut dialer = SlaveDialer::new(format!("unix:{}", path).parse().unwrap(), None);
+
+        let slave_thread = thread::spawn(move || {
+            // connections closed before the slave is ready are retried
+            drop(listener.accept().unwrap());
+
+            let (mut socket, _) = listener.accept().unwrap();
+            notify_ready(&mut socket).unwrap();
+            socket
+        });
+
+        assert!(dialer.accept().is_ok());
+        slave_thread.join().unwrap();
+    }
+
+    #[test]
+    fn test_adopt_listener() {
+        let tcp = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = tcp.local_addr().unwrap().port();
+        let mut listener = adopt_listener(tcp.into_raw_fd(), &Conf::default()).unwrap();
+
+        let _client = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        assert!(listener.accept().is_ok());
+
+        let path = format!("@rpty-test-adopt-{}", rand::random::<u64>());
+        let addr = unix_socket::socket_addr(&path).unwrap();
+        let unix = UnixListener::bind_addr(&addr).unwrap();
+        let mut listener = adopt_listener(unix.into_raw_fd(), &Conf::default()).unwrap();
+
+        let _client = UnixStream::connect_addr(&addr).unwrap();
+        assert!(listener.accept().is_ok());
+    }
+
+    #[test]
+    fn test_adopt_listener_tls() {
+        let conf = Conf {
+            tls: Some(TlsServerConf {
+                cert: "/nonexistent/cert.pem".to_string(),
+                key: "/nonexistent/key.pem".to_string(),
+                client_ca: None,
+            }),
+            ..Conf::default()
+        };
+
+        // tcp sockets are not served without tls when it is configured
+        let tcp = TcpListener::bind("127.0.0.1:0").unwrap();
+        assert!(adopt_listener(tcp.into_raw_fd(), &conf).is_err());
+    }
+
+    #[test]
+    fn test_listen_fds() {
+        let pid = Some(std::process::id().to_string());
+        let fds = |n: &str| Some(n.to_string());
+
+        assert_eq!(listen_fds(pid.clone(), fds("2")).unwrap(), 2);
+        assert_eq!(listen_fds(None, fds("2")).unwrap(), 0);
+        assert_eq!(listen_fds(Some("1".to_string()), fds("2")).unwrap(), 0);
+        assert!(listen_fds(pid.clone(), fds("0")).is_err());
+        assert!(listen_fds(pid.clone(), fds("-1")).is_err());
+        assert!(listen_fds(pid, fds("two")).is_err());
+    }
+}
@@ -1,8 +1,10 @@
 pub mod acceptor;
 pub mod listener;
 pub mod pty;
+pub mod session;
 pub mod signal;
 pub mod stdin;
+pub mod stream;
 
 use std::{
     collections::HashMap,
@@ -13,34 +15,50 @@ use std::{
         Arc,
     },
     thread::{self, JoinHandle},
+    time::Instant,
 };
 
 use remote_pty_common::{
     channel::{Channel, RemoteChannel},
     log::debug,
     proto::{
-        master::{PtyMasterCall, PtyMasterResponse, PtyMasterSignal, SignalCall, WriteStdinCall},
+        master::{
+            PacketStatusCall, PtyMasterCall, PtyMasterResponse, PtyMasterSignal, SignalCall,
+            WriteStdinCall,
+        },
         slave::{
-            PtySlaveCall, PtySlaveCallType, PtySlaveResponse, SetProcessGroupCall, TcError,
+            IoctlCall, PtySlaveCall, PtySlaveCallType, PtySlaveResponse, ResumeSessionCall,
+            SetCttyCall, SetProcessGroupCall, TcError, TcGetAttrResponse, TiocStiCall,
             WriteStdoutCall,
         },
+        Message, StreamCredit, TermiosLocalMode,
     },
 };
 
-use crate::{context::Context, handler::RemotePtyHandlers};
+use crate::{
+    conf::Conf,
+    context::Context,
+    handler::{handle_tcgetattr, packet_status, RemotePtyHandlers},
+};
 
 use self::{
-    acceptor::Acceptor, listener::Listener, pty::ClientPtyListener, signal::SignalWatcher,
-    stdin::StdinReader,
+    acceptor::Acceptor, listener::Listener, pty::ClientPtyListener, session::Session,
+    signal::SignalWatcher, stdin::StdinReader, stream::ClientStreams,
 };
 
 pub struct Server {
     // server state
     ctx: Context,
-    // listener
-    listener: Option<Box<dyn Listener + Send>>,
+    // server configuration
+    conf: Conf,
+    // listeners, each served by its own acceptor
+    listeners: Vec<Box<dyn Listener + Send>>,
     // list of clients indexed by pid
     clients: HashMap<u32, Client>,
+    // resumable sessions indexed by client pid
+    sessions: HashMap<u32, Session>,
+    // flow control state of client streams indexed by client pid
+    streams: HashMap<u32, ClientStreams>,
     // event sender
     sender: Sender<Event>,
     // event receiver
@@ -63,12 +81,17 @@ pub struct Client {
     chan: RemoteChannel,
     pid: u32,
     pgrp: u32,
+    // the session of the process if reported by the client,
+    // otherwise it is assumed to be the terminal's session
+    sid: Option<u32>,
+    // cleared once the process releases the terminal with TIOCNOTTY
+    ctty: bool,
 }
 
 pub enum Event {
     Stdin(Vec<u8>),
     Signal(PtyMasterSignal),
-    ClientEvent(ClientEvent),
+    ClientEvent(Box<ClientEvent>),
     Terminate,
 }
 
@@ -78,9 +101,19 @@ pub struct ClientEvent {
 }
 
 pub enum ClientEventType {
-    Registered(Client),
-    Call(PtySlaveCall),
+    Registered(Client, Option<Session>),
+    Resume(ResumeRequest),
+    Call(Message<PtySlaveCall>),
     Terminated,
+    // the client did not resume its session within the timeout
+    ResumeExpired(Instant),
+}
+
+pub struct ResumeRequest {
+    // the new connection from the client
+    pub chan: RemoteChannel,
+    pub id: u32,
+    pub req: ResumeSessionCall,
 }
 
 enum EventHandleResult {
@@ -91,13 +124,16 @@ enum EventHandleResult {
 }
 
 impl Server {
-    pub fn new(ctx: Context, listener: Box<dyn Listener + Send>) -> Self {
+    pub fn new(ctx: Context, listeners: Vec<Box<dyn Listener + Send>>, conf: Conf) -> Self {
         let (sender, receiver) = channel();
 
         Self {
             ctx,
-            listener: Some(listener),
+            conf,
+            listeners,
             clients: HashMap::new(),
+            sessions: HashMap::new(),
+            streams: HashMap::new(),
             sender,
             receiver,
             terminate: Arc::new(AtomicBool::new(false)),
@@ -117,7 +153,9 @@ impl Server {
     }
 
     fn work(mut self) {
-        Acceptor::new(self.listener.take().unwrap(), &self.terminate, &self.sender).start();
+        for listener in self.listeners.drain(..) {
+            Acceptor::new(listener, &self.conf, &self.terminate, &self.sender).start();
+        }
         StdinReader::new(&self.terminate, &self.sender).start();
         SignalWatcher::new(&self.terminate, &self.sender).start();
 
@@ -133,7 +171,7 @@ impl Server {
             let res = match evt {
                 Event::Stdin(data) => self.handle_stdin(data),
                 Event::Signal(sig) => self.handle_signal(sig),
-                Event::ClientEvent(cevt) => self.handle_client_event(cevt),
+                Event::ClientEvent(cevt) => self.handle_client_event(*cevt),
                 Event::Terminate => return,
             };
 
@@ -155,8 +193,8 @@ impl Server {
             .map(|i| i.clone())
     }
 
-    fn handle_stdin(&self, data: Vec<u8>) -> EventHandleResult {
-        let mut client = match self.get_active_client() {
+    fn handle_stdin(&mut self, data: Vec<u8>) -> EventHandleResult {
+        let client = match self.get_active_client() {
             Some(c) => c,
             None => {
                 debug("attempted write to stdin while no active pgrp, discarding");
@@ -164,16 +202,58 @@ impl Server {
             }
         };
 
-        let res = client.chan.send::<PtyMasterCall, PtyMasterResponse>(
-            Channel::STDIN,
-            PtyMasterCall::WriteStdin(WriteStdinCall { data }),
-        );
+        // retain the data until acknowledged so it can be replayed
+        // if the client resumes its session
+        if let Some(session) = self.sessions.get_mut(&client.pid) {
+            session.stdin.push(data.as_slice());
 
-        match res {
-            Ok(PtyMasterResponse::WriteSuccess) => EventHandleResult::Success,
-            Ok(res) => self.unexpected_result(client, res),
-            Err(err) => self.client_error(client, err),
+            if session.disconnected_at.is_some() {
+                debug("client is disconnected, buffering stdin");
+                return EventHandleResult::Success;
+            }
+        }
+
+        self.write_stdin(client, data)
+    }
+
+    fn write_stdin(&mut self, client: Client, data: Vec<u8>) -> EventHandleResult {
+        self.streams(client.pid).stdin_pending.extend(data);
+
+        self.flush_stdin(client)
+    }
+
+    // sends as much of the pending stdin as the client has granted credit for
+    fn flush_stdin(&mut self, mut client: Client) -> EventHandleResult {
+        while let Some(data) = self.streams(client.pid).next_stdin_chunk() {
+            let res = client.chan.notify(
+                Channel::STDIN,
+                PtyMasterCall::WriteStdin(WriteStdinCall { data }),
+            );
+
+            if let Err(err) = res {
+                return self.client_error(client, err);
+            }
         }
+
+        EventHandleResult::Success
+    }
+
+    fn handle_stdin_credit(&mut self, client: Client, credit: StreamCredit) -> EventHandleResult {
+        self.streams(client.pid).stdin.grant(credit);
+
+        if let Some(session) = self.sessions.get_mut(&client.pid) {
+            session.stdin.ack(credit.consumed);
+        }
+
+        self.flush_stdin(client)
+    }
+
+    fn streams(&mut self, pid: u32) -> &mut ClientStreams {
+        let window = self.conf.stream_window;
+
+        self.streams
+            .entry(pid)
+            .or_insert_with(|| ClientStreams::new(window))
     }
 
     fn handle_signal(&self, signal: PtyMasterSignal) -> EventHandleResult {
@@ -194,6 +274,14 @@ impl Server {
             }
         };
 
+        if !client.chan.capabilities().supports_signal(&signal) {
+            debug(format!(
+                "client does not support signal {:?}, ignoring",
+                signal
+            ));
+            return EventHandleResult::ErrorIgnore;
+        }
+
         let pgrp = {
             let state = self.ctx.state.lock().unwrap();
             state.pgrp.unwrap_or(client.pgrp)
@@ -212,10 +300,14 @@ impl Server {
     }
 
     fn handle_client_event(&mut self, cevt: ClientEvent) -> EventHandleResult {
-        if let ClientEventType::Registered(client) = cevt.event {
-            self.register_client(client);
-            return EventHandleResult::Success;
-        }
+        let cevt = match cevt.event {
+            ClientEventType::Registered(client, session) => {
+                self.register_client(client, session);
+                return EventHandleResult::Success;
+            }
+            ClientEventType::Resume(req) => return self.resume_client(cevt.client_pid, req),
+            _ => cevt,
+        };
 
         let client = match self.clients.get(&cevt.client_pid) {
             Some(c) => c.clone(),
@@ -229,64 +321,133 @@ impl Server {
         };
 
         match cevt.event {
+            // ignore listeners of a connection which has since been replaced
+            ClientEventType::Terminated if !client.chan.is_closed() => EventHandleResult::Success,
             ClientEventType::Terminated => {
-                self.remove_client(client.pid);
+                self.disconnect_client(client.pid);
                 EventHandleResult::Success
             }
-            ClientEventType::Call(PtySlaveCall {
-                typ: PtySlaveCallType::SetProcessGroup(req),
-                fd: _,
+            ClientEventType::ResumeExpired(disconnected_at) => {
+                let session = self.sessions.get(&client.pid);
+
+                if session.and_then(|s| s.disconnected_at) == Some(disconnected_at) {
+                    debug(format!("client {} did not resume session", client.pid));
+                    self.remove_client(client.pid);
+                }
+
+                EventHandleResult::Success
+            }
+            ClientEventType::Call(Message {
+                id,
+                payload:
+                    PtySlaveCall {
+                        typ: PtySlaveCallType::SetProcessGroup(req),
+                        fd: _,
+                    },
             }) => {
-                self.handle_set_process_group(client, req);
+                self.handle_set_process_group(client, id, req);
                 EventHandleResult::Success
             }
             ClientEventType::Call(req) => self.handle_pty_call(client, req),
-            ClientEventType::Registered(_) => unreachable!(),
+            ClientEventType::Registered(..) | ClientEventType::Resume(_) => unreachable!(),
         }
     }
 
-    fn handle_pty_call(&self, mut client: Client, req: PtySlaveCall) -> EventHandleResult {
+    fn handle_pty_call(
+        &mut self,
+        mut client: Client,
+        req: Message<PtySlaveCall>,
+    ) -> EventHandleResult {
+        let Message { id, payload: req } = req;
         let active_client = self.get_active_client();
+        let ctty = self.has_ctty(&client);
+
+        // the kernel fails these calls for processes in other sessions
+        if req.typ.requires_ctty() && !ctty {
+            debug(format!(
+                "received request from pid {} without the controlling terminal: {:?}",
+                client.pid, req
+            ));
+
+            return match client.chan.send_response(
+                Channel::PTY,
+                id,
+                PtySlaveResponse::Error(TcError::ENOTTY),
+            ) {
+                Ok(_) => EventHandleResult::ErrorIgnore,
+                Err(err) => self.client_error(client, err),
+            };
+        }
 
-        // send signal to naughty procs
-        if req.typ.must_be_foreground()
+        // stdout is streamed from a pipe so the process may have
+        // changed its pgrp since the data was written
+        let pgrp = match &req.typ {
+            PtySlaveCallType::WriteStdout(req) => req.pgrp,
+            _ => client.pgrp,
+        };
+
+        // send signal to naughty procs, job control only
+        // applies to processes in the terminal's session
+        if ctty
+            && req.typ.must_be_foreground()
             && active_client.is_some()
-            && active_client.unwrap().pgrp != client.pgrp
+            && active_client.unwrap().pgrp != pgrp
+            && (!matches!(req.typ, PtySlaveCallType::WriteStdout(_)) || self.tostop())
         {
             debug(format!(
                 "received invalid request from background pgrp {}: {:?}",
-                client.pgrp, req
+                pgrp, req
             ));
 
             let _ = client.chan.send::<PtyMasterCall, PtyMasterResponse>(
                 Channel::SIGNAL,
                 PtyMasterCall::Signal(SignalCall {
                     signal: PtyMasterSignal::SIGTTOU,
-                    pgrp: client.pgrp,
+                    pgrp,
                 }),
             );
-            let _ = client
-                .chan
-                .send_response(Channel::PTY, PtySlaveResponse::Error(TcError::EIO));
+
+            // stream messages are not responded to, the output is discarded
+            if let PtySlaveCallType::WriteStdout(req) = req.typ {
+                return self.received_stdout(client, req.data.len());
+            }
+
+            let _ =
+                client
+                    .chan
+                    .send_response(Channel::PTY, id, PtySlaveResponse::Error(TcError::EIO));
 
             return EventHandleResult::ErrorIgnore;
         }
 
-        let (channel, res) = if let PtySlaveCallType::WriteStdout(req) = req.typ {
-            (Channel::STDOUT, self.handle_stdout(req))
-        } else {
-            (Channel::PTY, Ok(RemotePtyHandlers::handle(&self.ctx, req)))
-        };
+        // the status must be determined before the call changes the terminal
+        let status = packet_status(&self.ctx, &req.typ);
 
-        let res = match res {
-            Ok(r) => r,
-            Err(err) => {
-                debug(err);
-                return EventHandleResult::ErrorTerminateServer;
+        let (channel, res) = match req.typ {
+            // stream messages are not responded to
+            PtySlaveCallType::WriteStdout(req) => return self.handle_stdout(client, req),
+            PtySlaveCallType::StdinCredit(credit) => {
+                return self.handle_stdin_credit(client, credit)
             }
+            // stdout is handled in order so all prior stdout has been written
+            PtySlaveCallType::FlushStdout => (Channel::STDOUT, PtySlaveResponse::Success(0)),
+            PtySlaveCallType::SetSession => (Channel::PTY, self.handle_set_session(&client)),
+            PtySlaveCallType::SetCtty(req) => (Channel::PTY, self.handle_set_ctty(&client, req)),
+            PtySlaveCallType::ReleaseCtty => (Channel::PTY, self.handle_release_ctty(&client)),
+            PtySlaveCallType::Ioctl(IoctlCall::TIOCSTI(req)) => {
+                (Channel::PTY, self.handle_tiocsti(&client, req))
+            }
+            typ => (
+                Channel::PTY,
+                RemotePtyHandlers::handle(&self.ctx, PtySlaveCall { fd: req.fd, typ }),
+            ),
         };
 
-        let res = client.chan.send_response(channel, res);
+        if let (Some(status), PtySlaveResponse::Success(_)) = (status, &res) {
+            self.send_packet_status(status);
+        }
+
+        let res = client.chan.send_response(channel, id, res);
 
         match res {
             Ok(_) => EventHandleResult::Success,
@@ -294,15 +455,84 @@ impl Server {
         }
     }
 
-    fn handle_stdout(&self, req: WriteStdoutCall) -> Result<PtySlaveResponse, String> {
-        io::stdout()
+    // the status is sent through the stdin stream so it is
+    // received in order with the input of the foreground process
+    fn send_packet_status(&mut self, status: u8) {
+        let mut client = match self.get_active_client() {
+            Some(c) => c,
+            None => {
+                debug("packet status changed while no active pgrp, discarding");
+                return;
+            }
+        };
+
+        // the client would not be able to decode the status
+        if !client
+            .chan
+            .capabilities()
+            .supports_ioctl(&IoctlCall::TIOCPKT(true))
+        {
+            debug("packet mode not supported by the active client, discarding status");
+            return;
+        }
+
+        let res = client.chan.notify(
+            Channel::STDIN,
+            PtyMasterCall::PacketStatus(PacketStatusCall { status }),
+        );
+        if let Err(err) = res {
+            debug(format!("failed to send packet status: {}", err));
+        }
+    }
+
+    fn handle_stdout(&mut self, client: Client, req: WriteStdoutCall) -> EventHandleResult {
+        let res = io::stdout()
             .write_all(req.data.as_slice())
-            .map_err(|e| format!("failed to write to stdout: {}", e))?;
-        io::stdout()
-            .flush()
-            .map_err(|e| format!("failed to flush stdout: {}", e))?;
+            .and_then(|_| io::stdout().flush());
+
+        if let Err(err) = res {
+            debug(format!("failed to write to stdout: {}", err));
+            return EventHandleResult::ErrorTerminateServer;
+        }
+
+        self.received_stdout(client, req.data.len())
+    }
+
+    fn received_stdout(&mut self, client: Client, len: usize) -> EventHandleResult {
+        // track the received bytes so the client does not resend them
+        // when resuming
+        if let Some(session) = self.sessions.get_mut(&client.pid) {
+            session.stdout_received += len as u64;
+        }
 
-        Ok(PtySlaveResponse::Success(0))
+        self.consume_stdout(client, len)
+    }
+
+    // background processes are only stopped from writing with stty tostop
+    fn tostop(&self) -> bool {
+        match handle_tcgetattr(&self.ctx) {
+            PtySlaveResponse::GetAttr(TcGetAttrResponse { termios, .. }) => {
+                termios.c_lflag.contains(&TermiosLocalMode::TOSTOP)
+            }
+            _ => false,
+        }
+    }
+
+    // grants the client more credit once enough stdout has been consumed
+    fn consume_stdout(&mut self, mut client: Client, len: usize) -> EventHandleResult {
+        let credit = match self.streams(client.pid).stdout.consume(len) {
+            Some(credit) => credit,
+            None => return EventHandleResult::Success,
+        };
+
+        let res = client
+            .chan
+            .notify(Channel::STDOUT, PtyMasterCall::StdoutCredit(credit));
+
+        match res {
+            Ok(_) => EventHandleResult::Success,
+            Err(err) => self.client_error(client, err),
+        }
     }
 
     fn unexpected_result(&self, client: Client, res: PtyMasterResponse) -> EventHandleResult {
@@ -326,7 +556,7 @@ impl Server {
             EventHandleResult::Success => {}
             EventHandleResult::ErrorIgnore => {}
             EventHandleResult::ErrorTerminateClient(pid) => {
-                self.remove_client(pid);
+                self.disconnect_client(pid);
             }
             EventHandleResult::ErrorTerminateServer => {
                 debug("terminating server");
@@ -340,6 +570,8 @@ impl Server {
         // TODO: signal clean up to pty listener when client terminated
 
         let _ = self.clients.remove(&pid);
+        let _ = self.sessions.remove(&pid);
+        let _ = self.streams.remove(&pid);
 
         // relinquish the foreground process slot if all terminated
         let mut ctx = self.ctx.state.lock().unwrap();
@@ -348,19 +580,124 @@ impl Server {
                 ctx.pgrp = None;
             }
         }
+
+        // the next process to register leads a new session
+        // and must enable packet mode itself
+        if self.clients.is_empty() {
+            ctx.sid = None;
+            ctx.packet = false;
+        }
+    }
+
+    // clients with a session are retained for the resume timeout
+    // so they can reconnect, otherwise they are removed immediately
+    fn disconnect_client(&mut self, pid: u32) {
+        let (session, timeout) = match (self.sessions.get_mut(&pid), self.conf.resume_timeout) {
+            (Some(session), Some(timeout)) => (session, timeout),
+            _ => return self.remove_client(pid),
+        };
+
+        if session.disconnected_at.is_some() {
+            return;
+        }
+
+        debug(format!("client {} disconnected, awaiting resume", pid));
+        let disconnected_at = Instant::now();
+        session.disconnected_at = Some(disconnected_at);
+
+        let sender = self.sender.clone();
+        thread::spawn(move || {
+            thread::sleep(timeout);
+            let _ = sender.send(Event::ClientEvent(Box::new(ClientEvent {
+                client_pid: pid,
+                event: ClientEventType::ResumeExpired(disconnected_at),
+            })));
+        });
+    }
+
+    fn resume_client(&mut self, pid: u32, req: ResumeRequest) -> EventHandleResult {
+        let ResumeRequest { mut chan, id, req } = req;
+
+        let (client, session, timeout) = match (
+            self.clients.get_mut(&pid),
+            self.sessions.get_mut(&pid),
+            self.conf.resume_timeout,
+        ) {
+            (Some(client), Some(session), Some(timeout)) if session.token == req.token => {
+                (client, session, timeout)
+            }
+            _ => {
+                debug(format!("could not find session to resume for pid {}", pid));
+                let _ =
+                    chan.send_response(Channel::PGRP, id, PtySlaveResponse::Error(TcError::EIO));
+                return EventHandleResult::ErrorIgnore;
+            }
+        };
+
+        let res = chan.send_response(
+            Channel::PGRP,
+            id,
+            PtySlaveResponse::Session(session.response(timeout)),
+        );
+
+        if let Err(err) = res {
+            debug(format!("failed to resume session for pid {}: {}", pid, err));
+            return EventHandleResult::ErrorIgnore;
+        }
+
+        debug(format!("resumed session for pid {}", pid));
+        session.disconnected_at = None;
+        session.stdin.ack(req.stdin_received);
+        let replay = session.stdin.since(req.stdin_received);
+
+        client.chan = chan;
+        let client = client.clone();
+        self.start_listeners(&client);
+
+        // the pending stdin is included in the replayed data
+        debug(format!("replaying {} bytes of stdin", replay.len()));
+        let streams = self.streams(pid);
+        streams.stdin.rewind(req.stdin_received);
+        streams.stdin_pending = replay.into();
+
+        self.flush_stdin(client)
     }
 
-    fn register_client(&mut self, client: Client) {
+    fn register_client(&mut self, client: Client, session: Option<Session>) {
         debug(format!("registered process {}", client.pid));
 
         // if no foreground group adopt the first registered proc
+        // and the first proc's session as the terminal's session
         {
             let mut ctx = self.ctx.state.lock().unwrap();
             if ctx.pgrp.is_none() {
                 let _ = ctx.pgrp.insert(client.pgrp);
             }
+            if ctx.sid.is_none() && self.clients.is_empty() {
+                let _ = ctx.sid.insert(client.sid.unwrap_or(client.pid));
+            }
+        }
+
+        self.start_listeners(&client);
+
+        // advertise our window in case it differs from the default
+        let streams = ClientStreams::new(self.conf.stream_window);
+        let res = client.chan.clone().notify(
+            Channel::STDOUT,
+            PtyMasterCall::StdoutCredit(streams.stdout.credit()),
+        );
+        if let Err(err) = res {
+            debug(format!("failed to send stdout credit: {}", err));
         }
 
+        if let Some(session) = session {
+            let _ = self.sessions.insert(client.pid, session);
+        }
+        let _ = self.streams.insert(client.pid, streams);
+        let _ = self.clients.insert(client.pid, client);
+    }
+
+    fn start_listeners(&self, client: &Client) {
         ClientPtyListener::new(
             client.pid,
             client.chan.clone(),
@@ -377,10 +714,135 @@ impl Server {
             &self.sender,
         )
         .start();
-        let _ = self.clients.insert(client.pid, client);
+        ClientPtyListener::new(
+            client.pid,
+            client.chan.clone(),
+            Channel::STDIN,
+            &self.terminate,
+            &self.sender,
+        )
+        .start();
+    }
+
+    // whether the terminal is the controlling terminal of the client
+    fn has_ctty(&self, client: &Client) -> bool {
+        let sid = self.ctx.state.lock().unwrap().sid;
+
+        client.ctty && sid.is_some() && client.sid.is_none_or(|s| Some(s) == sid)
+    }
+
+    // the process leads a new session and process group without
+    // a controlling terminal
+    fn handle_set_session(&mut self, client: &Client) -> PtySlaveResponse {
+        debug(format!("pid {} created a new session", client.pid));
+
+        if let Some(client) = self.clients.get_mut(&client.pid) {
+            client.sid = Some(client.pid);
+            client.pgrp = client.pid;
+        }
+
+        PtySlaveResponse::Success(0)
+    }
+
+    // @see https://man7.org/linux/man-pages/man2/TIOCSCTTY.2const.html
+    fn handle_set_ctty(&mut self, client: &Client, req: SetCttyCall) -> PtySlaveResponse {
+        if client.sid != Some(client.pid) {
+            debug(format!("pid {} is not a session leader", client.pid));
+            return PtySlaveResponse::Error(TcError::EPERM);
+        }
+
+        if self.has_ctty(client) {
+            return PtySlaveResponse::Success(0);
+        }
+
+        let mut state = self.ctx.state.lock().unwrap();
+
+        // the terminal can only be stolen from another session if permitted
+        if state.sid.is_some() && !req.steal {
+            debug(format!(
+                "terminal is the controlling terminal of session {:?}",
+                state.sid
+            ));
+            return PtySlaveResponse::Error(TcError::EPERM);
+        }
+
+        debug(format!("session {} acquired the terminal", client.pid));
+        state.sid = Some(client.pid);
+        state.pgrp = Some(client.pgrp);
+        drop(state);
+
+        // processes of other sessions lose the terminal, processes which
+        // never reported their session are assumed to be in the old one
+        for other in self.clients.values_mut() {
+            if other.pid == client.pid {
+                other.ctty = true;
+            } else if other.sid != Some(client.pid) {
+                other.ctty = false;
+            }
+        }
+
+        PtySlaveResponse::Success(0)
+    }
+
+    // @see https://man7.org/linux/man-pages/man2/TIOCNOTTY.2const.html
+    fn handle_release_ctty(&mut self, client: &Client) -> PtySlaveResponse {
+        let sid = self.ctx.state.lock().unwrap().sid;
+
+        // only the process releases the terminal unless it leads the session,
+        // in which case the foreground group is hung up and the session loses it
+        if sid != Some(client.pid) {
+            if let Some(client) = self.clients.get_mut(&client.pid) {
+                client.ctty = false;
+            }
+
+            return PtySlaveResponse::Success(0);
+        }
+
+        debug(format!("session {} released the terminal", client.pid));
+
+        for signal in [PtyMasterSignal::SIGHUP, PtyMasterSignal::SIGCONT] {
+            let res = self.handle_signal(signal);
+            self.handle_result(res);
+        }
+
+        // every process of the session loses the terminal
+        for other in self.clients.values_mut() {
+            if other.sid.is_none_or(|s| s == client.pid) {
+                other.ctty = false;
+            }
+        }
+
+        let mut state = self.ctx.state.lock().unwrap();
+        state.sid = None;
+        state.pgrp = None;
+
+        PtySlaveResponse::Success(0)
     }
 
-    fn handle_set_process_group(&mut self, mut client: Client, req: SetProcessGroupCall) {
+    // the byte is queued behind the stdin already received so it reaches
+    // the foreground process as if it were typed on the terminal
+    // @see https://man7.org/linux/man-pages/man2/TIOCSTI.2const.html
+    fn handle_tiocsti(&mut self, client: &Client, req: TiocStiCall) -> PtySlaveResponse {
+        // only privileged processes may fake input on a terminal
+        // which is not their controlling terminal
+        if !req.privileged && !self.has_ctty(client) {
+            debug(format!(
+                "pid {} faked input on terminal which is not its controlling terminal",
+                client.pid
+            ));
+            return PtySlaveResponse::Error(TcError::EPERM);
+        }
+
+        match self.sender.send(Event::Stdin(vec![req.c])) {
+            Ok(_) => PtySlaveResponse::Success(0),
+            Err(err) => {
+                debug(format!("failed to queue faked input: {}", err));
+                PtySlaveResponse::Error(TcError::EIO)
+            }
+        }
+    }
+
+    fn handle_set_process_group(&mut self, mut client: Client, id: u32, req: SetProcessGroupCall) {
         let pid = if req.pid == 0 { client.pid } else { req.pid };
         let pgrp = if req.new_pgrp == 0 {
             client.pid
@@ -400,7 +862,7 @@ impl Server {
 
         let _ = client
             .chan
-            .send_response(Channel::PTY, PtySlaveResponse::Success(0));
+            .send_response(Channel::PTY, id, PtySlaveResponse::Success(0));
     }
 }
 
@@ -425,24 +887,42 @@ impl ServerHandle {
 
 #[cfg(test)]
 mod tests {
-    use std::io;
-
-    use remote_pty_common::channel::{transport::mem::MemoryTransport, RemoteChannel};
+    use std::{io, thread, time::Duration};
+
+    use remote_pty_common::{
+        channel::{transport::mem::MemoryTransport, Channel, RemoteChannel},
+        proto::{
+            master::{PtyMasterCall, PtyMasterResponse, PtyMasterSignal, SignalCall},
+            slave::{
+                PtySlaveCall, PtySlaveCallType, PtySlaveResponse, ResumeSessionCall, SetCttyCall,
+                TcError, TcSetProcGroupCall, TiocStiCall, WriteStdoutCall,
+            },
+            Fd, Message, Termios,
+        },
+    };
 
-    use crate::context::Context;
+    use crate::{conf::Conf, context::Context};
 
-    use super::{listener::Listener, Client, Server};
+    use super::{
+        listener::{Connection, Listener},
+        session::Session,
+        Client, Event, EventHandleResult, ResumeRequest, Server,
+    };
 
     struct NoopListener;
 
     impl Listener for NoopListener {
-        fn accept(&mut self) -> io::Result<RemoteChannel> {
+        fn accept(&mut self) -> io::Result<Connection> {
             Err(io::Error::new(io::ErrorKind::Other, "unused test listener"))
         }
     }
 
     fn test_server() -> Server {
-        Server::new(Context::invalid_fds(), Box::new(NoopListener))
+        Server::new(
+            Context::invalid_fds(),
+            vec![Box::new(NoopListener)],
+            Conf::default(),
+        )
     }
 
     fn test_client(pid: u32, pgrp: u32) -> Client {
@@ -451,9 +931,35 @@ mod tests {
             chan: RemoteChannel::new(transport),
             pid,
             pgrp,
+            sid: None,
+            ctty: true,
         }
     }
 
+    // sends the call from the client's side of a live channel
+    // and returns the server's response
+    fn pty_call(server: &mut Server, pid: u32, typ: PtySlaveCallType) -> PtySlaveResponse {
+        let (t1, t2) = MemoryTransport::pair();
+        let mut chan = RemoteChannel::new(t1);
+        let mut client_chan = RemoteChannel::new(t2);
+
+        let client_thread = thread::spawn(move || {
+            client_chan
+                .send::<PtySlaveCall, PtySlaveResponse>(
+                    Channel::PTY,
+                    PtySlaveCall { fd: Fd(0), typ },
+                )
+                .unwrap()
+        });
+
+        let req = chan.receive_request::<PtySlaveCall>(Channel::PTY).unwrap();
+        let mut client = server.clients[&pid].clone();
+        client.chan = chan;
+        server.handle_pty_call(client, req);
+
+        client_thread.join().unwrap()
+    }
+
     #[test]
     fn get_active_client_falls_back_to_matching_process_group() {
         let mut server = test_server();
@@ -485,4 +991,264 @@ mod tests {
         assert_eq!(client.pid, 123);
         assert_eq!(client.pgrp, 123);
     }
+
+    #[test]
+    fn resume_client_replays_buffered_stdin() {
+        let mut server = Server::new(
+            Context::invalid_fds(),
+            vec![Box::new(NoopListener)],
+            Conf {
+                resume_timeout: Some(Duration::from_secs(10)),
+                ..Conf::default()
+            },
+        );
+        let session = Session::new();
+        let token = session.token;
+        server.clients.insert(123, test_client(123, 123));
+        server.sessions.insert(123, session);
+        server.ctx.state.lock().unwrap().pgrp = Some(123);
+
+        // stdin is buffered while the client is disconnected
+        server.disconnect_client(123);
+        assert!(matches!(
+            server.handle_stdin(b"abc".to_vec()),
+            EventHandleResult::Success
+        ));
+
+        let (t1, t2) = MemoryTransport::pair();
+        let mut chan = RemoteChannel::new(t1);
+        let mut client_chan = RemoteChannel::new(t2);
+
+        let client_thread = thread::spawn(move || {
+            let res = client_chan
+                .send::<PtySlaveCall, PtySlaveResponse>(
+                    Channel::PGRP,
+                    PtySlaveCall {
+                        fd: Fd(0),
+                        typ: PtySlaveCallType::ResumeSession(ResumeSessionCall {
+                            pid: 123,
+                            token,
+                            stdin_received: 1,
+                        }),
+                    },
+                )
+                .unwrap();
+            assert!(matches!(res, PtySlaveResponse::Session(_)));
+
+            // only the data not yet received should be replayed
+            let req = client_chan
+                .receive_request::<PtyMasterCall>(Channel::STDIN)
+                .unwrap();
+
+            match req.payload {
+                PtyMasterCall::WriteStdin(req) => req.data,
+                req => panic!("unexpected request: {:?}", req),
+            }
+        });
+
+        let req = chan.receive_request::<PtySlaveCall>(Channel::PGRP).unwrap();
+        let resume = match req.payload.typ {
+            PtySlaveCallType::ResumeSession(resume) => resume,
+            typ => panic!("unexpected request: {:?}", typ),
+        };
+
+        let res = server.resume_client(
+            123,
+            ResumeRequest {
+                chan,
+                id: req.id,
+                req: resume,
+            },
+        );
+        assert!(matches!(res, EventHandleResult::Success));
+        assert_eq!(client_thread.join().unwrap(), b"bc".to_vec());
+
+        let session = server.sessions.get(&123).unwrap();
+        assert_eq!(session.disconnected_at, None);
+        assert_eq!(session.stdin.end(), 3);
+    }
+
+    #[test]
+    fn set_ctty_requires_steal_when_owned_by_other_session() {
+        let mut server = test_server();
+        server.clients.insert(123, test_client(123, 123));
+        server.clients.insert(456, test_client(456, 123));
+        server.ctx.state.lock().unwrap().sid = Some(123);
+
+        // the new session leader loses the terminal
+        let leader = server.clients[&456].clone();
+        server.handle_set_session(&leader);
+        let leader = server.clients[&456].clone();
+        assert!(!server.has_ctty(&leader));
+
+        assert_eq!(
+            server.handle_set_ctty(&leader, SetCttyCall { steal: false }),
+            PtySlaveResponse::Error(TcError::EPERM)
+        );
+        assert_eq!(
+            server.handle_set_ctty(&leader, SetCttyCall { steal: true }),
+            PtySlaveResponse::Success(0)
+        );
+
+        {
+            let state = server.ctx.state.lock().unwrap();
+            assert_eq!(state.sid, Some(456));
+            assert_eq!(state.pgrp, Some(456));
+        }
+
+        // the previous session no longer has a controlling terminal
+        for typ in [
+            PtySlaveCallType::GetProcGroup,
+            PtySlaveCallType::SetProgGroup(TcSetProcGroupCall { pid: 123 }),
+        ] {
+            assert_eq!(
+                pty_call(&mut server, 123, typ),
+                PtySlaveResponse::Error(TcError::ENOTTY)
+            );
+        }
+    }
+
+    #[test]
+    fn release_ctty_by_leader_releases_session() {
+        let mut server = test_server();
+        server.clients.insert(123, test_client(123, 123));
+        server.clients.insert(456, test_client(456, 123));
+        server.ctx.state.lock().unwrap().sid = Some(123);
+
+        let leader = server.clients[&123].clone();
+        server.handle_set_session(&leader);
+        server.clients.get_mut(&456).unwrap().sid = Some(123);
+        let leader = server.clients[&123].clone();
+        assert_eq!(
+            server.handle_release_ctty(&leader),
+            PtySlaveResponse::Success(0)
+        );
+        assert_eq!(server.ctx.state.lock().unwrap().sid, None);
+
+        // reacquiring the terminal only makes it the leader's ctty
+        assert_eq!(
+            server.handle_set_ctty(&leader, SetCttyCall { steal: false }),
+            PtySlaveResponse::Success(0)
+        );
+        assert_eq!(
+            pty_call(&mut server, 456, PtySlaveCallType::GetProcGroup),
+            PtySlaveResponse::Error(TcError::ENOTTY)
+        );
+    }
+
+    #[test]
+    fn tiocsti_queues_input_behind_stdin() {
+        let mut server = test_server();
+        server.clients.insert(123, test_client(123, 123));
+        server.ctx.state.lock().unwrap().sid = Some(123);
+        server.sender.send(Event::Stdin(b"ab".to_vec())).unwrap();
+
+        let client = server.clients[&123].clone();
+        let req = TiocStiCall {
+            c: b'c',
+            privileged: false,
+        };
+        assert_eq!(
+            server.handle_tiocsti(&client, req),
+            PtySlaveResponse::Success(0)
+        );
+
+        for expected in [b"ab".to_vec(), b"c".to_vec()] {
+            match server.receiver.try_recv() {
+                Ok(Event::Stdin(data)) => assert_eq!(data, expected),
+                _ => panic!("expected stdin event"),
+            }
+        }
+    }
+
+    #[test]
+    fn tiocsti_requires_ctty_or_privilege() {
+        let mut server = test_server();
+        server.clients.insert(123, test_client(123, 123));
+        server.clients.insert(456, test_client(456, 456));
+        server.ctx.state.lock().unwrap().sid = Some(123);
+
+        // the process leads a session without a controlling terminal
+        let client = server.clients[&456].clone();
+        server.handle_set_session(&client);
+        let client = server.clients[&456].clone();
+
+        let req = |privileged| TiocStiCall {
+            c: b'c',
+            privileged,
+        };
+        assert_eq!(
+            server.handle_tiocsti(&client, req(false)),
+            PtySlaveResponse::Error(TcError::EPERM)
+        );
+        assert!(server.receiver.try_recv().is_err());
+
+        assert_eq!(
+            server.handle_tiocsti(&client, req(true)),
+            PtySlaveResponse::Success(0)
+        );
+        assert!(matches!(server.receiver.try_recv(), Ok(Event::Stdin(_))));
+    }
+
+    #[test]
+    fn background_stdout_is_stopped_with_tostop() {
+        let ctx = Context::openpty().unwrap();
+        let mut termios = Termios::zeroed_libc_termios();
+        unsafe {
+            libc::tcgetattr(ctx.pty.master, &mut termios);
+            termios.c_lflag |= libc::TOSTOP;
+            libc::tcsetattr(ctx.pty.master, libc::TCSANOW, &termios);
+        }
+
+        let mut server = Server::new(ctx, vec![Box::new(NoopListener)], Conf::default());
+        server.clients.insert(1, test_client(1, 1));
+        {
+            let mut state = server.ctx.state.lock().unwrap();
+            state.pgrp = Some(1);
+            state.sid = Some(1);
+        }
+
+        // the client has since moved to the foreground pgrp but the
+        // stdout was written while it was in the background
+        let (t1, t2) = MemoryTransport::pair();
+        let client = Client {
+            chan: RemoteChannel::new(t1),
+            ..test_client(2, 1)
+        };
+        let mut client_chan = RemoteChannel::new(t2);
+
+        let signal_thread = thread::spawn(move || {
+            let mut signal = None;
+            client_chan
+                .receive::<PtyMasterCall, PtyMasterResponse, _>(Channel::SIGNAL, |req| {
+                    signal = Some(req);
+                    PtyMasterResponse::Success(0)
+                })
+                .unwrap();
+            signal
+        });
+
+        let req = Message {
+            id: 1,
+            payload: PtySlaveCall {
+                fd: Fd(0),
+                typ: PtySlaveCallType::WriteStdout(WriteStdoutCall {
+                    data: b"abc".to_vec(),
+                    pgrp: 2,
+                }),
+            },
+        };
+        assert!(matches!(
+            server.handle_pty_call(client, req),
+            EventHandleResult::Success
+        ));
+
+        assert_eq!(
+            signal_thread.join().unwrap(),
+            Some(PtyMasterCall::Signal(SignalCall {
+                signal: PtyMasterSignal::SIGTTOU,
+                pgrp: 2,
+            }))
+        );
+    }
 }
@@ -59,10 +59,10 @@ impl ClientPtyListener {
                 }
             };
 
-            let res = self.sender.send(Event::ClientEvent(ClientEvent {
+            let res = self.sender.send(Event::ClientEvent(Box::new(ClientEvent {
                 client_pid: self.client_pid,
                 event: ClientEventType::Call(req),
-            }));
+            })));
 
             match res {
                 Ok(_) => {}
@@ -73,10 +73,10 @@ impl ClientPtyListener {
             }
         }
 
-        let res = self.sender.send(Event::ClientEvent(ClientEvent {
+        let res = self.sender.send(Event::ClientEvent(Box::new(ClientEvent {
             client_pid: self.client_pid,
             event: ClientEventType::Terminated,
-        }));
+        })));
         debug(format!("terminating client {:?} listener: {:?}", self.chan_type, res));
     }
 }
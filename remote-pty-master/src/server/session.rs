@@ -0,0 +1,40 @@
+use std::time::{Duration, Instant};
+
+use remote_pty_common::{
+    channel::stream::ReplayBuffer,
+    proto::{slave::SessionResponse, SessionToken},
+};
+
+// the max number of unacknowledged stdin bytes retained per session,
+// this includes stdin waiting for credit from the client
+const MAX_REPLAY_BYTES: usize = 1024 * 1024;
+
+// state retained for a client so it can resume after losing its connection
+pub struct Session {
+    pub(crate) token: SessionToken,
+    // total bytes of stdout received from the client
+    pub(crate) stdout_received: u64,
+    // stdin which has not been acknowledged by the client
+    pub(crate) stdin: ReplayBuffer,
+    // when the client lost its connection, if it is disconnected
+    pub(crate) disconnected_at: Option<Instant>,
+}
+
+impl Session {
+    pub(crate) fn new() -> Self {
+        Self {
+            token: SessionToken(rand::random()),
+            stdout_received: 0,
+            stdin: ReplayBuffer::new(MAX_REPLAY_BYTES),
+            disconnected_at: None,
+        }
+    }
+
+    pub(crate) fn response(&self, resume_timeout: Duration) -> SessionResponse {
+        SessionResponse {
+            token: self.token,
+            resume_timeout_ms: resume_timeout.as_millis() as _,
+            stdout_received: self.stdout_received,
+        }
+    }
+}
@@ -1,5 +1,5 @@
 use std::{
-    io::{self, Read},
+    io,
     sync::{
         atomic::{AtomicBool, Ordering},
         mpsc::Sender,
@@ -8,7 +8,10 @@ use std::{
     thread,
 };
 
-use remote_pty_common::log::debug;
+use remote_pty_common::{
+    io::coalesce::{read_coalesced, DEFAULT_COALESCE_WINDOW},
+    log::debug,
+};
 
 use super::Event;
 
@@ -34,7 +37,7 @@ impl StdinReader {
             let mut buf = [0u8; 1024];
 
             loop {
-                let res = io::stdin().read(&mut buf);
+                let res = read_coalesced(&mut io::stdin(), &mut buf, DEFAULT_COALESCE_WINDOW);
 
                 let n = match res {
                     Ok(0) => {
@@ -0,0 +1,82 @@
+use std::collections::VecDeque;
+
+use remote_pty_common::channel::stream::{ReceiveWindow, SendWindow};
+
+// the max size of each stdin message sent to a client
+const STDIN_CHUNK_SIZE: usize = 16 * 1024;
+
+// flow control state of the streams exchanged with a client
+pub struct ClientStreams {
+    // stdin sent to the client
+    pub(crate) stdin: SendWindow,
+    // stdin waiting for the client to grant credit
+    pub(crate) stdin_pending: VecDeque<u8>,
+    // stdout received from the client
+    pub(crate) stdout: ReceiveWindow,
+}
+
+impl ClientStreams {
+    pub(crate) fn new(window: u64) -> Self {
+        Self {
+            stdin: SendWindow::new(),
+            stdin_pending: VecDeque::new(),
+            stdout: ReceiveWindow::new(window),
+        }
+    }
+
+    // takes the next chunk of pending stdin which fits within the window
+    pub(crate) fn next_stdin_chunk(&mut self) -> Option<Vec<u8>> {
+        let len = self
+            .stdin
+            .available()
+            .min(self.stdin_pending.len())
+            .min(STDIN_CHUNK_SIZE);
+
+        if len == 0 {
+            return None;
+        }
+
+        self.stdin.record_sent(len);
+        Some(self.stdin_pending.drain(..len).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use remote_pty_common::proto::StreamCredit;
+
+    use super::{ClientStreams, STDIN_CHUNK_SIZE};
+
+    #[test]
+    fn test_next_stdin_chunk() {
+        let mut streams = ClientStreams::new(10);
+        streams.stdin.grant(StreamCredit {
+            consumed: 0,
+            window: 4,
+        });
+
+        streams.stdin_pending.extend([1, 2, 3, 4, 5, 6]);
+        assert_eq!(streams.next_stdin_chunk(), Some(vec![1, 2, 3, 4]));
+        // the window is full until more credit is granted
+        assert_eq!(streams.next_stdin_chunk(), None);
+
+        streams.stdin.grant(StreamCredit {
+            consumed: 4,
+            window: 4,
+        });
+        assert_eq!(streams.next_stdin_chunk(), Some(vec![5, 6]));
+        assert_eq!(streams.next_stdin_chunk(), None);
+    }
+
+    #[test]
+    fn test_next_stdin_chunk_size() {
+        let mut streams = ClientStreams::new(10);
+
+        streams.stdin_pending.extend(vec![0; STDIN_CHUNK_SIZE + 1]);
+        assert_eq!(
+            streams.next_stdin_chunk().map(|c| c.len()),
+            Some(STDIN_CHUNK_SIZE)
+        );
+        assert_eq!(streams.next_stdin_chunk().map(|c| c.len()), Some(1));
+    }
+}
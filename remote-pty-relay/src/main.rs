@@ -0,0 +1,27 @@
+use std::{env, net::TcpListener};
+
+use remote_pty_common::channel::transport::conf::HostPort;
+use remote_pty_relay::relay::Relay;
+
+// runs a rendezvous relay for sessions where neither the master nor the
+// slave can accept inbound connections, both connect out to the relay with
+// the same session key and are paired
+//
+// run relay
+// RPTY_DEBUG=1 cargo run -p remote-pty-relay -- 0.0.0.0:7000
+// run master
+// RPTY_TLS_CERT=cert.pem RPTY_TLS_KEY=key.pem cargo run -p remote-pty-master -- 'relay://relay-host:7000/a1b2c3?tls=1'
+// run slave
+// RPTY_TLS_FINGERPRINT=... LD_PRELOAD=libremote_pty_slave.so RPTY_TRANSPORT='relay://relay-host:7000/a1b2c3?tls=1' bash
+fn main() {
+    let addr = env::args()
+        .nth(1)
+        .expect("expected listen address")
+        .parse::<HostPort>()
+        .expect("could not parse listen address");
+
+    let listener = TcpListener::bind(&addr)
+        .unwrap_or_else(|e| panic!("could not bind listener {}: {}", addr, e));
+
+    Relay::new(listener).run();
+}
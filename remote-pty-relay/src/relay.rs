@@ -0,0 +1,277 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    io::{self, ErrorKind},
+    net::{Shutdown, SocketAddr, TcpListener, TcpStream},
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+use remote_pty_common::{
+    channel::transport::{
+        relay::{read_registration, Role},
+        reverse::notify_ready,
+    },
+    log::debug,
+};
+
+// max duration a connection can take to send its registration
+const REGISTRATION_TIMEOUT: Duration = Duration::from_secs(10);
+
+// max connections waiting for a peer per session key and role
+const MAX_PENDING: usize = 64;
+
+// pairs connections from the master and slave of a session, neither of
+// which can accept inbound connections, and splices the bytes between
+// them. the relay only reads the registration line, anything after it
+// is forwarded verbatim so tls between the peers is end to end
+pub struct Relay {
+    listener: TcpListener,
+    sessions: Arc<Sessions>,
+}
+
+// connections waiting for a peer, keyed by the session key and their role
+#[derive(Default)]
+struct Sessions {
+    pending: Mutex<HashMap<(String, Role), VecDeque<TcpStream>>>,
+}
+
+impl Relay {
+    pub fn new(listener: TcpListener) -> Self {
+        Self {
+            listener,
+            sessions: Arc::new(Sessions::default()),
+        }
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    pub fn run(self) {
+        loop {
+            let (socket, addr) = match self.listener.accept() {
+                Ok(res) => res,
+                Err(err) => {
+                    debug(format!("failed to accept connection: {}", err));
+                    continue;
+                }
+            };
+
+            let sessions = Arc::clone(&self.sessions);
+            thread::spawn(move || {
+                if let Err(err) = sessions.handle(socket) {
+                    debug(format!("relayed connection from {} failed: {}", addr, err));
+                }
+            });
+        }
+    }
+}
+
+impl Sessions {
+    fn handle(&self, mut socket: TcpStream) -> io::Result<()> {
+        socket.set_read_timeout(Some(REGISTRATION_TIMEOUT))?;
+        let (role, key) = read_registration(&mut socket)?;
+        socket.set_read_timeout(None)?;
+
+        let (mut socket, mut peer) = match self.pair(role, key, socket)? {
+            Some(pair) => pair,
+            // the connection is relayed by the thread of its peer
+            None => return Ok(()),
+        };
+
+        debug(format!("paired {} with waiting {}", role, role.peer()));
+        notify_ready(&mut peer)?;
+        notify_ready(&mut socket)?;
+
+        splice(socket, peer)
+    }
+
+    // takes the oldest connection waiting for the peer of the role,
+    // otherwise the connection waits until the peer connects
+    fn pair(
+        &self,
+        role: Role,
+        key: String,
+        socket: TcpStream,
+    ) -> io::Result<Option<(TcpStream, TcpStream)>> {
+        let mut pending = self.pending.lock().unwrap();
+        let peer_key = (key, role.peer());
+
+        if let Some(peers) = pending.get_mut(&peer_key) {
+            let mut found = None;
+
+            // peers which disconnected while waiting are discarded
+            while let Some(peer) = peers.pop_front() {
+                if is_waiting(&peer) {
+                    found = Some(peer);
+                    break;
+                }
+            }
+
+            if peers.is_empty() {
+                pending.remove(&peer_key);
+            }
+
+            if let Some(peer) = found {
+                return Ok(Some((socket, peer)));
+            }
+        }
+
+        let waiting = pending.entry((peer_key.0, role)).or_default();
+
+        if waiting.len() >= MAX_PENDING {
+            waiting.retain(is_waiting);
+        }
+
+        if waiting.len() >= MAX_PENDING {
+            return Err(io::Error::new(
+                ErrorKind::WouldBlock,
+                format!("too many {} connections waiting for the session", role),
+            ));
+        }
+
+        debug(format!("{} waiting for {}", role, role.peer()));
+        waiting.push_back(socket);
+
+        Ok(None)
+    }
+}
+
+// waiting connections do not send anything until they are paired,
+// so a readable connection has been closed by the other end
+fn is_waiting(socket: &TcpStream) -> bool {
+    if socket.set_nonblocking(true).is_err() {
+        return false;
+    }
+
+    let res = socket.peek(&mut [0u8]);
+    let _ = socket.set_nonblocking(false);
+
+    matches!(res, Err(err) if err.kind() == ErrorKind::WouldBlock)
+}
+
+// copies bytes in both directions until both connections are closed
+fn splice(a: TcpStream, b: TcpStream) -> io::Result<()> {
+    let (a_read, b_write) = (a.try_clone()?, b.try_clone()?);
+    let forward = thread::spawn(move || copy(a_read, b_write));
+
+    copy(b, a);
+    let _ = forward.join();
+
+    Ok(())
+}
+
+fn copy(mut from: TcpStream, mut to: TcpStream) {
+    let _ = io::copy(&mut from, &mut to);
+    // the eof is propagated so the peer closes its side
+    let _ = to.shutdown(Shutdown::Write);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{Read, Write},
+        net::{TcpListener, TcpStream},
+        sync::Arc,
+        thread,
+        time::Duration,
+    };
+
+    use remote_pty_common::channel::transport::{
+        conf::{HostPort, TransportOptions},
+        proxy::ProxyOption,
+        relay::{self, register, Role},
+    };
+
+    use super::{Relay, Sessions};
+
+    fn start() -> (HostPort, Arc<Sessions>) {
+        let relay = Relay::new(TcpListener::bind("127.0.0.1:0").unwrap());
+        let addr = HostPort {
+            host: "127.0.0.1".to_string(),
+            port: relay.local_addr().unwrap().port(),
+        };
+        let sessions = Arc::clone(&relay.sessions);
+        thread::spawn(move || relay.run());
+
+        (addr, sessions)
+    }
+
+    fn options() -> TransportOptions {
+        TransportOptions {
+            proxy: ProxyOption::Direct,
+            ..TransportOptions::default()
+        }
+    }
+
+    fn wait_pending(sessions: &Sessions, count: usize) {
+        for _ in 0..100 {
+            let pending = sessions.pending.lock().unwrap();
+
+            if pending.values().map(|p| p.len()).sum::<usize>() == count {
+                return;
+            }
+
+            drop(pending);
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        panic!("expected {} pending connections", count);
+    }
+
+    #[test]
+    fn test_relay_pairs_session() {
+        let (addr, _) = start();
+
+        let master_addr = addr.clone();
+        let master_thread = thread::spawn(move || {
+            let mut socket = relay::connect(&master_addr, "key", Role::Master, &options()).unwrap();
+            socket.write_all(b"to slave").unwrap();
+
+            let mut buf = vec![];
+            socket.read_to_end(&mut buf).unwrap();
+            buf
+        });
+
+        let mut socket = relay::connect(&addr, "key", Role::Slave, &options()).unwrap();
+        let mut buf = [0u8; 8];
+        socket.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"to slave");
+
+        socket.write_all(b"to master").unwrap();
+        drop(socket);
+        assert_eq!(master_thread.join().unwrap(), b"to master");
+    }
+
+    #[test]
+    fn test_relay_skips_closed_connections() {
+        let (addr, sessions) = start();
+
+        // a master which disconnected while waiting
+        let mut closed = TcpStream::connect(("127.0.0.1", addr.port)).unwrap();
+        register(&mut closed, Role::Master, "key").unwrap();
+        wait_pending(&sessions, 1);
+        drop(closed);
+
+        let master_addr = addr.clone();
+        let master_thread = thread::spawn(move || {
+            let mut socket = relay::connect(&master_addr, "key", Role::Master, &options()).unwrap();
+            socket.write_all(b"ok").unwrap();
+        });
+        wait_pending(&sessions, 2);
+
+        // other sessions are not paired
+        let other_addr = addr.clone();
+        thread::spawn(move || relay::connect(&other_addr, "other", Role::Slave, &options()));
+        wait_pending(&sessions, 3);
+
+        let mut socket = relay::connect(&addr, "key", Role::Slave, &options()).unwrap();
+        let mut buf = [0u8; 2];
+        socket.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"ok");
+
+        master_thread.join().unwrap();
+        wait_pending(&sessions, 1);
+    }
+}
@@ -0,0 +1,429 @@
+use std::{
+    env,
+    ffi::CString,
+    fs,
+    io::{self, Read, Write},
+    net::Shutdown,
+    os::unix::{
+        net::{UnixListener, UnixStream},
+        prelude::{AsRawFd, RawFd},
+    },
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+use remote_pty_common::{
+    channel::{
+        mux::{Mux, MuxStream},
+        transport::{conf::TransportType, unix_socket::peer_cred, Transport},
+        Channel, RemoteChannel,
+    },
+    log::debug,
+    proto::{
+        slave::{PtySlaveCall, PtySlaveCallType, PtySlaveResponse},
+        Fd,
+    },
+};
+
+use crate::{
+    channel::{connect_master, spawning_helper},
+    conf::Conf,
+    intercept,
+    reverse::LISTENER_FD_ENV,
+};
+
+// the socket of the session's broker, inherited by the
+// processes executed by the process which started it
+const BROKER_SOCKET_ENV: &str = "RPTY_BROKER_SOCKET";
+
+// the broker exits once no processes have been connected for this long
+const IDLE_TIMEOUT: Duration = Duration::from_secs(5);
+
+// the number of processes connected to the broker
+struct Clients {
+    connected: usize,
+    idle_since: Instant,
+}
+
+// connects to the session's broker, which is started by the first process
+// so each process does not need its own connection to the master.
+// processes connect directly if the broker has since exited
+pub(crate) fn connect(conf: &Conf) -> io::Result<UnixStream> {
+    let path = match env::var(BROKER_SOCKET_ENV) {
+        Ok(path) => path,
+        Err(_) => {
+            let path = spawn(conf)?;
+            // the env is read by processes executed after the constructor
+            env::set_var(BROKER_SOCKET_ENV, &path);
+            path
+        }
+    };
+
+    UnixStream::connect(path)
+}
+
+// binds the broker's socket in a private directory and forks the broker,
+// it is forked twice so it is not a child the session's process waits on
+fn spawn(conf: &Conf) -> io::Result<String> {
+    let dir = private_dir()?;
+    let path = format!("{}/broker.sock", dir);
+
+    let listener = match UnixListener::bind(&path) {
+        Ok(listener) => listener,
+        Err(err) => {
+            remove(&dir);
+            return Err(err);
+        }
+    };
+
+    let pid = spawning_helper(|| unsafe { libc::fork() });
+
+    match pid {
+        -1 => {
+            let err = io::Error::last_os_error();
+            remove(&dir);
+            return Err(err);
+        }
+        0 => {
+            if spawning_helper(|| unsafe { libc::fork() }) == 0 {
+                if let Err(err) = serve(conf, listener) {
+                    debug(format!("broker failed: {}", err));
+                }
+
+                exit(&dir);
+            }
+
+            unsafe { libc::_exit(0) };
+        }
+        pid => loop {
+            if unsafe { libc::waitpid(pid, std::ptr::null_mut(), 0) } != -1
+                || io::Error::last_os_error().kind() != io::ErrorKind::Interrupted
+            {
+                break;
+            }
+        },
+    }
+
+    debug(format!("started broker on {}", path));
+    Ok(path)
+}
+
+// detaches the broker and registers it with the master
+fn serve(conf: &Conf, listener: UnixListener) -> Result<(), String> {
+    detach(conf, listener.as_raw_fd());
+
+    let mut upstream = connect_master(conf)?;
+
+    if let Some(keepalive) = conf.keepalive {
+        upstream.start_keepalive(keepalive);
+    }
+
+    register_broker(&mut upstream)?;
+    debug("broker registered with the master");
+
+    serve_mux(Mux::new(upstream), listener)
+}
+
+// relays the connections of the session's processes over a single
+// connection to the master until it is closed or the broker is idle
+fn serve_mux(mux: Mux, listener: UnixListener) -> Result<(), String> {
+    let clients = Arc::new(Mutex::new(Clients {
+        connected: 0,
+        idle_since: Instant::now(),
+    }));
+
+    // the data from the master is dispatched by a single thread
+    let mut dispatcher = mux.clone();
+    let disconnected = thread::spawn(move || loop {
+        match dispatcher.accept() {
+            Ok((pid, _)) => debug(format!("master opened unexpected stream for {}", pid)),
+            Err(err) => return err,
+        }
+    });
+
+    let idle_clients = Arc::clone(&clients);
+    let listener_fd = listener.as_raw_fd();
+    thread::spawn(move || loop {
+        thread::sleep(Duration::from_secs(1));
+        let clients = idle_clients.lock().unwrap();
+
+        if clients.connected == 0 && clients.idle_since.elapsed() >= IDLE_TIMEOUT {
+            debug("no processes connected to broker, exiting");
+            // unblocks the listener
+            unsafe { libc::shutdown(listener_fd, libc::SHUT_RDWR) };
+            break;
+        }
+    });
+
+    for socket in listener.incoming() {
+        if disconnected.is_finished() {
+            break;
+        }
+
+        let socket = match socket {
+            Ok(socket) => socket,
+            Err(_) => break,
+        };
+
+        // the directory is private but the peer is checked regardless
+        let pid = match check_peer(&socket, unsafe { libc::geteuid() }) {
+            Ok(pid) => pid,
+            Err(err) => {
+                debug(err);
+                continue;
+            }
+        };
+
+        let stream = mux.open(pid)?;
+        debug(format!("opened stream for pid {}", pid));
+        clients.lock().unwrap().connected += 1;
+
+        let clients = Arc::clone(&clients);
+        thread::spawn(move || {
+            relay(socket, stream);
+
+            let mut clients = clients.lock().unwrap();
+            clients.connected -= 1;
+            clients.idle_since = Instant::now();
+        });
+    }
+
+    match disconnected.is_finished() {
+        true => Err(format!(
+            "disconnected from master: {}",
+            disconnected.join().unwrap()
+        )),
+        false => Ok(()),
+    }
+}
+
+// returns the pid of the connected process if it is run by the uid
+fn check_peer(socket: &UnixStream, uid: libc::uid_t) -> Result<u32, String> {
+    match peer_cred(socket) {
+        Ok(cred) if cred.uid == uid => Ok(cred.pid as u32),
+        Ok(cred) => Err(format!("rejected process of uid {}", cred.uid)),
+        Err(err) => Err(format!("failed to get peer credentials: {}", err)),
+    }
+}
+
+fn register_broker(chan: &mut RemoteChannel) -> Result<(), String> {
+    if !chan.capabilities().supports_mux() {
+        return Err("master does not support brokers".to_string());
+    }
+
+    let res = chan.send::<PtySlaveCall, PtySlaveResponse>(
+        Channel::PGRP,
+        PtySlaveCall {
+            fd: Fd(0), // unused
+            typ: PtySlaveCallType::RegisterBroker,
+        },
+    );
+
+    match res {
+        Ok(PtySlaveResponse::Success(_)) => Ok(()),
+        Ok(res) => Err(format!(
+            "failed to register broker: unexpected response {:?}",
+            res
+        )),
+        Err(err) => Err(format!("failed to register broker: {}", err)),
+    }
+}
+
+// copies the process's connection to and from its stream until either is closed
+fn relay(mut socket: UnixStream, stream: MuxStream) {
+    let close = stream.shutdown_handle();
+    let (mut reader, mut writer) = stream.split();
+
+    let mut socket_write = match socket.try_clone() {
+        Ok(socket) => socket,
+        Err(err) => {
+            debug(format!("failed to clone socket: {}", err));
+            return;
+        }
+    };
+    let from_master = thread::spawn(move || {
+        let _ = io::copy(&mut reader, &mut socket_write);
+        let _ = socket_write.shutdown(Shutdown::Both);
+    });
+
+    // each read is sent to the master once it is received
+    let mut buf = [0u8; 16 * 1024];
+    loop {
+        let len = match socket.read(&mut buf) {
+            Ok(0) | Err(_) => break,
+            Ok(len) => len,
+        };
+
+        if writer
+            .write_all(&buf[..len])
+            .and_then(|_| writer.flush())
+            .is_err()
+        {
+            break;
+        }
+    }
+
+    if let Some(close) = close {
+        close();
+    }
+
+    let _ = from_master.join();
+}
+
+// detaches the broker from the session so it is not signalled along with
+// the session's process group or holding the session's pipes open
+fn detach(conf: &Conf, listener_fd: RawFd) {
+    unsafe {
+        // the real setsid, the intercepted one would notify the master
+        intercept::__libc__setsid();
+        libc::signal(libc::SIGPIPE, libc::SIG_IGN);
+
+        let null = libc::open(c"/dev/null".as_ptr(), libc::O_RDWR);
+        if null != -1 {
+            for fd in 0..3 {
+                libc::dup2(null, fd);
+            }
+
+            if null > 2 {
+                libc::close(null);
+            }
+        }
+    }
+
+    // the fds used to connect to the master are kept
+    let mut keep = vec![listener_fd];
+
+    if let TransportType::Fd(fd) = conf.transport.typ {
+        keep.push(fd);
+    }
+
+    if let Some(fd) = env::var(LISTENER_FD_ENV).ok().and_then(|f| f.parse().ok()) {
+        keep.push(fd);
+    }
+
+    let fds = match fs::read_dir("/proc/self/fd") {
+        Ok(entries) => entries
+            .filter_map(|e| e.ok()?.file_name().to_str()?.parse::<RawFd>().ok())
+            .collect::<Vec<_>>(),
+        Err(_) => return,
+    };
+
+    for fd in fds.into_iter().filter(|fd| *fd > 2 && !keep.contains(fd)) {
+        unsafe { libc::close(fd) };
+    }
+}
+
+// creates a directory only accessible by the current user
+fn private_dir() -> io::Result<String> {
+    let base = env::var("XDG_RUNTIME_DIR").unwrap_or_else(|_| "/tmp".to_string());
+    let template = CString::new(format!("{}/rpty-XXXXXX", base))?.into_raw();
+
+    let res = unsafe { libc::mkdtemp(template) };
+    let template = unsafe { CString::from_raw(template) };
+
+    if res.is_null() {
+        return Err(io::Error::last_os_error());
+    }
+
+    template
+        .into_string()
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "invalid directory name"))
+}
+
+fn remove(dir: &str) {
+    let _ = fs::remove_file(format!("{}/broker.sock", dir));
+    let _ = fs::remove_dir(dir);
+}
+
+fn exit(dir: &str) -> ! {
+    remove(dir);
+    unsafe { libc::_exit(0) }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{Read, Write},
+        os::unix::net::{UnixListener, UnixStream},
+        process, thread,
+    };
+
+    use remote_pty_common::channel::{
+        mux::Mux,
+        transport::{mem::MemoryTransport, Transport},
+        RemoteChannel,
+    };
+
+    use super::{check_peer, private_dir, relay, remove, serve_mux};
+
+    #[test]
+    fn test_serve_mux() {
+        let (t1, t2) = MemoryTransport::pair();
+        let mut master = Mux::new(RemoteChannel::new(t2));
+
+        let dir = private_dir().unwrap();
+        let path = format!("{}/broker.sock", dir);
+        let listener = UnixListener::bind(&path).unwrap();
+        thread::spawn(move || serve_mux(Mux::new(RemoteChannel::new(t1)), listener));
+
+        let mut socket = UnixStream::connect(&path).unwrap();
+        remove(&dir);
+
+        // the stream is opened for the pid of the connected process
+        let (pid, stream) = master.accept().unwrap();
+        assert_eq!(pid, process::id());
+
+        // data is only dispatched while accepting
+        let mut dispatcher = master.clone();
+        thread::spawn(move || dispatcher.accept());
+
+        let (mut reader, mut writer) = stream.split();
+        socket.write_all(b"to master").unwrap();
+        let mut buf = [0u8; 9];
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"to master");
+
+        writer.write_all(b"to process").unwrap();
+        writer.flush().unwrap();
+        let mut buf = [0u8; 10];
+        socket.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"to process");
+    }
+
+    #[test]
+    fn test_relay_closes_stream() {
+        let (broker, mut master) = {
+            let (t1, t2) = MemoryTransport::pair();
+            (
+                Mux::new(RemoteChannel::new(t1)),
+                Mux::new(RemoteChannel::new(t2)),
+            )
+        };
+        let (socket, process) = UnixStream::pair().unwrap();
+
+        let stream = broker.open(10).unwrap();
+        let mut dispatcher = broker.clone();
+        thread::spawn(move || dispatcher.accept());
+        let relay_thread = thread::spawn(move || relay(socket, stream));
+
+        let (_, remote) = master.accept().unwrap();
+        let mut dispatcher = master.clone();
+        thread::spawn(move || dispatcher.accept());
+
+        // the process closing its connection closes the stream
+        drop(process);
+        let (mut reader, _writer) = remote.split();
+        assert_eq!(reader.read(&mut [0u8; 1]).unwrap(), 0);
+        relay_thread.join().unwrap();
+    }
+
+    #[test]
+    fn test_check_peer() {
+        let (socket, _peer) = UnixStream::pair().unwrap();
+        let uid = unsafe { libc::geteuid() };
+
+        assert_eq!(check_peer(&socket, uid), Ok(process::id()));
+        assert!(check_peer(&socket, uid + 1).is_err());
+    }
+}
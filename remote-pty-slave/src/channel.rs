@@ -1,8 +1,14 @@
 use std::{
-    io,
+    io, mem,
     net::TcpStream,
-    os::unix::{net::UnixStream, prelude::AsRawFd, prelude::FromRawFd},
-    sync::Mutex,
+    os::unix::{
+        net::UnixStream,
+        prelude::{AsRawFd, FromRawFd, IntoRawFd},
+    },
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
 };
 
 use errno::set_errno;
@@ -10,18 +16,36 @@ use lazy_static::lazy_static;
 
 use remote_pty_common::{
     channel::{
-        transport::{conf::TransportType, rw::ReadWriteTransport},
+        transport::{
+            cmd,
+            conf::{HostPort, TransportType},
+            relay::{self, Role},
+            rw::ReadWriteTransport,
+            tcp,
+            tls::TlsTransport,
+            unix_socket,
+            vsock::VsockStream,
+            ws::WsTransport,
+            Transport,
+        },
         RemoteChannel,
     },
     log::debug,
 };
 
-use crate::conf::Conf;
+use crate::{broker, conf::Conf, reverse};
 
 lazy_static! {
     static ref GLOBAL_CHANNEL: Mutex<Option<RemoteChannel>> = Mutex::new(Option::None);
 }
 
+// set while spawning a transport helper so the fork handler
+// does not initialise the helper as a remote pty process
+static SPAWNING_HELPER: AtomicBool = AtomicBool::new(false);
+
+// set once an inherited fd is used as it cannot be connected again
+static FD_TRANSPORT_USED: AtomicBool = AtomicBool::new(false);
+
 pub fn get_remote_channel(conf: &Conf) -> Result<RemoteChannel, String> {
     lazy_static::initialize(&GLOBAL_CHANNEL);
 
@@ -37,28 +61,185 @@ pub fn get_remote_channel(conf: &Conf) -> Result<RemoteChannel, String> {
     Ok(chan.as_ref().unwrap().clone())
 }
 
-trait FdConvertable: AsRawFd + FromRawFd + io::Read + io::Write + Send {}
-impl<T: AsRawFd + FromRawFd + io::Read + io::Write + Send> FdConvertable for T {}
+trait FdConvertable: AsRawFd + FromRawFd + IntoRawFd + io::Read + io::Write + Send {}
+impl<T: AsRawFd + FromRawFd + IntoRawFd + io::Read + io::Write + Send> FdConvertable for T {}
 
 fn init_channel(conf: &Conf) -> Result<RemoteChannel, String> {
-    let orig_errno = errno::errno();
-    let chan = match &conf.transport {
-        TransportType::Unix(sock_path) => {
-            process_transport(conf, UnixStream::connect(sock_path), |i| i.try_clone())
+    let chan = connect(conf)?;
+
+    if let Some(keepalive) = conf.keepalive {
+        chan.start_keepalive(keepalive);
+    }
+
+    Ok(chan)
+}
+
+// opens a new connection to the master, through the session's broker
+// if enabled, and performs the handshake
+pub(crate) fn connect(conf: &Conf) -> Result<RemoteChannel, String> {
+    if conf.mux {
+        let orig_errno = errno::errno();
+        let chan = process_transport(
+            conf,
+            broker::connect(conf),
+            |i| i.try_clone(),
+            Protocol::Plain,
+        );
+        set_errno(orig_errno);
+
+        match chan {
+            Ok(chan) => return Ok(chan),
+            Err(err) => debug(format!(
+                "failed to connect through broker, connecting directly: {}",
+                err
+            )),
         }
-        TransportType::Tcp(sock_addr) => {
-            process_transport(conf, TcpStream::connect(sock_addr), |i| i.try_clone())
+    }
+
+    connect_master(conf)
+}
+
+// opens a new connection to the master and performs the handshake
+pub(crate) fn connect_master(conf: &Conf) -> Result<RemoteChannel, String> {
+    let orig_errno = errno::errno();
+    let options = &conf.transport.options;
+
+    if options.listen {
+        let chan = accept_master(conf);
+        set_errno(orig_errno);
+        return chan;
+    }
+
+    let chan = match &conf.transport.typ {
+        TransportType::Unix(sock_path) => process_transport(
+            conf,
+            options.retry(|| {
+                unix_socket::socket_addr(sock_path).and_then(|a| UnixStream::connect_addr(&a))
+            }),
+            |i| i.try_clone(),
+            Protocol::Plain,
+        ),
+        TransportType::Tcp(addr) => process_transport(
+            conf,
+            options.retry(|| tcp::connect(addr, options)),
+            |i| i.try_clone(),
+            Protocol::Plain,
+        ),
+        TransportType::Tls(addr) => process_transport(
+            conf,
+            options.retry(|| tcp::connect(addr, options)),
+            |i| i.try_clone(),
+            Protocol::Tls(&addr.host),
+        ),
+        TransportType::Cmd(cmdline) => process_transport(
+            conf,
+            spawn_helper(cmdline),
+            |i| i.try_clone(),
+            Protocol::Plain,
+        ),
+        TransportType::Fd(fd) => {
+            process_transport(conf, inherited_fd(*fd), |i| i.try_clone(), Protocol::Plain)
         }
+        TransportType::Vsock(cid, port) => process_transport(
+            conf,
+            options.retry(|| VsockStream::connect(*cid, *port)),
+            |i| i.try_clone(),
+            Protocol::Plain,
+        ),
+        TransportType::Ws(addr, path) => process_transport(
+            conf,
+            options.retry(|| tcp::connect(addr, options)),
+            |i| i.try_clone(),
+            Protocol::Ws(addr, path),
+        ),
+        TransportType::Wss(addr, path) => process_transport(
+            conf,
+            options.retry(|| tcp::connect(addr, options)),
+            |i| i.try_clone(),
+            Protocol::Wss(addr, path),
+        ),
+        // the relay splices the connection to the master so
+        // tls is negotiated with the master end to end
+        TransportType::Relay(addr, key) => process_transport(
+            conf,
+            options.retry(|| relay::connect(addr, key, Role::Slave, options)),
+            |i| i.try_clone(),
+            if options.tls {
+                Protocol::Tls(&addr.host)
+            } else {
+                Protocol::Plain
+            },
+        ),
     };
     set_errno(orig_errno);
 
     chan
 }
 
+// waits for the master to connect to the listening slave
+// and performs the handshake
+fn accept_master(conf: &Conf) -> Result<RemoteChannel, String> {
+    let accept_tcp = || {
+        reverse::accept::<TcpStream>(conf)
+            .and_then(|s| s.set_nodelay(conf.transport.options.nodelay).map(|_| s))
+    };
+
+    match &conf.transport.typ {
+        TransportType::Unix(_) => process_transport(
+            conf,
+            reverse::accept::<UnixStream>(conf),
+            |i| i.try_clone(),
+            Protocol::Plain,
+        ),
+        TransportType::Tcp(_) => {
+            process_transport(conf, accept_tcp(), |i| i.try_clone(), Protocol::Plain)
+        }
+        TransportType::Tls(addr) => process_transport(
+            conf,
+            accept_tcp(),
+            |i| i.try_clone(),
+            Protocol::Tls(&addr.host),
+        ),
+        TransportType::Ws(addr, path) => process_transport(
+            conf,
+            accept_tcp(),
+            |i| i.try_clone(),
+            Protocol::Ws(addr, path),
+        ),
+        TransportType::Wss(addr, path) => process_transport(
+            conf,
+            accept_tcp(),
+            |i| i.try_clone(),
+            Protocol::Wss(addr, path),
+        ),
+        TransportType::Vsock(..) => process_transport(
+            conf,
+            reverse::accept::<VsockStream>(conf),
+            |i| i.try_clone(),
+            Protocol::Plain,
+        ),
+        TransportType::Cmd(_) | TransportType::Fd(_) | TransportType::Relay(..) => {
+            Err("the transport can only be used to connect to the master".to_string())
+        }
+    }
+}
+
+// the protocol spoken over the connected stream
+enum Protocol<'a> {
+    Plain,
+    // encrypted with tls, verifying the master against the server name
+    Tls(&'a str),
+    // websocket messages to the path on the host
+    Ws(&'a HostPort, &'a str),
+    Wss(&'a HostPort, &'a str),
+}
+
+// wraps the connected transport in a channel speaking the protocol
 fn process_transport<T: FdConvertable + 'static, C>(
     conf: &Conf,
     transport: Result<T, io::Error>,
     try_clone: C,
+    protocol: Protocol,
 ) -> Result<RemoteChannel, String>
 where
     C: Fn(&T) -> Result<T, io::Error>,
@@ -78,13 +259,60 @@ where
         conf,
         try_clone(&transport_read).map_err(|_| "failed to clone socket")?,
     )?;
-    let transport = ReadWriteTransport::new(transport_read, transport_write);
+    let read_fd = transport_read.as_raw_fd();
+    // unblocks any threads reading from the socket once the channel is closed
+    let shutdown = move || unsafe {
+        libc::shutdown(read_fd, libc::SHUT_RDWR);
+    };
+
+    let tls_config = || {
+        conf.tls
+            .as_ref()
+            .ok_or("tls transport requires RPTY_TLS_CA or RPTY_TLS_FINGERPRINT")?
+            .client_config()
+    };
+
+    let mut chan = match protocol {
+        Protocol::Plain => RemoteChannel::new(
+            ReadWriteTransport::new(transport_read, transport_write).with_shutdown(shutdown),
+        ),
+        Protocol::Tls(server_name) => {
+            let transport =
+                TlsTransport::connect(transport_read, transport_write, tls_config()?, server_name)?;
 
-    Ok(RemoteChannel::new(transport))
+            RemoteChannel::new(transport.with_shutdown(shutdown))
+        }
+        Protocol::Ws(addr, path) => {
+            let transport = WsTransport::connect(transport_read, transport_write, addr, path)?;
+
+            RemoteChannel::new(transport.with_shutdown(shutdown))
+        }
+        Protocol::Wss(addr, path) => {
+            let (read, write) =
+                TlsTransport::connect(transport_read, transport_write, tls_config()?, &addr.host)?
+                    .split();
+            let transport = WsTransport::connect(read, write, addr, path)?;
+
+            RemoteChannel::new(transport.with_shutdown(shutdown))
+        }
+    };
+    chan.set_call_timeout(conf.call_timeout);
+    chan.set_max_frame_size(conf.max_frame_size);
+    chan.set_compression(conf.compression);
+    chan.set_psk(conf.psk.clone());
+
+    chan.handshake()
+        .map_err(|e| format!("failed to complete handshake: {}", e))?;
+
+    Ok(chan)
 }
 
-fn ensure_not_stdio_fd<T: FdConvertable>(conf: &Conf, transport: T) -> Result<T, String> {
-    let fd = transport.as_raw_fd();
+pub(crate) fn ensure_not_stdio_fd<T: FromRawFd + IntoRawFd>(
+    conf: &Conf,
+    transport: T,
+) -> Result<T, String> {
+    // we take ownership of the fd as it is closed below
+    let fd = transport.into_raw_fd();
     let mut new_fd = 255;
 
     while conf.is_stdio_fd(fd) || is_fd_taken(new_fd) {
@@ -110,6 +338,49 @@ fn ensure_not_stdio_fd<T: FdConvertable>(conf: &Conf, transport: T) -> Result<T,
     Ok(unsafe { T::from_raw_fd(new_fd) })
 }
 
+fn spawn_helper(cmdline: &str) -> Result<UnixStream, io::Error> {
+    spawning_helper(|| cmd::spawn(cmdline))
+}
+
+// runs f, which forks a helper process, without the fork handler
+pub(crate) fn spawning_helper<T>(f: impl FnOnce() -> T) -> T {
+    SPAWNING_HELPER.store(true, Ordering::SeqCst);
+    let res = f();
+    SPAWNING_HELPER.store(false, Ordering::SeqCst);
+
+    res
+}
+
+pub(crate) fn is_spawning_helper() -> bool {
+    SPAWNING_HELPER.load(Ordering::SeqCst)
+}
+
+// takes ownership of the inherited socket, the fd is closed once relocated
+// so it cannot be used by forked processes or when resuming
+fn inherited_fd(fd: i32) -> Result<UnixStream, io::Error> {
+    if FD_TRANSPORT_USED.swap(true, Ordering::SeqCst) {
+        return Err(io::Error::new(
+            io::ErrorKind::NotConnected,
+            format!("inherited fd {} has already been used", fd),
+        ));
+    }
+
+    let mut stat: libc::stat = unsafe { mem::zeroed() };
+
+    if unsafe { libc::fstat(fd, &mut stat) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    if stat.st_mode & libc::S_IFMT != libc::S_IFSOCK {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("inherited fd {} is not a socket", fd),
+        ));
+    }
+
+    Ok(unsafe { UnixStream::from_raw_fd(fd) })
+}
+
 fn is_fd_taken(fd: libc::c_int) -> bool {
     return unsafe { libc::fcntl(fd, libc::F_GETFL) } != -1 || errno::errno().0 != libc::EBADF;
 }
@@ -128,9 +399,17 @@ pub(crate) fn close_remote_channel() -> Result<(), String> {
 
 #[cfg(test)]
 mod tests {
-    use std::{os::unix::net::UnixListener, sync::Mutex};
-
-    use remote_pty_common::channel::transport::conf::TransportType;
+    use std::{os::unix::net::UnixListener, sync::Mutex, thread};
+
+    use remote_pty_common::channel::{
+        frame::DEFAULT_MAX_FRAME_SIZE,
+        stream::DEFAULT_STREAM_WINDOW,
+        transport::{
+            conf::{TransportConf, TransportOptions, TransportType},
+            unix_socket::UnixSocketTransport,
+        },
+        RemoteChannel,
+    };
 
     use crate::{
         channel::{get_remote_channel, GLOBAL_CHANNEL},
@@ -145,11 +424,31 @@ mod tests {
         // create temp sock
         let sock_path = "/tmp/remote-pty.sock";
         let _ = std::fs::remove_file(sock_path);
-        let _sock = UnixListener::bind(sock_path).unwrap();
+        let sock = UnixListener::bind(sock_path).unwrap();
+
+        // complete the handshake on the master side
+        let master_thread = thread::spawn(move || {
+            let (stream, _) = sock.accept().unwrap();
+            let mut chan = RemoteChannel::new(UnixSocketTransport::new(stream));
+            chan.accept_handshake().unwrap();
+            chan
+        });
+
         let conf = Conf {
-            transport: TransportType::Unix(sock_path.to_string()),
+            transport: TransportConf {
+                typ: TransportType::Unix(sock_path.to_string()),
+                options: TransportOptions::default(),
+            },
             stdin_fd: 0,
             stdout_fds: vec![],
+            keepalive: None,
+            call_timeout: None,
+            max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+            stream_window: DEFAULT_STREAM_WINDOW,
+            compression: None,
+            tls: None,
+            psk: None,
+            mux: false,
             state: Mutex::new(State::new()),
         };
 
@@ -158,5 +457,7 @@ mod tests {
 
         let _chan = get_remote_channel(&conf).unwrap();
         assert!(GLOBAL_CHANNEL.lock().is_ok());
+
+        let _master_chan = master_thread.join().unwrap();
     }
 }
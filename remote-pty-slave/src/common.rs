@@ -2,7 +2,7 @@ use std::fmt::Debug;
 
 use remote_pty_common::{channel::RemoteChannel, log::debug};
 
-use crate::{channel::get_remote_channel, conf::get_conf};
+use crate::{channel::get_remote_channel, conf::get_conf, session::wait_for_resume};
 
 // boilerplate logic for intercepting a libc function
 // operating on a fd
@@ -55,8 +55,35 @@ where
         }
     };
 
+    // if the transport was lost give the session a chance to
+    // resume before failing the call
+    if chan.is_closed() && !wait_for_resume(&chan) {
+        debug("remote channel is closed");
+    }
+
     let res = remote_cb(chan);
     debug(format!("response: {:?}", res));
 
     res
 }
+
+// CAP_SYS_ADMIN from linux/capability.h
+pub(crate) const CAP_SYS_ADMIN: u32 = 21;
+
+// determines if the process has the capability in its effective set
+// @see https://man7.org/linux/man-pages/man7/capabilities.7.html
+pub(crate) fn has_capability(cap: u32) -> bool {
+    let status = match std::fs::read_to_string("/proc/self/status") {
+        Ok(status) => status,
+        Err(err) => {
+            debug(format!("failed to read process status: {}", err));
+            return false;
+        }
+    };
+
+    status
+        .lines()
+        .find_map(|l| l.strip_prefix("CapEff:"))
+        .and_then(|caps| u64::from_str_radix(caps.trim(), 16).ok())
+        .is_some_and(|caps| caps & (1 << cap) != 0)
+}
@@ -3,20 +3,48 @@ use std::{
     env,
     num::ParseIntError,
     sync::{Arc, Mutex},
+    time::Duration,
 };
 
 use lazy_static::lazy_static;
-use remote_pty_common::{channel::transport::conf::TransportType, log::debug};
+use remote_pty_common::{
+    channel::{
+        auth::Psk,
+        compress::CompressionConf,
+        frame::max_frame_size_from_env,
+        keepalive::KeepaliveConf,
+        stream::stream_window_from_env,
+        transport::{conf::TransportConf, tls::TlsClientConf},
+    },
+    log::debug,
+};
 
 use crate::fd::get_inode_from_fd;
 
 pub struct Conf {
-    // the path of the unix socket to send pty requests
-    pub transport: TransportType,
+    // the transport used to connect to the master
+    pub transport: TransportConf,
     // stdin fd
     pub stdin_fd: i32,
     // stdout fds
     pub stdout_fds: Vec<i32>,
+    // heartbeat settings, disabled if none
+    pub keepalive: Option<KeepaliveConf>,
+    // max duration to wait for the master to respond to a call
+    pub call_timeout: Option<Duration>,
+    // max size of frames exchanged with the master
+    pub max_frame_size: usize,
+    // window of stdin the master may send before being granted more credit
+    pub stream_window: u64,
+    // compression offered to the master, disabled if none
+    pub compression: Option<CompressionConf>,
+    // how the master is verified when using a tls transport
+    pub tls: Option<TlsClientConf>,
+    // key used to authenticate with the master, disabled if none
+    pub psk: Option<Psk>,
+    // multiplexes the connections of the session's processes
+    // through a broker started by the first process
+    pub mux: bool,
     // mutable state
     pub state: Mutex<State>,
 }
@@ -39,7 +67,7 @@ impl Conf {
         Ok(Self {
             transport: env::var("RPTY_TRANSPORT")
                 .map_err(|_| "could not find env var RPTY_TRANSPORT")?
-                .parse::<TransportType>()?,
+                .parse::<TransportConf>()?,
             //
             stdin_fd: env::var("RPTY_STDIN")
                 .unwrap_or_else(|_| "0".to_string())
@@ -53,6 +81,37 @@ impl Conf {
                 .collect::<Result<Vec<i32>, ParseIntError>>()
                 .map_err(|_| "failed to parse numbers in RPTY_STDOUT".to_string())?,
             //
+            keepalive: KeepaliveConf::from_env()?,
+            //
+            call_timeout: match env::var("RPTY_CALL_TIMEOUT_MS") {
+                Ok(ms) => Some(
+                    ms.parse::<u64>()
+                        .map_err(|_| "failed to parse number in RPTY_CALL_TIMEOUT_MS")?,
+                )
+                .filter(|ms| *ms > 0)
+                .map(Duration::from_millis),
+                Err(_) => None,
+            },
+            //
+            max_frame_size: max_frame_size_from_env()?,
+            //
+            stream_window: stream_window_from_env()?,
+            //
+            compression: CompressionConf::from_env()?,
+            //
+            tls: TlsClientConf::from_env()?,
+            //
+            psk: Psk::from_env()?,
+            //
+            mux: match env::var("RPTY_MUX") {
+                Ok(mux) => match mux.as_str() {
+                    "1" | "true" => true,
+                    "0" | "false" => false,
+                    _ => return Err("failed to parse boolean in RPTY_MUX".to_string()),
+                },
+                Err(_) => false,
+            },
+            //
             state: Mutex::new(State::new()),
         })
     }
@@ -152,7 +211,10 @@ mod tests {
         env::set_var("RPTY_STDOUT", "1,2");
 
         let conf = get_conf().expect("could not construct conf");
-        assert_eq!(conf.transport, TransportType::Unix(sock_path.to_string()));
+        assert_eq!(
+            conf.transport.typ,
+            TransportType::Unix(sock_path.to_string())
+        );
         assert_eq!(conf.stdin_fd, 0);
         assert_eq!(conf.stdout_fds, vec![1, 2]);
 
@@ -23,6 +23,7 @@ where
         TcError::ENOTTY => libc::ENOTTY,
         TcError::EINTR => libc::EINTR,
         TcError::EIO => libc::EIO,
+        TcError::EPERM => libc::EPERM,
     }));
 
     -1
@@ -1,11 +1,20 @@
 use remote_pty_common::log::debug;
 
-use crate::{channel::close_remote_channel, conf::clear_conf, init::remote_pty_init};
+use crate::{
+    channel::{close_remote_channel, is_spawning_helper},
+    conf::clear_conf,
+    init::remote_pty_init,
+};
 
 // re-initialises the process
 pub extern "C" fn fork_handler() {
     debug("process fork");
 
+    // the transport helper is about to exec and must not connect itself
+    if is_spawning_helper() {
+        return;
+    }
+
     if let Err(msg) = close_remote_channel() {
         debug(format!("failed to close remote channel: {}", msg));
         return;
@@ -4,7 +4,7 @@ use remote_pty_common::log::debug;
 
 use crate::{
     channel::get_remote_channel, conf::{get_conf, State}, fork::fork_handler, pgrp::register_process,
-    signal::init_signal_handler, stdin::init_stdin, stdout::init_stdout,
+    session::init_session, signal::init_signal_handler, stdin::init_stdin, stdout::init_stdout,
 };
 
 // track the number of calls to the init
@@ -45,12 +45,15 @@ pub (crate) fn remote_pty_init(pre_fork_state: Option<State>) {
         }
     };
 
-    let res = register_process(&mut chan);
+    let session = match register_process(&mut chan) {
+        Ok(session) => session,
+        Err(_) => {
+            debug("init failed: could not register process");
+            return;
+        }
+    };
 
-    if res.is_err() {
-        debug("init failed: could not register process");
-        return;
-    }
+    init_session(&chan, session);
 
     init_signal_handler(chan.clone());
     init_stdin(&conf, chan.clone(), pre_fork_state.as_ref());
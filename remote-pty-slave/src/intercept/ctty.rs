@@ -0,0 +1,121 @@
+use errno::{set_errno, Errno};
+use remote_pty_common::{
+    channel::{Channel, RemoteChannel},
+    log::debug,
+    proto::{
+        slave::{PtySlaveCall, PtySlaveCallType, PtySlaveResponse, SetCttyCall},
+        Fd,
+    },
+};
+
+use crate::{
+    common::{has_capability, CAP_SYS_ADMIN},
+    error::{generic_error, tc_error},
+};
+
+// equivalent to ioctl(fd, TIOCSCTTY, arg)
+// @see https://man7.org/linux/man-pages/man2/TIOCSCTTY.2const.html
+pub(crate) fn tiocsctty_chan(
+    chan: RemoteChannel,
+    fd: libc::c_int,
+    arg: libc::c_int,
+) -> libc::c_int {
+    // the terminal can only be stolen from another session by a privileged process
+    let steal = arg == 1 && has_capability(CAP_SYS_ADMIN);
+
+    ctty_call(
+        chan,
+        fd,
+        "TIOCSCTTY",
+        PtySlaveCallType::SetCtty(SetCttyCall { steal }),
+    )
+}
+
+// equivalent to ioctl(fd, TIOCNOTTY)
+// @see https://man7.org/linux/man-pages/man2/TIOCNOTTY.2const.html
+pub(crate) fn tiocnotty_chan(chan: RemoteChannel, fd: libc::c_int) -> libc::c_int {
+    ctty_call(chan, fd, "TIOCNOTTY", PtySlaveCallType::ReleaseCtty)
+}
+
+fn ctty_call(
+    mut chan: RemoteChannel,
+    fd: libc::c_int,
+    name: &str,
+    typ: PtySlaveCallType,
+) -> libc::c_int {
+    // older masters do not model the controlling terminal
+    if !chan.capabilities().supports_ctty() {
+        debug(format!("{} not supported by master", name));
+        set_errno(Errno(libc::EINVAL));
+        return -1;
+    }
+
+    let req = PtySlaveCall { fd: Fd(fd), typ };
+
+    let res = match chan.send(Channel::PTY, req) {
+        Ok(res) => res,
+        Err(msg) => return generic_error(name, msg),
+    };
+
+    match res {
+        PtySlaveResponse::Success(_) => 0,
+        PtySlaveResponse::Error(err) => tc_error(name, err),
+        _ => generic_error(name, "unexpected response"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use remote_pty_common::{
+        channel::{mock::MockChannel, Channel},
+        proto::{
+            slave::{PtySlaveCall, PtySlaveCallType, PtySlaveResponse, SetCttyCall, TcError},
+            Fd,
+        },
+    };
+
+    use crate::intercept::{tiocnotty_chan, tiocsctty_chan};
+
+    #[test]
+    fn test_tiocsctty() {
+        let expected_req = PtySlaveCall {
+            fd: Fd(0),
+            typ: PtySlaveCallType::SetCtty(SetCttyCall { steal: false }),
+        };
+        let mock_res = PtySlaveResponse::Success(0);
+        let mock = MockChannel::assert_sends(Channel::PTY, vec![expected_req], vec![mock_res]);
+
+        let res = tiocsctty_chan(mock.chan.clone(), 0, 0);
+
+        assert_eq!(res, 0);
+    }
+
+    #[test]
+    fn test_tiocsctty_owned_by_other_session() {
+        let expected_req = PtySlaveCall {
+            fd: Fd(0),
+            typ: PtySlaveCallType::SetCtty(SetCttyCall { steal: false }),
+        };
+        let mock_res = PtySlaveResponse::Error(TcError::EPERM);
+        let mock = MockChannel::assert_sends(Channel::PTY, vec![expected_req], vec![mock_res]);
+
+        let res = tiocsctty_chan(mock.chan.clone(), 0, 0);
+
+        assert_eq!(res, -1);
+        assert_eq!(errno::errno().0, libc::EPERM);
+    }
+
+    #[test]
+    fn test_tiocnotty() {
+        let expected_req = PtySlaveCall {
+            fd: Fd(0),
+            typ: PtySlaveCallType::ReleaseCtty,
+        };
+        let mock_res = PtySlaveResponse::Success(0);
+        let mock = MockChannel::assert_sends(Channel::PTY, vec![expected_req], vec![mock_res]);
+
+        let res = tiocnotty_chan(mock.chan.clone(), 0);
+
+        assert_eq!(res, 0);
+    }
+}
@@ -5,13 +5,17 @@ use remote_pty_common::{
     proto::{
         slave::{
             IoctlCall, IoctlResponse, IoctlValueResponse, PtySlaveCall, PtySlaveCallType,
-            PtySlaveResponse,
+            PtySlaveResponse, TiocStiCall,
         },
         Fd,
     },
 };
 
-use crate::{common::handle_intercept, error::generic_error, intercept};
+use crate::{
+    common::{handle_intercept, has_capability, CAP_SYS_ADMIN},
+    error::{generic_error, tc_error},
+    intercept, stdin,
+};
 
 #[cfg(target_os = "linux")]
 type Cmd = libc::Ioctl;
@@ -80,9 +84,17 @@ fn ioctl_chan(
         libc::TCXONC => return intercept::tcflow_chan(chan, fd, arg as libc::c_int),
         libc::TIOCINQ => return ioctl_get_int(chan, fd, IoctlCall::FIONREAD, arg), // same as libc::FIONREAD
         libc::TCFLSH => return intercept::tcflush_chan(chan, fd, arg as libc::c_int),
-        libc::TIOCGSID => return cmd_unimplemented("TIOCGSID"),
+        libc::TIOCGSID => {
+            return match intercept::tcgetsid_chan(chan, fd) {
+                -1 => -1,
+                sid => unsafe {
+                    *(arg as *mut libc::pid_t) = sid;
+                    0
+                },
+            }
+        }
         libc::TIOCGEXCL => return cmd_unimplemented("TIOCGEXCL"),
-        libc::TIOCGPKT => return cmd_unimplemented("TIOCGPKT"),
+        libc::TIOCGPKT => return ioctl_get_int(chan, fd, IoctlCall::TIOCGPKT, arg),
         libc::TIOCSPTLCK => return cmd_unimplemented("TIOCSPTLCK"),
         libc::TIOCGPTLCK => return cmd_unimplemented("TIOCGPTLCK"),
         libc::TIOCGPTPEER => return cmd_unimplemented("TIOCGPTPEER"),
@@ -101,14 +113,16 @@ fn ioctl_chan(
         _ if cmd == libc::TIOCSWINSZ as _ => {
             intercept::tcsetwinsize_chan(chan, fd, arg as *mut libc::winsize)
         }
-        _ if cmd == libc::TIOCSBRK as _ => cmd_unimplemented("TIOCSBRK"),
-        _ if cmd == libc::TIOCCBRK as _ => cmd_unimplemented("TIOCCBRK"),
+        _ if cmd == libc::TIOCSBRK as _ => ioctl_set_int(chan, fd, IoctlCall::TIOCSBRK),
+        _ if cmd == libc::TIOCCBRK as _ => ioctl_set_int(chan, fd, IoctlCall::TIOCCBRK),
         _ if cmd == libc::FIONREAD as _ => ioctl_get_int(chan, fd, IoctlCall::FIONREAD, arg),
         _ if cmd == libc::TIOCOUTQ as _ => ioctl_get_int(chan, fd, IoctlCall::TIOCOUTQ, arg),
-        _ if cmd == libc::TIOCSTI as _ => cmd_unimplemented("TIOCSTI"),
+        _ if cmd == libc::TIOCSTI as _ => {
+            ioctl_tiocsti(chan, fd, unsafe { *(arg as *const libc::c_char) } as _)
+        }
         _ if cmd == libc::TIOCCONS as _ => cmd_unimplemented("TIOCCONS"),
-        _ if cmd == libc::TIOCSCTTY as _ => cmd_unimplemented("TIOCSCTTY"),
-        _ if cmd == libc::TIOCNOTTY as _ => cmd_unimplemented("TIOCNOTTY"),
+        _ if cmd == libc::TIOCSCTTY as _ => intercept::tiocsctty_chan(chan, fd, arg as libc::c_int),
+        _ if cmd == libc::TIOCNOTTY as _ => intercept::tiocnotty_chan(chan, fd),
         _ if cmd == libc::TIOCGPGRP as _ => unsafe {
             *(arg as *mut _) = intercept::tcgetpgrp_chan(chan, fd);
             0
@@ -124,11 +138,33 @@ fn ioctl_chan(
             fd,
             IoctlCall::TIOCSETD(unsafe { *(arg as *const libc::c_int) } as _),
         ),
-        _ if cmd == libc::TIOCPKT as _ => cmd_unimplemented("TIOCPKT"),
-        _ if cmd == libc::TIOCMGET as _ => cmd_unimplemented("TIOCMGET"),
-        _ if cmd == libc::TIOCMSET as _ => cmd_unimplemented("TIOCMSET"),
-        _ if cmd == libc::TIOCMBIC as _ => cmd_unimplemented("TIOCMBIC"),
-        _ if cmd == libc::TIOCMBIS as _ => cmd_unimplemented("TIOCMBIS"),
+        _ if cmd == libc::TIOCPKT as _ => {
+            let packet = unsafe { *(arg as *const libc::c_int) } != 0;
+
+            match ioctl_set_int(chan, fd, IoctlCall::TIOCPKT(packet)) {
+                0 => {
+                    stdin::set_packet_mode(packet);
+                    0
+                }
+                ret => ret,
+            }
+        }
+        _ if cmd == libc::TIOCMGET as _ => ioctl_get_int(chan, fd, IoctlCall::TIOCMGET, arg),
+        _ if cmd == libc::TIOCMSET as _ => ioctl_set_int(
+            chan,
+            fd,
+            IoctlCall::TIOCMSET(unsafe { *(arg as *const libc::c_int) } as _),
+        ),
+        _ if cmd == libc::TIOCMBIC as _ => ioctl_set_int(
+            chan,
+            fd,
+            IoctlCall::TIOCMBIC(unsafe { *(arg as *const libc::c_int) } as _),
+        ),
+        _ if cmd == libc::TIOCMBIS as _ => ioctl_set_int(
+            chan,
+            fd,
+            IoctlCall::TIOCMBIS(unsafe { *(arg as *const libc::c_int) } as _),
+        ),
         _ => unsafe {
             debug("falling back to native ioctl");
             libc::ioctl(fd, cmd, arg)
@@ -142,6 +178,10 @@ fn ioctl_get_int(
     cmd: IoctlCall,
     arg: *mut libc::c_void,
 ) -> libc::c_int {
+    if !chan.capabilities().supports_ioctl(&cmd) {
+        return cmd_unimplemented(cmd.name());
+    }
+
     let req = PtySlaveCall {
         fd: Fd(fd),
         typ: PtySlaveCallType::Ioctl(cmd),
@@ -158,6 +198,7 @@ fn ioctl_get_int(
             ret,
             val: IoctlValueResponse::Int(val),
         }) => (ret, val),
+        PtySlaveResponse::Error(err) => return tc_error("ioctl", err),
         _ => return generic_error("ioctl", "unexpected response"),
     };
 
@@ -169,6 +210,41 @@ fn ioctl_get_int(
 }
 
 fn ioctl_set_int(mut chan: RemoteChannel, fd: libc::c_int, cmd: IoctlCall) -> libc::c_int {
+    if !chan.capabilities().supports_ioctl(&cmd) {
+        return cmd_unimplemented(cmd.name());
+    }
+
+    let req = PtySlaveCall {
+        fd: Fd(fd),
+        typ: PtySlaveCallType::Ioctl(cmd),
+    };
+
+    // send ioctl request to remote
+    let res = match chan.send(Channel::PTY, req) {
+        Ok(res) => res,
+        Err(msg) => return generic_error("ioctl", msg),
+    };
+
+    match res {
+        PtySlaveResponse::Success(ret) => ret as _,
+        PtySlaveResponse::Error(err) => tc_error("ioctl", err),
+        _ => generic_error("ioctl", "unexpected response"),
+    }
+}
+
+// @see https://man7.org/linux/man-pages/man2/TIOCSTI.2const.html
+fn ioctl_tiocsti(mut chan: RemoteChannel, fd: libc::c_int, c: u8) -> libc::c_int {
+    // like the kernel only privileged processes may fake input on a
+    // terminal which is not their controlling terminal, checked by the master
+    let cmd = IoctlCall::TIOCSTI(TiocStiCall {
+        c,
+        privileged: has_capability(CAP_SYS_ADMIN),
+    });
+
+    if !chan.capabilities().supports_ioctl(&cmd) {
+        return cmd_unimplemented(cmd.name());
+    }
+
     let req = PtySlaveCall {
         fd: Fd(fd),
         typ: PtySlaveCallType::Ioctl(cmd),
@@ -182,6 +258,7 @@ fn ioctl_set_int(mut chan: RemoteChannel, fd: libc::c_int, cmd: IoctlCall) -> li
 
     match res {
         PtySlaveResponse::Success(ret) => ret as _,
+        PtySlaveResponse::Error(err) => tc_error("ioctl", err),
         _ => generic_error("ioctl", "unexpected response"),
     }
 }
@@ -199,12 +276,14 @@ mod tests {
         proto::{
             slave::{
                 IoctlCall, IoctlResponse, IoctlValueResponse, ProcGroupResponse, PtySlaveCall,
-                PtySlaveCallType, PtySlaveResponse, TcSetProcGroupCall,
+                PtySlaveCallType, PtySlaveResponse, TcSetProcGroupCall, TiocStiCall,
             },
             Fd,
         },
     };
 
+    use crate::common::{has_capability, CAP_SYS_ADMIN};
+
     use super::ioctl_chan;
 
     #[test]
@@ -218,7 +297,7 @@ mod tests {
         let res = ioctl_chan(
             mock.chan.clone(),
             1,
-            libc::TIOCSBRK as _,
+            libc::TIOCEXCL as _,
             &mut 10 as *mut _ as *mut libc::c_void,
         );
 
@@ -315,6 +394,71 @@ mod tests {
         assert_eq!(unsafe { *val }, 10 as libc::c_int);
     }
 
+    #[test]
+    fn test_ioctl_tiocsti() {
+        let expected_req = PtySlaveCall {
+            fd: Fd(1),
+            typ: PtySlaveCallType::Ioctl(IoctlCall::TIOCSTI(TiocStiCall {
+                c: b'a',
+                privileged: has_capability(CAP_SYS_ADMIN),
+            })),
+        };
+        let mock_res = PtySlaveResponse::Success(0);
+
+        let mock = MockChannel::assert_sends(Channel::PTY, vec![expected_req], vec![mock_res]);
+
+        let res = ioctl_chan(
+            mock.chan.clone(),
+            1,
+            libc::TIOCSTI,
+            &mut (b'a' as libc::c_char) as *mut _ as *mut libc::c_void,
+        );
+
+        assert_eq!(res, 0);
+    }
+
+    #[test]
+    fn test_ioctl_tiocpkt() {
+        let expected_req = PtySlaveCall {
+            fd: Fd(1),
+            typ: PtySlaveCallType::Ioctl(IoctlCall::TIOCPKT(true)),
+        };
+        let mock_res = PtySlaveResponse::Success(0);
+
+        let mock = MockChannel::assert_sends(Channel::PTY, vec![expected_req], vec![mock_res]);
+
+        let res = ioctl_chan(
+            mock.chan.clone(),
+            1,
+            libc::TIOCPKT,
+            &mut 1 as *mut _ as *mut libc::c_void,
+        );
+
+        assert_eq!(res, 0);
+    }
+
+    #[test]
+    fn test_ioctl_tiocmbis() {
+        let expected_req = PtySlaveCall {
+            fd: Fd(1),
+            typ: PtySlaveCallType::Ioctl(IoctlCall::TIOCMBIS(libc::TIOCM_RTS as _)),
+        };
+        let mock_res = PtySlaveResponse::Success(0);
+
+        let mock = MockChannel::assert_sends(Channel::PTY, vec![expected_req], vec![mock_res]);
+
+        let val = &mut (libc::TIOCM_RTS as libc::c_int) as *mut libc::c_int;
+
+        let res = ioctl_chan(
+            mock.chan.clone(),
+            1,
+            libc::TIOCMBIS,
+            val as *mut _ as *mut libc::c_void,
+        );
+
+        assert_eq!(res, 0);
+    }
+
     #[test]
     fn test_ioctl_tiocgpgrp() {
         let expected_req = PtySlaveCall {
@@ -25,4 +25,8 @@ pub use tcgetpgrp::*;
 mod tcsetpgrp;
 pub use tcsetpgrp::*;
 mod setpgid;
-pub use setpgid::*;
\ No newline at end of file
+pub use setpgid::*;
+mod setsid;
+pub use setsid::*;
+mod ctty;
+pub(crate) use ctty::*;
\ No newline at end of file
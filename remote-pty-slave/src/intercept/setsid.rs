@@ -0,0 +1,120 @@
+use remote_pty_common::{
+    channel::{Channel, RemoteChannel},
+    log::debug,
+    proto::{
+        slave::{PtySlaveCall, PtySlaveCallType, PtySlaveResponse},
+        Fd,
+    },
+};
+
+use crate::common::handle_intercept;
+
+// @see https://man7.org/linux/man-pages/man2/setsid.2.html
+#[no_mangle]
+pub extern "C" fn setsid() -> libc::pid_t {
+    handle_intercept(
+        "setsid",
+        0, // unused
+        setsid_chan,
+        || unsafe { __libc__setsid() },
+    )
+}
+
+#[cfg(all(not(test), target_env = "musl"))]
+extern "C" {
+    // symbol overridden during build scripts
+    pub(crate) fn __libc__setsid() -> libc::pid_t;
+}
+
+#[cfg(any(test, target_os = "macos", target_env = "gnu"))]
+#[no_mangle]
+#[allow(non_snake_case)]
+pub(crate) unsafe fn __libc__setsid() -> libc::pid_t {
+    let setsid = libc::dlsym(libc::RTLD_NEXT, c"setsid".as_ptr());
+
+    if setsid.is_null() {
+        panic!("unable to find setsid sym");
+    }
+
+    let setsid =
+        std::mem::transmute::<*mut libc::c_void, unsafe extern "C" fn() -> libc::pid_t>(setsid);
+
+    setsid()
+}
+
+pub(crate) fn setsid_chan(chan: RemoteChannel) -> libc::pid_t {
+    // we run the local setsid and then, only if it was successful,
+    // detach the process from the terminal on the master
+    match unsafe { __libc__setsid() } {
+        -1 => -1,
+        sid => set_session_chan(chan, sid),
+    }
+}
+
+// the process is in the new session once the local setsid has succeeded,
+// so failing to notify the master is logged rather than returned
+pub(crate) fn set_session_chan(mut chan: RemoteChannel, sid: libc::pid_t) -> libc::pid_t {
+    // older masters assume every process is in the terminal's session
+    if !chan.capabilities().supports_ctty() {
+        debug("sessions not supported by master");
+        return sid;
+    }
+
+    let req = PtySlaveCall {
+        fd: Fd(0), // unused
+        typ: PtySlaveCallType::SetSession,
+    };
+
+    match chan.send(Channel::PTY, req) {
+        Ok(PtySlaveResponse::Success(_)) => {}
+        Ok(PtySlaveResponse::Error(err)) => {
+            debug(format!("master failed to set session: {:?}", err))
+        }
+        Ok(res) => debug(format!("unexpected response to set session: {:?}", res)),
+        Err(msg) => debug(format!("failed to set session on master: {}", msg)),
+    }
+
+    sid
+}
+
+#[cfg(test)]
+mod tests {
+    use remote_pty_common::{
+        channel::{mock::MockChannel, Channel},
+        proto::{
+            slave::{PtySlaveCall, PtySlaveCallType, PtySlaveResponse, TcError},
+            Fd,
+        },
+    };
+
+    use crate::intercept::set_session_chan;
+
+    #[test]
+    fn test_set_session() {
+        let expected_req = PtySlaveCall {
+            fd: Fd(0),
+            typ: PtySlaveCallType::SetSession,
+        };
+        let mock_res = PtySlaveResponse::Success(0);
+        let mock = MockChannel::assert_sends(Channel::PTY, vec![expected_req], vec![mock_res]);
+
+        let res = set_session_chan(mock.chan.clone(), 1234);
+
+        assert_eq!(res, 1234);
+    }
+
+    #[test]
+    fn test_set_session_error() {
+        let expected_req = PtySlaveCall {
+            fd: Fd(0),
+            typ: PtySlaveCallType::SetSession,
+        };
+        let mock_res = PtySlaveResponse::Error(TcError::EIO);
+        let mock = MockChannel::assert_sends(Channel::PTY, vec![expected_req], vec![mock_res]);
+
+        // the local session was created regardless
+        let res = set_session_chan(mock.chan.clone(), 1234);
+
+        assert_eq!(res, 1234);
+    }
+}
@@ -1,6 +1,19 @@
-use remote_pty_common::{channel::RemoteChannel, log::debug};
+use std::cmp::min;
 
-use crate::common::handle_intercept;
+use errno::{set_errno, Errno};
+use remote_pty_common::{
+    channel::{Channel, RemoteChannel},
+    log::debug,
+    proto::{
+        slave::{PtySlaveCall, PtySlaveCallType, PtySlaveResponse},
+        Fd,
+    },
+};
+
+use crate::{
+    common::handle_intercept,
+    error::{generic_error, tc_error},
+};
 
 // @see https://pubs.opengroup.org/onlinepubs/007904975/functions/tcgetsid.html
 #[no_mangle]
@@ -34,30 +47,72 @@ unsafe fn __libc__tcgetsid(fd: libc::c_int) -> libc::c_int {
     tcgetsid(fd)
 }
 
-pub(crate) fn tcgetsid_chan(_chan: RemoteChannel, _fd: libc::c_int) -> libc::c_int {
-    debug("tcgetsid not implemented");
-    -1
+pub(crate) fn tcgetsid_chan(mut chan: RemoteChannel, fd: libc::c_int) -> libc::pid_t {
+    // older masters do not track the session of the terminal
+    if !chan.capabilities().supports_get_sid() {
+        debug("tcgetsid not supported by master");
+        set_errno(Errno(libc::ENOTTY));
+        return -1;
+    }
+
+    // send tcgetsid request to remote
+    let req = PtySlaveCall {
+        fd: Fd(fd),
+        typ: PtySlaveCallType::GetSid,
+    };
+
+    let res = match chan.send(Channel::PTY, req) {
+        Ok(res) => res,
+        Err(msg) => return generic_error("tcgetsid", msg),
+    };
+
+    let res = match res {
+        PtySlaveResponse::GetSid(res) => res,
+        PtySlaveResponse::Error(err) => return tc_error("tcgetsid", err),
+        _ => return generic_error("tcgetsid", "unexpected response"),
+    };
+
+    (min(res.sid, libc::pid_t::MAX)) as _
 }
 
 #[cfg(test)]
 mod tests {
     use remote_pty_common::{
         channel::{mock::MockChannel, Channel},
-        proto::slave::{PtySlaveCall, PtySlaveResponse},
+        proto::{
+            slave::{PtySlaveCall, PtySlaveCallType, PtySlaveResponse, SidResponse, TcError},
+            Fd,
+        },
     };
 
     use crate::intercept::tcgetsid_chan;
 
     #[test]
-    fn test_tcgetattr() {
-        let mock = MockChannel::assert_sends::<PtySlaveCall, PtySlaveResponse>(
-            Channel::PTY,
-            vec![],
-            vec![],
-        );
+    fn test_tcgetsid() {
+        let expected_req = PtySlaveCall {
+            fd: Fd(1),
+            typ: PtySlaveCallType::GetSid,
+        };
+        let mock_res = PtySlaveResponse::GetSid(SidResponse { sid: 1234 });
+        let mock = MockChannel::assert_sends(Channel::PTY, vec![expected_req], vec![mock_res]);
+
+        let res = tcgetsid_chan(mock.chan.clone(), 1);
+
+        assert_eq!(res, 1234);
+    }
+
+    #[test]
+    fn test_tcgetsid_no_session() {
+        let expected_req = PtySlaveCall {
+            fd: Fd(1),
+            typ: PtySlaveCallType::GetSid,
+        };
+        let mock_res = PtySlaveResponse::Error(TcError::ENOTTY);
+        let mock = MockChannel::assert_sends(Channel::PTY, vec![expected_req], vec![mock_res]);
 
         let res = tcgetsid_chan(mock.chan.clone(), 1);
 
         assert_eq!(res, -1);
+        assert_eq!(errno::errno().0, libc::ENOTTY);
     }
 }
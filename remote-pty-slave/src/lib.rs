@@ -9,4 +9,7 @@ pub mod fork;
 pub mod stdin;
 pub mod stdout;
 pub mod signal;
-pub mod pgrp;
\ No newline at end of file
+pub mod pgrp;
+pub mod session;
+pub mod reverse;
+pub mod broker;
@@ -2,32 +2,54 @@ use remote_pty_common::{
     channel::{Channel, RemoteChannel},
     log::debug,
     proto::{
-        slave::{PtySlaveCall, PtySlaveCallType, PtySlaveResponse, RegisterProcessCall},
+        slave::{
+            PtySlaveCall, PtySlaveCallType, PtySlaveResponse, RegisterProcessCall,
+            RegisterSessionProcessCall, SessionResponse,
+        },
         Fd,
     },
 };
 
 // here we register the process with the remote master
-pub(crate) fn register_process(chan: &mut RemoteChannel) -> Result<(), String> {
+// the master returns a session if it supports resuming the process
+pub(crate) fn register_process(
+    chan: &mut RemoteChannel,
+) -> Result<Option<SessionResponse>, String> {
     debug("pgrp init");
 
-    let (pid, pgrp) = unsafe { (libc::getpid(), libc::getpgrp()) };
+    let (pid, pgrp, sid) = unsafe { (libc::getpid(), libc::getpgrp(), libc::getsid(0)) };
+
+    // the session is reported so processes which have left
+    // the terminal's session are not treated as part of it
+    let typ = if chan.capabilities().supports_ctty() {
+        PtySlaveCallType::RegisterSessionProcess(RegisterSessionProcessCall {
+            pid: pid as _,
+            pgrp: pgrp as _,
+            sid: sid as _,
+        })
+    } else {
+        PtySlaveCallType::RegisterProcess(RegisterProcessCall {
+            pid: pid as _,
+            pgrp: pgrp as _,
+        })
+    };
 
     let res = chan.send::<PtySlaveCall, PtySlaveResponse>(
         Channel::PGRP,
         PtySlaveCall {
             fd: Fd(0), // unused
-            typ: PtySlaveCallType::RegisterProcess(RegisterProcessCall {
-                pid: pid as _,
-                pgrp: pgrp as _,
-            }),
+            typ,
         },
     );
 
     let res = match res {
         Ok(PtySlaveResponse::Success(_)) => {
             debug("pgrp sent");
-            Ok(())
+            Ok(None)
+        }
+        Ok(PtySlaveResponse::Session(session)) => {
+            debug("pgrp sent, received session");
+            Ok(Some(session))
         }
         Ok(res) => {
             Err(format!(
@@ -0,0 +1,147 @@
+use std::{
+    env, fs,
+    io::{self, ErrorKind, Write},
+    mem,
+    net::TcpListener,
+    os::unix::{
+        net::UnixListener,
+        prelude::{FromRawFd, IntoRawFd, OwnedFd, RawFd},
+    },
+    sync::Mutex,
+};
+
+use remote_pty_common::{
+    channel::transport::{
+        conf::TransportType, reverse::notify_ready, unix_socket, vsock::VsockListener,
+    },
+    log::debug,
+};
+
+use crate::{channel::ensure_not_stdio_fd, conf::Conf};
+
+// the listener is bound by the first process of the session and
+// inherited by the processes it executes through this env var
+pub(crate) const LISTENER_FD_ENV: &str = "RPTY_LISTENER_FD";
+
+// the listener shared by all processes of the session,
+// forked processes inherit it along with the fd
+static LISTENER_FD: Mutex<Option<RawFd>> = Mutex::new(None);
+
+// waits for the master to connect to the session's listener, used when
+// only the slave host can accept inbound connections. each process
+// accepts its own connection from the master
+pub(crate) fn accept<T: FromRawFd + Write>(conf: &Conf) -> io::Result<T> {
+    let listener = listener(conf)?;
+    debug("waiting for the master to connect");
+
+    let fd = loop {
+        let fd = unsafe {
+            libc::accept4(
+                listener,
+                std::ptr::null_mut(),
+                std::ptr::null_mut(),
+                libc::SOCK_CLOEXEC,
+            )
+        };
+
+        match fd {
+            -1 if io::Error::last_os_error().kind() == ErrorKind::Interrupted => continue,
+            -1 => return Err(io::Error::last_os_error()),
+            fd => break fd,
+        }
+    };
+
+    let mut stream = unsafe { T::from_raw_fd(fd) };
+    notify_ready(&mut stream)?;
+
+    Ok(stream)
+}
+
+fn listener(conf: &Conf) -> io::Result<RawFd> {
+    let mut listener = LISTENER_FD.lock().unwrap();
+
+    if let Some(fd) = *listener {
+        return Ok(fd);
+    }
+
+    let fd = match env::var(LISTENER_FD_ENV) {
+        Ok(fd) => inherited_listener(&fd)?,
+        Err(_) => {
+            let fd = bind(conf)?;
+            // the env is read by processes executed after the constructor
+            env::set_var(LISTENER_FD_ENV, fd.to_string());
+            fd
+        }
+    };
+
+    let _ = listener.insert(fd);
+    Ok(fd)
+}
+
+fn inherited_listener(fd: &str) -> io::Result<RawFd> {
+    let fd = fd.parse::<RawFd>().map_err(|_| {
+        io::Error::new(
+            ErrorKind::InvalidInput,
+            format!("failed to parse fd in {}", LISTENER_FD_ENV),
+        )
+    })?;
+
+    let mut accepting: libc::c_int = 0;
+    let mut len = mem::size_of::<libc::c_int>() as libc::socklen_t;
+    let res = unsafe {
+        libc::getsockopt(
+            fd,
+            libc::SOL_SOCKET,
+            libc::SO_ACCEPTCONN,
+            &mut accepting as *mut libc::c_int as *mut libc::c_void,
+            &mut len,
+        )
+    };
+
+    // the fd may have been closed by a process which did not preserve it
+    if res != 0 || accepting == 0 {
+        return Err(io::Error::new(
+            ErrorKind::NotConnected,
+            format!("inherited listener fd {} is not listening", fd),
+        ));
+    }
+
+    Ok(fd)
+}
+
+// binds the listener to an fd which is inherited by executed processes
+fn bind(conf: &Conf) -> io::Result<RawFd> {
+    let transport = &conf.transport;
+
+    let fd: OwnedFd = match &transport.typ {
+        TransportType::Unix(path) => {
+            // replace the socket left by a previous session
+            if !path.starts_with('@') {
+                let _ = fs::remove_file(path);
+            }
+
+            UnixListener::bind_addr(&unix_socket::socket_addr(path)?)?.into()
+        }
+        TransportType::Tcp(addr)
+        | TransportType::Tls(addr)
+        | TransportType::Ws(addr, _)
+        | TransportType::Wss(addr, _) => TcpListener::bind(addr)?.into(),
+        TransportType::Vsock(cid, port) => unsafe {
+            OwnedFd::from_raw_fd(
+                VsockListener::bind(*cid, *port, transport.options.backlog)?.into_raw_fd(),
+            )
+        },
+        TransportType::Cmd(_) | TransportType::Fd(_) | TransportType::Relay(..) => {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                "the transport can only be used to connect to the master",
+            ))
+        }
+    };
+
+    debug(format!("listening for the master on {:?}", transport.typ));
+
+    // relocated without close-on-exec so it is inherited
+    let fd = ensure_not_stdio_fd(conf, fd).map_err(io::Error::other)?;
+    Ok(fd.into_raw_fd())
+}
@@ -0,0 +1,175 @@
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use lazy_static::lazy_static;
+use remote_pty_common::{
+    channel::{Channel, RemoteChannel},
+    log::debug,
+    proto::{
+        slave::{
+            PtySlaveCall, PtySlaveCallType, PtySlaveResponse, ResumeSessionCall, SessionResponse,
+        },
+        Fd, SessionToken,
+    },
+};
+
+use crate::{channel::connect, conf::get_conf};
+
+// the process's session with the master, used to resume
+// the session after the transport is lost
+pub(crate) struct Session {
+    token: SessionToken,
+    // how long the master retains the session after a disconnect
+    resume_timeout: Duration,
+    // total bytes of stdin received from the master
+    stdin_received: AtomicU64,
+    // total bytes of stdout received by the master, as of the last resume
+    stdout_received: AtomicU64,
+    // number of times the session has been resumed
+    resumes: AtomicU64,
+}
+
+lazy_static! {
+    static ref GLOBAL_SESSION: Mutex<Option<Arc<Session>>> = Mutex::new(Option::None);
+}
+
+// stores the session returned from registering the process and
+// handles the channel disconnecting
+pub(crate) fn init_session(chan: &RemoteChannel, res: Option<SessionResponse>) {
+    let session = res.map(|res| {
+        Arc::new(Session {
+            token: res.token,
+            resume_timeout: Duration::from_millis(res.resume_timeout_ms),
+            stdin_received: AtomicU64::new(0),
+            stdout_received: AtomicU64::new(res.stdout_received),
+            resumes: AtomicU64::new(0),
+        })
+    });
+
+    *GLOBAL_SESSION.lock().unwrap() = session.clone();
+
+    register_disconnect_hook(chan, session);
+}
+
+fn register_disconnect_hook(chan: &RemoteChannel, session: Option<Arc<Session>>) {
+    let hook_chan = chan.clone();
+
+    chan.on_disconnect(move |reason| {
+        debug(format!("remote channel disconnected: {}", reason));
+
+        let session = match session {
+            Some(s) => s,
+            None => return hangup(),
+        };
+
+        // resume on another thread as the hook is called
+        // while receiving on the channel
+        thread::spawn(move || {
+            if let Err(err) = resume(&hook_chan, &session) {
+                debug(format!("failed to resume session: {}", err));
+                hangup();
+            }
+        });
+    });
+}
+
+// reconnects to the master and reattaches the channel to the session,
+// retrying until the master would have discarded the session
+fn resume(chan: &RemoteChannel, session: &Arc<Session>) -> Result<(), String> {
+    let deadline = Instant::now() + session.resume_timeout;
+    let mut backoff = Duration::from_millis(100);
+
+    loop {
+        match try_resume(chan, session) {
+            Ok(_) => break,
+            Err(err) if Instant::now() + backoff < deadline => {
+                debug(format!("failed to resume session, retrying: {}", err));
+                thread::sleep(backoff);
+                backoff = (backoff * 2).min(Duration::from_secs(2));
+            }
+            Err(err) => return Err(err),
+        }
+    }
+
+    register_disconnect_hook(chan, Some(Arc::clone(session)));
+    Ok(())
+}
+
+fn try_resume(chan: &RemoteChannel, session: &Session) -> Result<(), String> {
+    let conf = get_conf()?;
+    let mut new_chan = connect(&conf)?;
+    let pid = unsafe { libc::getpid() };
+
+    let res = new_chan.send::<PtySlaveCall, PtySlaveResponse>(
+        Channel::PGRP,
+        PtySlaveCall {
+            fd: Fd(0), // unused
+            typ: PtySlaveCallType::ResumeSession(ResumeSessionCall {
+                pid: pid as _,
+                token: session.token,
+                stdin_received: session.stdin_received.load(Ordering::SeqCst),
+            }),
+        },
+    )?;
+
+    let res = match res {
+        PtySlaveResponse::Session(res) => res,
+        res => return Err(format!("unexpected response {:?}", res)),
+    };
+
+    // must be updated before the channel is resumed as waiting threads
+    // use them to determine what needs to be resent
+    session
+        .stdout_received
+        .store(res.stdout_received, Ordering::SeqCst);
+    session.resumes.fetch_add(1, Ordering::SeqCst);
+
+    chan.resume_with(new_chan)
+}
+
+// mimic a terminal hangup when we lose the connection to the master
+fn hangup() {
+    unsafe {
+        libc::kill(libc::getpid(), libc::SIGHUP);
+    }
+}
+
+fn get_session() -> Option<Arc<Session>> {
+    GLOBAL_SESSION.lock().unwrap().clone()
+}
+
+// blocks until the channel is resumed, returns false if the
+// session could not be resumed
+pub(crate) fn wait_for_resume(chan: &RemoteChannel) -> bool {
+    match get_session() {
+        Some(session) => chan.wait_resumed(session.resume_timeout),
+        None => false,
+    }
+}
+
+pub(crate) fn record_stdin_received(len: usize) {
+    if let Some(session) = get_session() {
+        session.stdin_received.fetch_add(len as _, Ordering::SeqCst);
+    }
+}
+
+// the total bytes of stdout received by the master, as of the last resume
+pub(crate) fn stdout_received() -> u64 {
+    get_session()
+        .map(|s| s.stdout_received.load(Ordering::SeqCst))
+        .unwrap_or(0)
+}
+
+// the number of times the session has been resumed, used to detect
+// when streams need to resend data lost with the previous connection
+pub(crate) fn resume_count() -> u64 {
+    get_session()
+        .map(|s| s.resumes.load(Ordering::SeqCst))
+        .unwrap_or(0)
+}
@@ -6,6 +6,8 @@ use remote_pty_common::{
     proto::master::{IoError, PtyMasterCall, PtyMasterResponse, PtyMasterSignal},
 };
 
+use crate::session::wait_for_resume;
+
 // we forward signals from the remote master to the local process
 pub(crate) fn init_signal_handler(mut chan: RemoteChannel) {
     debug("signal handler");
@@ -13,40 +15,47 @@ pub(crate) fn init_signal_handler(mut chan: RemoteChannel) {
     thread::spawn(move || {
         let _ = block_signals_on_thread();
         loop {
-            chan
-                .receive::<PtyMasterCall, PtyMasterResponse, _>(Channel::SIGNAL, |req| {
-                    let req = match req {
-                        PtyMasterCall::Signal(sig) => sig,
-                        _ => {
-                            debug(format!("unexpected request: {:?}", req));
-                            return PtyMasterResponse::Error(IoError::EIO);
-                        }
-                    };
-
-                    debug(format!("received signal from master: {:?}", req));
-
-                    let signal = match req.signal {
-                        PtyMasterSignal::SIGWINCH => libc::SIGWINCH,
-                        PtyMasterSignal::SIGINT => libc::SIGINT,
-                        PtyMasterSignal::SIGTERM => libc::SIGTERM,
-                        PtyMasterSignal::SIGCONT => libc::SIGCONT,
-                        PtyMasterSignal::SIGTTOU => libc::SIGTTOU,
-                        PtyMasterSignal::SIGTTIN => libc::SIGTTIN,
-                    };
-
-                    let ret = unsafe { libc::kill(req.pgrp as _, signal) };
-
-                    if ret == -1 {
-                        debug(format!(
-                            "failed to send signal to local process: {}",
-                            errno::errno()
-                        ));
+            let res = chan.receive::<PtyMasterCall, PtyMasterResponse, _>(Channel::SIGNAL, |req| {
+                let req = match req {
+                    PtyMasterCall::Signal(sig) => sig,
+                    _ => {
+                        debug(format!("unexpected request: {:?}", req));
                         return PtyMasterResponse::Error(IoError::EIO);
                     }
+                };
+
+                debug(format!("received signal from master: {:?}", req));
+
+                let signal = match req.signal {
+                    PtyMasterSignal::SIGWINCH => libc::SIGWINCH,
+                    PtyMasterSignal::SIGINT => libc::SIGINT,
+                    PtyMasterSignal::SIGTERM => libc::SIGTERM,
+                    PtyMasterSignal::SIGCONT => libc::SIGCONT,
+                    PtyMasterSignal::SIGTTOU => libc::SIGTTOU,
+                    PtyMasterSignal::SIGTTIN => libc::SIGTTIN,
+                    PtyMasterSignal::SIGHUP => libc::SIGHUP,
+                };
+
+                let ret = unsafe { libc::kill(req.pgrp as _, signal) };
+
+                if ret == -1 {
+                    debug(format!(
+                        "failed to send signal to local process: {}",
+                        errno::errno()
+                    ));
+                    return PtyMasterResponse::Error(IoError::EIO);
+                }
+
+                PtyMasterResponse::Success(0)
+            });
+
+            if let Err(err) = res {
+                debug(format!("failed to receive signal: {}", err));
 
-                    PtyMasterResponse::Success(0)
-                })
-                .unwrap();
+                if !wait_for_resume(&chan) {
+                    return;
+                }
+            }
         }
     });
 
@@ -1,14 +1,25 @@
-use std::{fs::File, io::Write, os::unix::prelude::FromRawFd, thread};
+use std::{
+    fs::File,
+    io::{self, Write},
+    os::unix::prelude::FromRawFd,
+    sync::atomic::{AtomicBool, Ordering},
+    thread,
+};
 
 use remote_pty_common::{
-    channel::{Channel, RemoteChannel},
+    channel::{stream::ReceiveWindow, Channel, RemoteChannel},
     log::debug,
-    proto::master::{IoError, PtyMasterCall, PtyMasterResponse},
+    proto::{
+        master::{PacketStatusCall, PtyMasterCall},
+        slave::{PtySlaveCall, PtySlaveCallType},
+        Fd, Message, StreamCredit,
+    },
 };
 
 use crate::{
     conf::{Conf, State},
     fd::{get_inode_from_fd, get_open_fds_by_inode},
+    session::{record_stdin_received, wait_for_resume},
     signal::block_signals_on_thread,
 };
 
@@ -19,6 +30,17 @@ extern "C" {
     static mut LIBC_STDIN: *mut libc::FILE;
 }
 
+// whether the process enabled packet mode, in which case each write to
+// stdin is prefixed with a status byte like reads from a pty master
+static PACKET_MODE: AtomicBool = AtomicBool::new(false);
+
+// the max data in each packet, writes up to PIPE_BUF are a single packet
+const PACKET_DATA_SIZE: usize = libc::PIPE_BUF - 1;
+
+pub(crate) fn set_packet_mode(packet: bool) {
+    PACKET_MODE.store(packet, Ordering::Relaxed);
+}
+
 // this replaces the stdin fd with a fd which is driven by the remote master
 pub(crate) fn init_stdin(conf: &Conf, mut chan: RemoteChannel, pre_fork_state: Option<&State>) {
     debug("redirecting stdin");
@@ -70,32 +92,173 @@ pub(crate) fn init_stdin(conf: &Conf, mut chan: RemoteChannel, pre_fork_state: O
         let _ = state.stdin_inode.insert(inode);
     });
 
+    let mut window = ReceiveWindow::new(conf.stream_window);
+    let mut packetized = false;
+
     // stream remote master data to stdin
     thread::spawn(move || {
         let _ = block_signals_on_thread();
 
+        // advertise our window in case it differs from the default
+        if let Err(err) = send_credit(&mut chan, window.credit()) {
+            debug(format!("failed to send stdin credit: {}", err));
+        }
+
         loop {
-            chan.receive::<PtyMasterCall, PtyMasterResponse, _>(Channel::STDIN, |req| {
-                let write = match req {
-                    PtyMasterCall::WriteStdin(write) => write,
-                    _ => return PtyMasterResponse::Error(IoError::EIO),
-                };
-
-                if let Err(err) = stdin.write_all(write.data.as_slice()) {
-                    debug(format!("failed to write to stdin: {}", err));
-                    return PtyMasterResponse::Error(IoError::EIO);
+            let res = chan.receive_request::<PtyMasterCall>(Channel::STDIN);
+
+            let write = match res {
+                Ok(Message {
+                    payload: PtyMasterCall::WriteStdin(write),
+                    ..
+                }) => write,
+                Ok(Message {
+                    payload: PtyMasterCall::PacketStatus(packet),
+                    ..
+                }) => {
+                    if packet_mode(&stdin, &mut packetized) {
+                        if let Err(err) = stdin.write_all(&[packet.status]) {
+                            debug(format!("failed to write packet status: {}", err));
+                            return;
+                        }
+                    }
+
+                    continue;
+                }
+                Ok(req) => {
+                    debug(format!("unexpected stdin message: {:?}", req.payload));
+                    continue;
                 }
+                Err(err) => {
+                    debug(format!("failed to receive stdin: {}", err));
+
+                    if !wait_for_resume(&chan) {
+                        return;
+                    }
 
-                if let Err(err) = stdin.flush() {
-                    debug(format!("failed to write to flush stdin: {}", err));
-                    return PtyMasterResponse::Error(IoError::EIO);
+                    continue;
                 }
+            };
 
-                PtyMasterResponse::WriteSuccess
-            })
-            .unwrap();
+            // this blocks while the pipe is full, so no further credit is
+            // granted to the master until the process reads its stdin
+            let res = match packet_mode(&stdin, &mut packetized) {
+                true => write.data.chunks(PACKET_DATA_SIZE).try_for_each(|chunk| {
+                    stdin.write_all(&[&[PacketStatusCall::TIOCPKT_DATA], chunk].concat())
+                }),
+                false => stdin.write_all(write.data.as_slice()),
+            };
+
+            if let Err(err) = res {
+                debug(format!("failed to write to stdin: {}", err));
+                return;
+            }
+
+            if let Err(err) = stdin.flush() {
+                debug(format!("failed to write to flush stdin: {}", err));
+                return;
+            }
+
+            record_stdin_received(write.data.len());
+
+            if let Some(credit) = window.consume(write.data.len()) {
+                if let Err(err) = send_credit(&mut chan, credit) {
+                    debug(format!("failed to send stdin credit: {}", err));
+                }
+            }
         }
     });
 
     debug("init stdin");
 }
+
+// packetizes the pipe when the process enables packet mode so each
+// read returns a single status or chunk of data, as from a pty master
+fn packet_mode(pipe: &File, packetized: &mut bool) -> bool {
+    let packet = PACKET_MODE.load(Ordering::Relaxed);
+
+    if packet != *packetized {
+        match set_packetized(pipe, packet) {
+            Ok(_) => *packetized = packet,
+            Err(err) => debug(format!("failed to packetize stdin: {}", err)),
+        }
+    }
+
+    packet
+}
+
+// writes to a pipe opened with O_DIRECT are read as separate packets
+#[cfg(target_os = "linux")]
+fn set_packetized(pipe: &File, packet: bool) -> io::Result<()> {
+    use std::os::unix::prelude::AsRawFd;
+
+    let fd = pipe.as_raw_fd();
+
+    let flags = unsafe { libc::fcntl(fd, libc::F_GETFL) };
+    if flags == -1 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let flags = match packet {
+        true => flags | libc::O_DIRECT,
+        false => flags & !libc::O_DIRECT,
+    };
+
+    if unsafe { libc::fcntl(fd, libc::F_SETFL, flags) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn set_packetized(_pipe: &File, _packet: bool) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "packetized pipes are not supported",
+    ))
+}
+
+// grants the master credit to send more stdin
+fn send_credit(chan: &mut RemoteChannel, credit: StreamCredit) -> Result<(), String> {
+    chan.notify(
+        Channel::STDIN,
+        PtySlaveCall {
+            fd: Fd(0), // not used
+            typ: PtySlaveCallType::StdinCredit(credit),
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs::File,
+        io::{Read, Write},
+        os::unix::prelude::FromRawFd,
+    };
+
+    use super::set_packetized;
+
+    #[test]
+    fn test_packetized_pipe() {
+        let (mut read, mut write) = unsafe {
+            let mut fds = [0 as libc::c_int; 2];
+            assert_eq!(libc::pipe(&mut fds as *mut _), 0);
+            (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1]))
+        };
+        let mut buf = [0u8; 16];
+
+        // each write is read separately
+        set_packetized(&write, true).unwrap();
+        write.write_all(&[0, b'a']).unwrap();
+        write.write_all(&[0, b'b']).unwrap();
+        assert_eq!(read.read(&mut buf).unwrap(), 2);
+        assert_eq!(read.read(&mut buf).unwrap(), 2);
+
+        set_packetized(&write, false).unwrap();
+        write.write_all(b"a").unwrap();
+        write.write_all(b"b").unwrap();
+        assert_eq!(read.read(&mut buf).unwrap(), 2);
+    }
+}
@@ -1,19 +1,23 @@
 use std::{
     fs::File,
-    io::Read,
-    os::unix::prelude::FromRawFd,
+    os::unix::prelude::{AsRawFd, FromRawFd},
     ptr,
-    sync::mpsc::channel,
+    sync::{mpsc::channel, Arc, Condvar, Mutex},
     thread::{self, JoinHandle},
     time::Duration,
 };
 
 use remote_pty_common::{
-    channel::{Channel, RemoteChannel},
+    channel::{
+        stream::{ReplayBuffer, SendWindow},
+        Channel, RemoteChannel,
+    },
+    io::coalesce::{poll_readable, read_coalesced, DEFAULT_COALESCE_WINDOW},
     log::debug,
     proto::{
+        master::PtyMasterCall,
         slave::{PtySlaveCall, PtySlaveCallType, PtySlaveResponse, WriteStdoutCall},
-        Fd,
+        Fd, Message,
     },
 };
 
@@ -21,6 +25,7 @@ use crate::{
     conf::{get_conf, Conf, State},
     fd::{get_inode_from_fd, get_open_fds_by_inode},
     init::is_proc_forked,
+    session::{resume_count, stdout_received, wait_for_resume},
     signal::block_signals_on_thread,
 };
 
@@ -33,6 +38,24 @@ extern "C" {
     static mut LIBC_STDERR: *mut libc::FILE;
 }
 
+// the max size of each stdout message
+const STDOUT_CHUNK_SIZE: usize = 16 * 1024;
+
+// the master's window bounds the unconsumed stdout, this guards
+// against retaining an excessive amount for huge windows
+const MAX_UNCONSUMED_BYTES: usize = 4 * 1024 * 1024;
+
+// flow control state of stdout shared with the thread receiving credit
+struct StdoutStream {
+    window: SendWindow,
+    // stdout sent but not yet consumed by the master, resent after resuming
+    unconsumed: ReplayBuffer,
+    // the session resume count when the stream was last resynced
+    resumes: u64,
+}
+
+type SharedStdoutStream = Arc<(Mutex<StdoutStream>, Condvar)>;
+
 #[cfg(target_os = "linux")]
 static mut STDOUT_STREAM_THREAD: Option<(JoinHandle<()>, libc::c_int)> = Option::None;
 
@@ -95,45 +118,50 @@ pub(crate) fn init_stdout(conf: &Conf, mut chan: RemoteChannel, pre_fork_state:
         let _ = state.stdout_inode.insert(inode);
     });
 
-    // stream remote master data to stdin
+    let stream = Arc::new((
+        Mutex::new(StdoutStream {
+            window: SendWindow::new(),
+            unconsumed: ReplayBuffer::new(MAX_UNCONSUMED_BYTES),
+            resumes: resume_count(),
+        }),
+        Condvar::new(),
+    ));
+    init_credit_listener(chan.clone(), Arc::clone(&stream));
+
+    // stream stdout data to the remote master
     let stream_thread = thread::spawn(move || {
         let _ = block_signals_on_thread();
 
-        let mut buff = [0u8; 4096];
+        let mut buff = [0u8; STDOUT_CHUNK_SIZE];
 
         loop {
-            let n = match stdout.read(&mut buff) {
+            // shells exec the last command straight after writing, which ends
+            // this thread, so writes to an idle pipe are sent without waiting
+            // for the rest of a burst
+            let window = match poll_readable(stdout.as_raw_fd(), Duration::ZERO) {
+                Ok(true) => DEFAULT_COALESCE_WINDOW,
+                _ => Duration::ZERO,
+            };
+
+            let n = match read_coalesced(&mut stdout, &mut buff, window) {
                 Ok(0) => {
                     debug("eof from stdout pipe");
-                    return;
+                    break;
                 }
                 Ok(n) => n,
                 Err(err) => {
                     debug(format!("failed to read from stdout: {}", err));
-                    return;
+                    break;
                 }
             };
 
-            let res = chan
-                .send::<PtySlaveCall, PtySlaveResponse>(
-                    Channel::STDOUT,
-                    PtySlaveCall {
-                        fd: Fd(0), // not used, todo: refactor data structure
-                        typ: PtySlaveCallType::WriteStdout(WriteStdoutCall {
-                            data: buff[..n].to_vec(),
-                        }),
-                    },
-                )
-                .unwrap();
-
-            match res {
-                PtySlaveResponse::Success(_) => continue,
-                res @ _ => {
-                    debug(format!("expected response from master: {:?}", res));
-                    return;
-                }
+            if let Err(err) = send_stdout(&mut chan, &stream, &buff[..n]) {
+                debug(format!("failed to send stdout: {}", err));
+                return;
             }
         }
+
+        flush_stdout(&mut chan);
     });
 
     unsafe {
@@ -157,6 +185,155 @@ pub(crate) fn init_stdout(conf: &Conf, mut chan: RemoteChannel, pre_fork_state:
     debug("init stdout");
 }
 
+// receives credit from the master, waking the stdout thread once
+// more stdout can be sent
+fn init_credit_listener(mut chan: RemoteChannel, stream: SharedStdoutStream) {
+    thread::spawn(move || {
+        let _ = block_signals_on_thread();
+        let (lock, condvar) = &*stream;
+
+        loop {
+            let res = chan.receive_request::<PtyMasterCall>(Channel::STDOUT);
+
+            match res {
+                Ok(Message {
+                    payload: PtyMasterCall::StdoutCredit(credit),
+                    ..
+                }) => {
+                    let mut state = lock.lock().unwrap();
+                    state.window.grant(credit);
+                    state.unconsumed.ack(credit.consumed);
+                    condvar.notify_all();
+                }
+                Ok(req) => debug(format!("unexpected stdout message: {:?}", req.payload)),
+                Err(err) => {
+                    debug(format!("failed to receive stdout credit: {}", err));
+
+                    // wake the stdout thread if it is waiting for credit
+                    {
+                        let _state = lock.lock().unwrap();
+                        condvar.notify_all();
+                    }
+
+                    if !wait_for_resume(&chan) {
+                        return;
+                    }
+                }
+            }
+
+            resync_stdout(&mut chan, &stream);
+        }
+    });
+}
+
+// sends the data to the master, waiting for credit when the window is full
+fn send_stdout(
+    chan: &mut RemoteChannel,
+    stream: &SharedStdoutStream,
+    mut data: &[u8],
+) -> Result<(), String> {
+    let (lock, condvar) = &**stream;
+
+    while !data.is_empty() {
+        let mut state = lock.lock().unwrap();
+
+        while state.window.available() == 0 && !chan.is_closed() {
+            state = condvar.wait(state).unwrap();
+        }
+
+        let len = state.window.available().min(data.len());
+
+        // the data is recorded before it is sent, under the lock, so it is
+        // resent if the connection is lost regardless of which thread resyncs
+        let res = if len > 0 {
+            let (chunk, rest) = data.split_at(len);
+            state.window.record_sent(len);
+            state.unconsumed.push(chunk);
+            data = rest;
+            write_stdout(chan, chunk)
+        } else {
+            Err("channel closed".to_string())
+        };
+        drop(state);
+
+        if let Err(err) = res {
+            debug(format!("failed to send stdout: {}", err));
+
+            if !wait_for_resume(chan) {
+                return Err(err);
+            }
+
+            resync_stdout(chan, stream);
+        }
+    }
+
+    Ok(())
+}
+
+// resends the stdout which the master did not receive before the
+// session was resumed, this is a noop if already resynced
+fn resync_stdout(chan: &mut RemoteChannel, stream: &SharedStdoutStream) {
+    if !wait_for_resume(chan) {
+        return;
+    }
+
+    let (lock, condvar) = &**stream;
+    let mut state = lock.lock().unwrap();
+
+    let resumes = resume_count();
+    if state.resumes == resumes {
+        return;
+    }
+    state.resumes = resumes;
+
+    let received = stdout_received();
+    let data = state.unconsumed.since(received);
+    state.unconsumed.ack(received);
+    state.window.rewind(received);
+
+    debug(format!("resending {} bytes of stdout", data.len()));
+
+    for chunk in data.chunks(STDOUT_CHUNK_SIZE) {
+        state.window.record_sent(chunk.len());
+
+        // a failure here is handled once the session is resumed again
+        if let Err(err) = write_stdout(chan, chunk) {
+            debug(format!("failed to resend stdout: {}", err));
+            break;
+        }
+    }
+
+    condvar.notify_all();
+}
+
+fn write_stdout(chan: &mut RemoteChannel, data: &[u8]) -> Result<(), String> {
+    chan.notify(
+        Channel::STDOUT,
+        PtySlaveCall {
+            fd: Fd(0), // not used, todo: refactor data structure
+            typ: PtySlaveCallType::WriteStdout(WriteStdoutCall {
+                data: data.to_vec(),
+                pgrp: unsafe { libc::getpgrp() } as _,
+            }),
+        },
+    )
+}
+
+// waits for the master to write all of the stdout which has been sent
+fn flush_stdout(chan: &mut RemoteChannel) {
+    let res = chan.send::<PtySlaveCall, PtySlaveResponse>(
+        Channel::STDOUT,
+        PtySlaveCall {
+            fd: Fd(0),
+            typ: PtySlaveCallType::FlushStdout,
+        },
+    );
+
+    if let Err(err) = res {
+        debug(format!("failed to flush stdout: {}", err));
+    }
+}
+
 extern "C" fn wait_for_output() {
     debug("atexit: stdout");
 